@@ -0,0 +1,224 @@
+//! A builder for configuring and validating a [`ShardMap`], [`ShardSet`], [`ExpiringShardMap`],
+//! [`SyncShardMap`] or [`RcuShardMap`] before creating it.
+//!
+//! # Example
+//! ```
+//! use whirlwind::{ShardMap, ShardMapBuilder};
+//!
+//! let map: ShardMap<&str, u32> = ShardMapBuilder::new()
+//!     .shards(16)
+//!     .capacity(1024)
+//!     .build()
+//!     .unwrap();
+//!
+//! assert_eq!(map.shard_count(), 16);
+//! ```
+use std::hash::{BuildHasher, Hash, RandomState};
+
+#[cfg(feature = "tokio")]
+use crate::expiring::{Clock, ExpiringShardMap, TokioClock};
+use crate::{
+    error::BuildError, rcu::RcuShardMap, shard_map::ShardMap, shard_set::ShardSet,
+    sync::SyncShardMap,
+};
+
+/// A builder for a [`ShardMap`], [`ShardSet`], [`ExpiringShardMap`], [`SyncShardMap`] or
+/// [`RcuShardMap`].
+///
+/// Unlike the `with_*` constructors, which panic on invalid input, [`ShardMapBuilder::build`]
+/// validates the configuration and returns a [`BuildError`] describing what is wrong with it.
+///
+/// # Example
+/// ```
+/// use whirlwind::{error::BuildError, ShardMap, ShardMapBuilder};
+///
+/// let err = ShardMapBuilder::new().shards(3).build::<u32, u32>().err();
+/// assert_eq!(err, Some(BuildError::ShardsNotPowerOfTwo(3)));
+///
+/// let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+///     .shards(3)
+///     .round_up_shards(true)
+///     .build()
+///     .unwrap();
+/// assert_eq!(map.shard_count(), 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShardMapBuilder<S = RandomState> {
+    pub(crate) shards: Option<usize>,
+    pub(crate) capacity: usize,
+    pub(crate) hasher: S,
+    pub(crate) round_up_shards: bool,
+    pub(crate) incremental_resize: bool,
+}
+
+impl Default for ShardMapBuilder<RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShardMapBuilder<RandomState> {
+    /// Creates a new builder with the default hasher and shard count.
+    pub fn new() -> Self {
+        Self {
+            shards: None,
+            capacity: 0,
+            hasher: RandomState::new(),
+            round_up_shards: false,
+            incremental_resize: false,
+        }
+    }
+}
+
+impl<S> ShardMapBuilder<S> {
+    /// Sets the number of shards. This must be a power of two greater than one, unless
+    /// [`ShardMapBuilder::round_up_shards`] is enabled.
+    ///
+    /// Defaults to four times the available parallelism, rounded up to a power of two.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    /// Sets the number of elements the map can hold across all shards without reallocating.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the hasher used to hash keys, which also determines which shard a key belongs to.
+    pub fn hasher<H>(self, hasher: H) -> ShardMapBuilder<H> {
+        ShardMapBuilder {
+            shards: self.shards,
+            capacity: self.capacity,
+            hasher,
+            round_up_shards: self.round_up_shards,
+            incremental_resize: self.incremental_resize,
+        }
+    }
+
+    /// If enabled, a shard count that is not a power of two is rounded up to the next power of
+    /// two instead of being rejected.
+    pub fn round_up_shards(mut self, round_up: bool) -> Self {
+        self.round_up_shards = round_up;
+        self
+    }
+
+    /// If enabled, a shard that runs out of room moves its entries to a larger table a few at a
+    /// time, on the insertions that follow, instead of all at once on the insertion that fills
+    /// it. This bounds the time an insertion can take, and how long it holds the shard's lock, at
+    /// the cost of slightly slower lookups while a move is in progress and of keeping both tables
+    /// allocated until it is done.
+    ///
+    /// Applies to [`ShardMap`] and the maps built on it, and to [`SyncShardMap`]. [`RcuShardMap`]
+    /// copies a shard's table on every write anyway, and ignores it.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::{ShardMap, ShardMapBuilder};
+    /// use tokio::runtime::Runtime;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+    ///     .incremental_resize(true)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// rt.block_on(async {
+    ///     map.extend((0..10_000).map(|i| (i, i))).await;
+    ///     assert_eq!(map.len().await, 10_000);
+    ///     assert_eq!(map.get(&1234).await.unwrap().value(), &1234);
+    /// });
+    /// ```
+    pub fn incremental_resize(mut self, incremental: bool) -> Self {
+        self.incremental_resize = incremental;
+        self
+    }
+
+    /// Validates the configuration, returning the number of shards to create.
+    pub(crate) fn validate_shards(&self) -> Result<usize, BuildError> {
+        let shards = self.shards.unwrap_or_else(crate::shard_map::shard_count);
+
+        if shards < 2 {
+            return Err(BuildError::TooFewShards(shards));
+        }
+
+        let shards = match shards.is_power_of_two() {
+            true => shards,
+            false if self.round_up_shards => shards
+                .checked_next_power_of_two()
+                .ok_or(BuildError::TooManyShards(shards))?,
+            false => return Err(BuildError::ShardsNotPowerOfTwo(shards)),
+        };
+
+        if shards > crate::shard_map::MAX_SHARDS {
+            return Err(BuildError::TooManyShards(shards));
+        }
+
+        Ok(shards)
+    }
+
+    /// Creates a [`ShardMap`] with this configuration.
+    pub fn build<K, V>(self) -> Result<ShardMap<K, V, S>, BuildError>
+    where
+        K: Eq + Hash + 'static,
+        V: 'static,
+        S: BuildHasher,
+    {
+        ShardMap::from_builder(self)
+    }
+
+    /// Creates a [`ShardSet`] with this configuration.
+    pub fn build_set<T>(self) -> Result<ShardSet<T, S>, BuildError>
+    where
+        T: Eq + Hash + 'static,
+        S: BuildHasher,
+    {
+        self.build().map(ShardSet::from_map)
+    }
+
+    /// Creates a [`SyncShardMap`] with this configuration.
+    pub fn build_sync<K, V>(self) -> Result<SyncShardMap<K, V, S>, BuildError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        SyncShardMap::from_builder(self)
+    }
+
+    /// Creates a [`RcuShardMap`] with this configuration.
+    pub fn build_rcu<K, V>(self) -> Result<RcuShardMap<K, V, S>, BuildError>
+    where
+        K: Eq + Hash,
+        S: BuildHasher,
+    {
+        RcuShardMap::from_builder(self)
+    }
+
+    /// Creates an [`ExpiringShardMap`] with this configuration, which follows tokio's clock.
+    #[cfg(feature = "tokio")]
+    pub fn build_expiring<K, V>(self) -> Result<ExpiringShardMap<K, V, S>, BuildError>
+    where
+        K: Eq + Hash + 'static,
+        V: 'static,
+        S: BuildHasher,
+    {
+        self.build_expiring_with_clock(TokioClock)
+    }
+
+    /// Creates an [`ExpiringShardMap`] with this configuration, which reads the time from
+    /// `clock`.
+    #[cfg(feature = "tokio")]
+    pub fn build_expiring_with_clock<K, V, C>(
+        self,
+        clock: C,
+    ) -> Result<ExpiringShardMap<K, V, S, C>, BuildError>
+    where
+        K: Eq + Hash + 'static,
+        V: 'static,
+        S: BuildHasher,
+        C: Clock,
+    {
+        ExpiringShardMap::from_builder(self, clock)
+    }
+}
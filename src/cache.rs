@@ -0,0 +1,545 @@
+//! This module contains [`CacheShardMap`], a [`ShardMap`] with a maximum size that evicts entries
+//! once it is full.
+//!
+//! The maximum size is split evenly across the shards, and each shard evicts on its own when it
+//! goes over its share, so eviction never needs more than the one shard lock that the insert
+//! already holds.
+//!
+//! # Example
+//! ```
+//! use whirlwind::{cache::EvictionPolicy, CacheShardMap};
+//! use tokio::runtime::Runtime;
+//!
+//! let rt = Runtime::new().unwrap();
+//! let cache = CacheShardMap::builder(1_000)
+//!     .policy(EvictionPolicy::TinyLfu)
+//!     .weigher(|_key: &u32, value: &String| value.len() as u64)
+//!     .build()
+//!     .unwrap();
+//!
+//! rt.block_on(async {
+//!     cache.insert(1, "hello".to_string()).await;
+//!     assert_eq!(cache.get(&1).await.unwrap().value(), "hello");
+//!     assert_eq!(cache.weighted_size().await, 5);
+//! });
+//! ```
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crossbeam_utils::CachePadded;
+use hashbrown::Equivalent;
+
+use crate::{
+    error::BuildError,
+    mapref::MapRef,
+    policy::{CacheEntry, ShardPolicy},
+    table::Table,
+    ShardMap, ShardMapBuilder,
+};
+
+type Weigher<K, V> = Box<dyn Fn(&K, &V) -> u64 + Send + Sync>;
+type Listener<K, V> = Box<dyn Fn(K, V, RemovalCause) + Send + Sync>;
+type Evicted<K, V> = Vec<(K, V, RemovalCause)>;
+
+/// How a [`CacheShardMap`] picks which entries to evict when a shard is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evicts the least recently used entry.
+    #[default]
+    Lru,
+    /// W-TinyLFU: new entries go through a small LRU window, and are only admitted to the rest of
+    /// the cache if they have been accessed more often than the entry they would replace. This
+    /// keeps one-off keys from flushing out popular ones.
+    TinyLfu,
+}
+
+/// The reason an entry was passed to the eviction listener of a [`CacheShardMap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    /// The entry was evicted to make room for other entries, or was too heavy to fit at all.
+    Size,
+    /// The entry was new, and the admission policy kept it out in favor of a more popular one.
+    Rejected,
+    /// The entry was removed by [`CacheShardMap::clear`].
+    Explicit,
+}
+
+struct Shared<K, V, S> {
+    map: ShardMap<K, CacheEntry<V>, S>,
+    policies: Box<[CachePadded<Mutex<ShardPolicy>>]>,
+    weigher: Option<Weigher<K, V>>,
+    listener: Option<Listener<K, V>>,
+}
+
+/// A concurrent hashmap with a maximum size, which evicts entries once it is full.
+///
+/// Every entry has a weight, which is 1 unless a weigher is provided to
+/// [`CacheBuilder::weigher`]. The maximum weight is split as evenly as possible across the shards,
+/// so that the shares add up to exactly the maximum, and inserting into a shard that goes over its
+/// share evicts entries from that shard according to the [`EvictionPolicy`].
+///
+/// Entries the cache drops on its own, or that are dropped by [`CacheShardMap::clear`], are handed
+/// to the listener set with [`CacheBuilder::eviction_listener`] after the shard lock is released.
+/// Values returned from [`CacheShardMap::insert`] and [`CacheShardMap::remove`] are handed back
+/// to the caller instead.
+///
+/// # Example
+/// ```
+/// use whirlwind::CacheShardMap;
+/// use tokio::runtime::Runtime;
+///
+/// let rt = Runtime::new().unwrap();
+/// let cache = CacheShardMap::builder(2).shards(2).build().unwrap();
+///
+/// rt.block_on(async {
+///     for i in 0..100 {
+///         cache.insert(i, i).await;
+///     }
+///     // Each of the two shards holds at most one entry.
+///     assert!(cache.len().await <= 2);
+/// });
+/// ```
+pub struct CacheShardMap<K, V, S = RandomState> {
+    shared: Arc<Shared<K, V, S>>,
+}
+
+impl<K, V, S> Clone for CacheShardMap<K, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<K, V> CacheShardMap<K, V, RandomState>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+{
+    /// Creates a new LRU `CacheShardMap` that holds at most `max_capacity` entries.
+    pub fn new(max_capacity: u64) -> Self {
+        Self::builder(max_capacity)
+            .build()
+            .unwrap_or_else(|err| panic!("invalid `CacheShardMap` configuration: {err}"))
+    }
+
+    /// Returns a builder for a `CacheShardMap` with a maximum total weight of `max_capacity`.
+    pub fn builder(max_capacity: u64) -> CacheBuilder<K, V> {
+        CacheBuilder {
+            map: ShardMapBuilder::new(),
+            max_capacity,
+            policy: EvictionPolicy::default(),
+            weigher: None,
+            listener: None,
+        }
+    }
+}
+
+impl<K, V, S> CacheShardMap<K, V, S>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    S: BuildHasher,
+{
+    fn policy(&self, idx: usize) -> MutexGuard<'_, ShardPolicy> {
+        self.shared.policies[idx]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn weigh(&self, key: &K, value: &V) -> u64 {
+        self.shared
+            .weigher
+            .as_ref()
+            .map_or(1, |weigher| weigher(key, value))
+    }
+
+    fn notify(&self, evicted: Evicted<K, V>) {
+        if let Some(listener) = &self.shared.listener {
+            for (key, value, cause) in evicted {
+                listener(key, value, cause);
+            }
+        }
+    }
+
+    /// Inserts a key-value pair into the cache, evicting other entries from its shard if the shard
+    /// goes over capacity. If the key already exists, the value is updated and the old value is
+    /// returned.
+    ///
+    /// An entry that is heavier than a whole shard is never stored. It is handed to the eviction
+    /// listener right away with [`RemovalCause::Size`].
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        let (idx, hash) = self.shared.map.locate(&key);
+        let weight = self.weigh(&key, &value);
+        let mut evicted = Vec::new();
+
+        let old = {
+            let mut table = self.shared.map.shard_at(idx).write().await;
+            let mut policy = self.policy(idx);
+            policy.record(hash);
+
+            let existing = table.find_entry(hash, |(k, _)| k == &key);
+            if weight > policy.capacity {
+                evicted.push((key, value, RemovalCause::Size));
+                existing.ok().map(|occupied| {
+                    let ((_, entry), _) = occupied.remove();
+                    policy.remove(&entry);
+                    entry.value
+                })
+            } else {
+                let old = match existing {
+                    Ok(mut occupied) => {
+                        let (_, entry) = occupied.get_mut();
+                        Some(policy.replace(hash, entry, value, weight))
+                    }
+                    Err(_) => {
+                        let hasher = self.shared.map.hasher();
+                        let (_, entry) = table
+                            .insert_unique(hash, (key, CacheEntry::new(value, weight)), |(k, _)| {
+                                hasher.hash_one(k)
+                            })
+                            .into_mut();
+                        policy.add(hash, entry);
+                        None
+                    }
+                };
+                Self::evict(&mut table, &mut policy, &mut evicted);
+                old
+            }
+        };
+
+        self.notify(evicted);
+        old
+    }
+
+    /// Evicts entries until the shard is back within its capacity.
+    fn evict(
+        table: &mut Table<(K, CacheEntry<V>)>,
+        policy: &mut ShardPolicy,
+        evicted: &mut Evicted<K, V>,
+    ) {
+        // Entries leaving the admission window compete with the main space's least recently used
+        // entry for room, and the one that has been accessed less often loses.
+        while policy.is_window_over_capacity() {
+            let Some((stamp, hash)) = policy.window_candidate() else {
+                break;
+            };
+
+            loop {
+                if !policy.is_over_capacity() {
+                    Self::promote(table, policy, stamp, hash);
+                    break;
+                }
+
+                match policy.main_victim() {
+                    Some((victim_stamp, victim_hash))
+                        if policy.frequency(hash) > policy.frequency(victim_hash) =>
+                    {
+                        Self::take(
+                            table,
+                            policy,
+                            victim_stamp,
+                            victim_hash,
+                            RemovalCause::Size,
+                            evicted,
+                        );
+                    }
+                    Some(_) => {
+                        Self::take(table, policy, stamp, hash, RemovalCause::Rejected, evicted);
+                        break;
+                    }
+                    None => {
+                        Self::promote(table, policy, stamp, hash);
+                        break;
+                    }
+                }
+            }
+        }
+
+        while policy.is_over_capacity() {
+            let Some((stamp, hash)) = policy.victim() else {
+                break;
+            };
+            Self::take(table, policy, stamp, hash, RemovalCause::Size, evicted);
+        }
+    }
+
+    fn promote(
+        table: &mut Table<(K, CacheEntry<V>)>,
+        policy: &mut ShardPolicy,
+        stamp: u64,
+        hash: u64,
+    ) {
+        let (_, entry) = table
+            .find_mut(hash, |(_, entry)| entry.stamp() == stamp)
+            .expect("tracked entry is missing from its shard");
+        policy.promote(hash, entry);
+    }
+
+    fn take(
+        table: &mut Table<(K, CacheEntry<V>)>,
+        policy: &mut ShardPolicy,
+        stamp: u64,
+        hash: u64,
+        cause: RemovalCause,
+        evicted: &mut Evicted<K, V>,
+    ) {
+        let Ok(occupied) = table.find_entry(hash, |(_, entry)| entry.stamp() == stamp) else {
+            unreachable!("tracked entry is missing from its shard");
+        };
+        let ((key, entry), _) = occupied.remove();
+        policy.remove(&entry);
+        evicted.push((key, entry.value, cause));
+    }
+
+    /// Returns a reference to the value associated with the key, marking it as recently used.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn get<'a, Q>(&'a self, key: &Q) -> Option<CacheRef<'a, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (idx, hash) = self.shared.map.locate(key);
+        let reader = self.shared.map.shard_at(idx).read().await;
+
+        let found = reader
+            .find(hash, |(k, _)| key.equivalent(k))
+            .map(|(k, entry)| (k as *const K, entry as *const CacheEntry<V>));
+
+        let mut policy = self.policy(idx);
+        policy.record(hash);
+        let (k, entry) = found?;
+        // SAFETY: The key and entry are guaranteed to be valid for the lifetime of the reader.
+        let (k, entry) = unsafe { (&*k, &*entry) };
+        policy.touch(hash, entry);
+        drop(policy);
+
+        Some(CacheRef {
+            entry: MapRef::new(reader, k, entry),
+        })
+    }
+
+    /// Returns `true` if the cache contains the key.
+    ///
+    /// Like [`CacheShardMap::get`], this counts towards how often the key is accessed, which the
+    /// [`EvictionPolicy::TinyLfu`] admission policy goes by. It does not mark the entry as
+    /// recently used.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (idx, hash) = self.shared.map.locate(key);
+        let reader = self.shared.map.shard_at(idx).read().await;
+
+        self.policy(idx).record(hash);
+        reader.find(hash, |(k, _)| key.equivalent(k)).is_some()
+    }
+
+    /// Removes a key from the cache and returns the value associated with it. The value is not
+    /// passed to the eviction listener.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (idx, hash) = self.shared.map.locate(key);
+        let mut table = self.shared.map.shard_at(idx).write().await;
+
+        let occupied = table.find_entry(hash, |(k, _)| key.equivalent(k)).ok()?;
+        let ((_, entry), _) = occupied.remove();
+        self.policy(idx).remove(&entry);
+
+        Some(entry.value)
+    }
+
+    /// Returns the number of entries in the cache.
+    pub async fn len(&self) -> usize {
+        self.shared.map.len().await
+    }
+
+    /// Returns `true` if the cache is empty.
+    pub async fn is_empty(&self) -> bool {
+        self.shared.map.is_empty().await
+    }
+
+    /// Returns the total weight of the entries in the cache.
+    pub async fn weighted_size(&self) -> u64 {
+        let mut size = 0;
+        for idx in 0..self.shared.policies.len() {
+            let _reader = self.shared.map.shard_at(idx).read().await;
+            size += self.policy(idx).weight();
+        }
+        size
+    }
+
+    /// Removes all entries from the cache, passing each of them to the eviction listener with
+    /// [`RemovalCause::Explicit`].
+    pub async fn clear(&self) {
+        for idx in 0..self.shared.policies.len() {
+            let evicted: Evicted<K, V> = {
+                let mut table = self.shared.map.shard_at(idx).write().await;
+                self.policy(idx).clear();
+                table
+                    .take()
+                    .into_iter()
+                    .map(|(key, entry)| (key, entry.value, RemovalCause::Explicit))
+                    .collect()
+            };
+            self.notify(evicted);
+        }
+    }
+}
+
+/// A builder for a [`CacheShardMap`].
+pub struct CacheBuilder<K, V, S = RandomState> {
+    map: ShardMapBuilder<S>,
+    max_capacity: u64,
+    policy: EvictionPolicy,
+    weigher: Option<Weigher<K, V>>,
+    listener: Option<Listener<K, V>>,
+}
+
+impl<K, V, S> CacheBuilder<K, V, S> {
+    /// Sets the number of shards. See [`ShardMapBuilder::shards`].
+    ///
+    /// By default, a cache has no more shards than its maximum weight. With more shards than
+    /// that, some shards get no share at all and never keep an entry.
+    pub fn shards(mut self, shards: usize) -> Self {
+        self.map = self.map.shards(shards);
+        self
+    }
+
+    /// Makes shards grow a step at a time. See [`ShardMapBuilder::incremental_resize`].
+    pub fn incremental_resize(mut self, incremental: bool) -> Self {
+        self.map = self.map.incremental_resize(incremental);
+        self
+    }
+
+    /// Sets the hasher used to hash keys. See [`ShardMapBuilder::hasher`].
+    pub fn hasher<H>(self, hasher: H) -> CacheBuilder<K, V, H> {
+        CacheBuilder {
+            map: self.map.hasher(hasher),
+            max_capacity: self.max_capacity,
+            policy: self.policy,
+            weigher: self.weigher,
+            listener: self.listener,
+        }
+    }
+
+    /// Sets the eviction policy. Defaults to [`EvictionPolicy::Lru`].
+    pub fn policy(mut self, policy: EvictionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets the function used to compute the weight of each entry when it is inserted. By
+    /// default, every entry has a weight of 1.
+    pub fn weigher<F>(mut self, weigher: F) -> Self
+    where
+        F: Fn(&K, &V) -> u64 + Send + Sync + 'static,
+    {
+        self.weigher = Some(Box::new(weigher));
+        self
+    }
+
+    /// Sets a function to call with every entry the cache drops on its own or through
+    /// [`CacheShardMap::clear`], along with the reason it was dropped.
+    ///
+    /// Entries removed with [`CacheShardMap::remove`], and old values replaced by
+    /// [`CacheShardMap::insert`], are returned to the caller and never passed to the listener.
+    ///
+    /// The listener is called after the shard lock is released, from the task that caused the
+    /// removal.
+    pub fn eviction_listener<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(K, V, RemovalCause) + Send + Sync + 'static,
+    {
+        self.listener = Some(Box::new(listener));
+        self
+    }
+
+    /// Creates a [`CacheShardMap`] with this configuration.
+    pub fn build(self) -> Result<CacheShardMap<K, V, S>, BuildError>
+    where
+        K: Eq + Hash + 'static,
+        V: 'static,
+        S: BuildHasher,
+    {
+        let mut map = self.map;
+        if map.shards.is_none() {
+            // Small caches get fewer shards by default, so that every shard has room for at least
+            // one entry.
+            let fit = usize::try_from(self.max_capacity.max(2) + 1)
+                .map_or(usize::MAX, |fit| fit.next_power_of_two() / 2);
+            map = map.shards(crate::shard_map::shard_count().min(fit));
+        }
+        let map = map.build()?;
+
+        // The first `extra` shards take one more unit each, so that the shares add up to exactly
+        // `max_capacity`.
+        let shards = map.shard_count() as u64;
+        let (share, extra) = (self.max_capacity / shards, self.max_capacity % shards);
+        let policies = (0..shards)
+            .map(|idx| {
+                let capacity = share + u64::from(idx < extra);
+                CachePadded::new(Mutex::new(ShardPolicy::new(self.policy, capacity)))
+            })
+            .collect();
+
+        Ok(CacheShardMap {
+            shared: Arc::new(Shared {
+                map,
+                policies,
+                weigher: self.weigher,
+                listener: self.listener,
+            }),
+        })
+    }
+}
+
+/// A reference to a key-value pair in a [`CacheShardMap`].
+///
+/// Holds a shared (read-only) lock on the shard associated with the key. Dropping this
+/// reference will release the lock.
+pub struct CacheRef<'a, K, V> {
+    entry: MapRef<'a, K, CacheEntry<V>>,
+}
+
+impl<K, V> std::ops::Deref for CacheRef<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V> CacheRef<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        &self.entry.value().value
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+}
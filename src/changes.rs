@@ -0,0 +1,232 @@
+//! This module contains the [`ChangeStream`] returned by [`crate::ShardMap::subscribe`], an
+//! ordered feed of every mutation applied to a map, for replication and auditing.
+//!
+//! Every [`ChangeEvent`] carries the shard it happened in and a sequence number that increases
+//! by exactly one for each event in that shard. Events of a single shard are delivered in the
+//! order they were applied, while events of different shards may interleave.
+//!
+//! Shards are numbered from 0 in a new map. [`crate::ShardMap::reshard`] moves the entries of a
+//! map into new shards, which are numbered after every shard the map had before, so a shard
+//! number never refers to two different shards. Moving an entry is not a change to the map and
+//! is not reported; the events of a key simply come from its new shard afterwards.
+//!
+//! The feed is buffered up to [`CHANGE_BUFFER`] events. A subscriber that falls further behind
+//! misses the oldest events, which shows up as a gap in the sequence numbers of a shard.
+//!
+//! # Example
+//! ```
+//! use whirlwind::{changes::ChangeKind, ShardMap};
+//! use tokio::runtime::Runtime;
+//!
+//! let rt = Runtime::new().unwrap();
+//! # #[cfg(feature = "tokio")]
+//! let map = ShardMap::new();
+//! # #[cfg(feature = "tokio")]
+//! rt.block_on(async {
+//!     let mut changes = map.subscribe();
+//!
+//!     map.insert("foo", 1).await;
+//!     *map.get_mut(&"foo").await.unwrap() += 1;
+//!     map.remove(&"foo").await;
+//!
+//!     let event = changes.recv().await.unwrap();
+//!     assert_eq!(event.seq, 0);
+//!     assert_eq!(event.kind, ChangeKind::Insert("foo", 1));
+//!
+//!     assert_eq!(changes.recv().await.unwrap().kind, ChangeKind::Update("foo", 2));
+//!     assert_eq!(changes.recv().await.unwrap().kind, ChangeKind::Remove("foo"));
+//! });
+//! ```
+#[cfg(feature = "tokio")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "tokio")]
+use futures_util::{stream, Stream, StreamExt};
+#[cfg(feature = "tokio")]
+use tokio::sync::broadcast;
+
+#[cfg(feature = "tokio")]
+use crate::shard::Shard;
+
+/// The number of events the feed buffers for subscribers that have not received them yet.
+pub const CHANGE_BUFFER: usize = 1024;
+
+/// A single mutation applied to a [`crate::ShardMap`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent<K, V> {
+    /// The shard the mutation was applied to. This is the index of the shard, unless the map has
+    /// been resharded.
+    pub shard: usize,
+    /// The position of the event among the events of its shard, starting at 0 when the first
+    /// subscriber of the map subscribed.
+    pub seq: u64,
+    /// The mutation itself.
+    pub kind: ChangeKind<K, V>,
+}
+
+/// The kind of a [`ChangeEvent`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind<K, V> {
+    /// A key that was not in the map was inserted with a value.
+    Insert(K, V),
+    /// The value of a key was replaced or modified. Holds a copy of the new value.
+    ///
+    /// A mutable reference to a value, such as a [`crate::mapref::MapRefMut`], counts as an
+    /// update when it is dropped if it was ever used to get mutable access to the value.
+    Update(K, V),
+    /// A key was removed from the map.
+    Remove(K),
+    /// Every key in the shard was removed at once.
+    Clear,
+}
+
+/// An ordered feed of the [`ChangeEvent`]s of a [`crate::ShardMap`].
+///
+/// Once every clone of the map has been dropped, the buffered events are drained and then
+/// [`ChangeStream::recv`] returns `None`.
+#[cfg(feature = "tokio")]
+pub struct ChangeStream<K, V> {
+    events: Pin<Box<dyn Stream<Item = ChangeEvent<K, V>> + Send>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<K, V> ChangeStream<K, V>
+where
+    K: Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    fn new(rx: broadcast::Receiver<ChangeEvent<K, V>>) -> Self {
+        let events = stream::unfold(rx, |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    // Missed events show up as gaps in the sequence numbers.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        Self {
+            events: Box::pin(events),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<K, V> ChangeStream<K, V> {
+    /// Waits for the next event. Returns `None` once the map has been dropped.
+    pub async fn recv(&mut self) -> Option<ChangeEvent<K, V>> {
+        self.events.next().await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<K, V> Stream for ChangeStream<K, V> {
+    type Item = ChangeEvent<K, V>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.as_mut().poll_next(cx)
+    }
+}
+
+/// The sending half of the change feed of a map, created by its first subscriber.
+#[cfg(feature = "tokio")]
+pub(crate) struct Feed<K, V> {
+    tx: broadcast::Sender<ChangeEvent<K, V>>,
+    // Changes are published without any bounds on `K` and `V`, so cloning them is captured up
+    // front.
+    clone_key: fn(&K) -> K,
+    clone_value: fn(&V) -> V,
+}
+
+#[cfg(feature = "tokio")]
+impl<K, V> Feed<K, V>
+where
+    K: Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    pub(crate) fn new() -> Self {
+        Self {
+            tx: broadcast::channel(CHANGE_BUFFER).0,
+            clone_key: K::clone,
+            clone_value: V::clone,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> ChangeStream<K, V> {
+        ChangeStream::new(self.tx.subscribe())
+    }
+}
+
+/// A change to a single key, as seen by the watchers of a map.
+pub(crate) enum Change<'a, V> {
+    Insert(&'a V),
+    Update(&'a V),
+    Remove,
+}
+
+// Deriving these would require `V: Copy`.
+impl<V> Clone for Change<'_, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<V> Copy for Change<'_, V> {}
+
+impl<'a, V> Change<'a, V> {
+    /// The new value of the key, or `None` if it was removed.
+    pub(crate) fn value(self) -> Option<&'a V> {
+        match self {
+            Change::Insert(value) | Change::Update(value) => Some(value),
+            Change::Remove => None,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<K, V> Feed<K, V> {
+    pub(crate) fn is_active(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    /// Sequence numbers are only taken while the shard is locked for writing, so they match the
+    /// order in which changes were applied.
+    fn publish(&self, shard: &Shard<K, V>, kind: ChangeKind<K, V>) {
+        let event = ChangeEvent {
+            shard: shard.id(),
+            seq: shard.next_seq(),
+            kind,
+        };
+        // Sending only fails if every subscriber is gone, in which case nobody is missing out.
+        let _ = self.tx.send(event);
+    }
+
+    /// Publishes a change to a key. This must be called while the key's shard is locked for
+    /// writing.
+    pub(crate) fn change(&self, shard: &Shard<K, V>, key: &K, change: Change<'_, V>) {
+        if !self.is_active() {
+            return;
+        }
+
+        let key = (self.clone_key)(key);
+        let kind = match change {
+            Change::Insert(value) => ChangeKind::Insert(key, (self.clone_value)(value)),
+            Change::Update(value) => ChangeKind::Update(key, (self.clone_value)(value)),
+            Change::Remove => ChangeKind::Remove(key),
+        };
+        self.publish(shard, kind);
+    }
+
+    /// Publishes the removal of every key in a shard. This must be called while the shard is
+    /// locked for writing.
+    pub(crate) fn clear(&self, shard: &Shard<K, V>) {
+        if self.is_active() {
+            self.publish(shard, ChangeKind::Clear);
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! This module contains the [`Entry`] API for [`crate::ShardMap`], which allows for inspecting and
+//! modifying a single key-value pair while holding a write lock on its shard.
+//!
+//! # Example
+//! ```
+//! use whirlwind::ShardMap;
+//! use tokio::runtime::Runtime;
+//! use std::sync::Arc;
+//!
+//! let rt = Runtime::new().unwrap();
+//! let map = Arc::new(ShardMap::new());
+//! rt.block_on(async {
+//!     map.entry("foo").await.or_insert(0);
+//!     map.entry("foo").await.and_modify(|v| *v += 1).or_insert(0);
+//!
+//!     assert_eq!(map.get(&"foo").await.unwrap().value(), &1);
+//! });
+//! ```
+use std::{
+    future::Future,
+    hash::BuildHasher,
+    mem::ManuallyDrop,
+    ptr::{self, NonNull},
+};
+
+use crate::{
+    mapref::MapRefMut,
+    shard::{Shard, ShardWriter},
+    watch::{ChangeTracker, Watchers},
+};
+
+/// A view into a single entry in a [`crate::ShardMap`], which may be either vacant or occupied.
+///
+/// Holds an exclusive lock on the shard associated with the key. Dropping this entry will
+/// release the lock.
+pub enum Entry<'a, K, V, S = std::hash::RandomState> {
+    /// An occupied entry.
+    Occupied(OccupiedEntry<'a, K, V>),
+    /// A vacant entry.
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+/// A view into an occupied entry in a [`crate::ShardMap`].
+pub struct OccupiedEntry<'a, K, V> {
+    pair: NonNull<(K, V)>,
+    hash: u64,
+    tracker: ChangeTracker<'a, K, V>,
+    writer: ShardWriter<'a, K, V>,
+}
+
+/// A view into a vacant entry in a [`crate::ShardMap`].
+pub struct VacantEntry<'a, K, V, S = std::hash::RandomState> {
+    key: K,
+    shard: &'a Shard<K, V>,
+    hash: u64,
+    writer: ShardWriter<'a, K, V>,
+    hasher: &'a S,
+    watchers: &'a Watchers<K, V>,
+}
+
+// SAFETY: The pointer is only ever dereferenced while the write lock is held, so the entry is as
+// thread-safe as the writer itself.
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for OccupiedEntry<'_, K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for OccupiedEntry<'_, K, V> {}
+
+impl<K, V> Drop for OccupiedEntry<'_, K, V> {
+    fn drop(&mut self) {
+        if self.tracker.is_dirty() {
+            // SAFETY: The pair is valid for as long as the writer is held, and a removed entry is
+            // never dirty.
+            let (key, value) = unsafe { self.pair.as_ref() };
+            self.tracker.flush(key, value);
+        }
+    }
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
+where
+    K: Eq + std::hash::Hash,
+    S: BuildHasher,
+{
+    /// Returns a reference to the key of this entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting `default` if it is vacant, and returns a
+    /// mutable reference to the value.
+    pub fn or_insert(self, default: V) -> MapRefMut<'a, K, V> {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of `default` if it is vacant, and
+    /// returns a mutable reference to the value.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> MapRefMut<'a, K, V> {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of `default` if it is vacant, and
+    /// returns a mutable reference to the value. The key is passed to `default`.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> MapRefMut<'a, K, V> {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default(entry.key());
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Ensures a value is in the entry by awaiting the future returned by `default` if it is
+    /// vacant, and returns a mutable reference to the value.
+    ///
+    /// The shard stays locked while the future is awaited, so it should complete quickly.
+    pub async fn or_insert_with_async<F, Fut>(self, default: F) -> MapRefMut<'a, K, V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let value = default().await;
+                entry.insert(value)
+            }
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting `V::default()` if it is vacant, and returns a
+    /// mutable reference to the value.
+    pub fn or_default(self) -> MapRefMut<'a, K, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Calls `f` with a mutable reference to the value if the entry is occupied.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(entry.get_mut());
+                Entry::Occupied(entry)
+            }
+            Entry::Vacant(entry) => Entry::Vacant(entry),
+        }
+    }
+
+    /// Sets the value of the entry, and returns the occupied entry.
+    pub fn insert(self, value: V) -> OccupiedEntry<'a, K, V> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                entry.insert(value);
+                entry
+            }
+            Entry::Vacant(entry) => entry.insert_entry(value),
+        }
+    }
+
+    /// Removes the entry from the map, returning the value if it was occupied.
+    pub fn remove(self) -> Option<V> {
+        match self {
+            Entry::Occupied(entry) => Some(entry.remove()),
+            Entry::Vacant(_) => None,
+        }
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Eq + std::hash::Hash,
+{
+    pub(crate) fn new(
+        writer: ShardWriter<'a, K, V>,
+        pair: NonNull<(K, V)>,
+        shard: &'a Shard<K, V>,
+        hash: u64,
+        watchers: &'a Watchers<K, V>,
+    ) -> Self {
+        Self {
+            pair,
+            hash,
+            tracker: ChangeTracker::new(watchers, shard, hash),
+            writer,
+        }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        // SAFETY: The pair is valid for as long as the writer is held.
+        unsafe { &self.pair.as_ref().0 }
+    }
+
+    /// Returns a reference to the value.
+    pub fn get(&self) -> &V {
+        // SAFETY: The pair is valid for as long as the writer is held.
+        unsafe { &self.pair.as_ref().1 }
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn get_mut(&mut self) -> &mut V {
+        self.tracker.mark();
+        // SAFETY: The pair is valid for as long as the writer is held.
+        unsafe { &mut self.pair.as_mut().1 }
+    }
+
+    /// Sets the value of the entry, and returns the old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Converts the entry into a [`MapRefMut`] which keeps the shard locked.
+    pub fn into_mut(self) -> MapRefMut<'a, K, V> {
+        let this = ManuallyDrop::new(self);
+        let pair = this.pair.as_ptr();
+        // SAFETY: `this` is never used or dropped again, so the writer and tracker are moved out
+        // exactly once. The key and value are guaranteed to be valid for the lifetime of the writer.
+        unsafe {
+            let writer = ptr::read(&this.writer);
+            let tracker = ptr::read(&this.tracker);
+            MapRefMut::new(writer, &(*pair).0, &mut (*pair).1, tracker)
+        }
+    }
+
+    /// Removes the entry from the map, and returns the value.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Removes the entry from the map, and returns the key-value pair.
+    pub fn remove_entry(mut self) -> (K, V) {
+        let pair = self.pair.as_ptr() as *const (K, V);
+        match self
+            .writer
+            .find_entry(self.hash, |candidate| std::ptr::eq(candidate, pair))
+        {
+            Ok(occupied) => {
+                let (pair, _) = occupied.remove();
+                self.tracker.removed(&pair.0);
+                pair
+            }
+            Err(_) => unreachable!("occupied entry is missing from its shard"),
+        }
+    }
+}
+
+impl<'a, K, V, S> VacantEntry<'a, K, V, S>
+where
+    K: Eq + std::hash::Hash,
+    S: BuildHasher,
+{
+    pub(crate) fn new(
+        writer: ShardWriter<'a, K, V>,
+        key: K,
+        shard: &'a Shard<K, V>,
+        hash: u64,
+        hasher: &'a S,
+        watchers: &'a Watchers<K, V>,
+    ) -> Self {
+        Self {
+            key,
+            shard,
+            hash,
+            writer,
+            hasher,
+            watchers,
+        }
+    }
+
+    /// Returns a reference to the key that would be used when inserting a value.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Takes ownership of the key, releasing the lock on the shard.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Inserts a value into the entry, and returns a mutable reference to it.
+    pub fn insert(self, value: V) -> MapRefMut<'a, K, V> {
+        self.insert_entry(value).into_mut()
+    }
+
+    /// Inserts a value into the entry, and returns the occupied entry.
+    pub fn insert_entry(mut self, value: V) -> OccupiedEntry<'a, K, V> {
+        let hasher = self.hasher;
+        let pair = NonNull::from(
+            self.writer
+                .insert_unique(self.hash, (self.key, value), |(k, _)| hasher.hash_one(k))
+                .into_mut(),
+        );
+        let mut entry = OccupiedEntry::new(self.writer, pair, self.shard, self.hash, self.watchers);
+        entry.tracker.mark_inserted();
+        entry
+    }
+}
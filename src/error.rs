@@ -0,0 +1,95 @@
+//! Error types returned by the data structures in this crate.
+
+/// Error returned by the non-blocking `try_*` methods when the shard associated with a key is
+/// currently locked.
+///
+/// Methods that take ownership of their arguments hand them back through [`WouldBlock::into_inner`]
+/// so that the operation can be retried later.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct WouldBlock<T = ()>(pub T);
+
+impl<T> WouldBlock<T> {
+    /// Returns the value that could not be inserted.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::fmt::Debug for WouldBlock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WouldBlock(..)")
+    }
+}
+
+impl<T> std::fmt::Display for WouldBlock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("operation would block because the shard is locked")
+    }
+}
+
+impl<T> std::error::Error for WouldBlock<T> {}
+
+/// Error returned by [`crate::ShardMapBuilder::build`] when the configuration is invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// The shard count was less than two.
+    TooFewShards(usize),
+    /// The shard count was not a power of two, and rounding up was not enabled.
+    ShardsNotPowerOfTwo(usize),
+    /// The shard count was too large to select shards from a hash.
+    TooManyShards(usize),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::TooFewShards(n) => {
+                write!(f, "shard count must be at least 2, got {n}")
+            }
+            BuildError::ShardsNotPowerOfTwo(n) => {
+                write!(f, "shard count must be a power of two, got {n}")
+            }
+            BuildError::TooManyShards(n) => {
+                write!(
+                    f,
+                    "shard count must be at most {}, got {n}",
+                    crate::shard_map::MAX_SHARDS
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Error returned by [`crate::ShardMap::transaction`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionError<E> {
+    /// The transaction closure returned an error after reading a consistent view of the map.
+    Aborted(E),
+    /// The transaction conflicted with other writers on every one of its
+    /// [`crate::transaction::MAX_ATTEMPTS`] attempts.
+    Conflict,
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for TransactionError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::Aborted(err) => write!(f, "transaction aborted: {err}"),
+            TransactionError::Conflict => write!(
+                f,
+                "transaction conflicted with other writers {} times in a row",
+                crate::transaction::MAX_ATTEMPTS
+            ),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for TransactionError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransactionError::Aborted(err) => Some(err),
+            TransactionError::Conflict => None,
+        }
+    }
+}
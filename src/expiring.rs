@@ -0,0 +1,600 @@
+//! This module contains [`ExpiringShardMap`], a [`ShardMap`] whose entries can be given a
+//! time-to-live, along with the [`Clock`] it uses to tell the time.
+//!
+//! Expired entries are invisible to reads as soon as their deadline passes, but they keep using
+//! memory until they are reclaimed, either by [`ExpiringShardMap::purge_expired`] or by a
+//! background sweeper started with [`ExpiringShardMap::spawn_sweeper`].
+//!
+//! Every shard has a queue of the deadlines of its entries, soonest first, so reclaiming expired
+//! entries only visits those entries rather than the whole map. Replacing an entry or changing
+//! its deadline outdates its previous deadline, which is skipped when it passes, and the queue is
+//! compacted once outdated deadlines make up half of it.
+//!
+//! # Example
+//! ```
+//! use std::time::Duration;
+//! use whirlwind::ExpiringShardMap;
+//!
+//! # #[tokio::main(flavor = "current_thread", start_paused = true)]
+//! # async fn main() {
+//! let map = ExpiringShardMap::new();
+//! map.insert_with_ttl("session", 42, Duration::from_secs(30)).await;
+//! map.insert("config", 7).await;
+//!
+//! tokio::time::advance(Duration::from_secs(31)).await;
+//!
+//! assert!(map.get(&"session").await.is_none());
+//! assert_eq!(map.get(&"config").await.unwrap().value(), &7);
+//! # }
+//! ```
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use crossbeam_utils::CachePadded;
+use hashbrown::Equivalent;
+use parking_lot::Mutex;
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    entry::Entry,
+    error::BuildError,
+    mapref::{MapRef, MapRefMut},
+    shard_map::{shard_for_hash, shard_shift},
+    ShardMap, ShardMapBuilder,
+};
+
+/// A source of the current time for an [`ExpiringShardMap`].
+///
+/// The default, [`TokioClock`], follows tokio's clock, so expiry can be tested by pausing and
+/// advancing time with [`tokio::time::pause`] and [`tokio::time::advance`]. Any
+/// `Fn() -> Instant` closure can be used as a clock as well.
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+}
+
+/// A [`Clock`] that reads the time from [`tokio::time::Instant::now`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> Instant + Send + Sync + 'static,
+{
+    fn now(&self) -> Instant {
+        self()
+    }
+}
+
+/// A value stored alongside the time it expires at, if any.
+pub(crate) struct Timed<V> {
+    value: V,
+    expires_at: Option<Instant>,
+}
+
+impl<V> Timed<V> {
+    fn new(value: V, expires_at: Option<Instant>) -> Self {
+        Self { value, expires_at }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    fn live(self, now: Instant) -> Option<V> {
+        (!self.is_expired(now)).then_some(self.value)
+    }
+}
+
+/// The deadlines of the entries that expire, identified by the hashes of their keys. Deadlines
+/// are only scheduled while the shard of their key is locked for writing, so that the latest
+/// deadline of a hash is the one of the entry in the map.
+#[derive(Default)]
+struct ExpiryQueue {
+    /// Every deadline scheduled and not popped yet, soonest first, including outdated ones.
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    /// The latest deadline scheduled for each hash.
+    live: HashMap<u64, Instant>,
+}
+
+impl ExpiryQueue {
+    fn schedule(&mut self, hash: u64, at: Instant) {
+        self.live.insert(hash, at);
+        self.heap.push(Reverse((at, hash)));
+        self.compact();
+    }
+
+    fn unschedule(&mut self, hash: u64) {
+        if self.live.remove(&hash).is_some() {
+            self.compact();
+        }
+    }
+
+    /// Drops the outdated deadlines once they make up more than half of the heap, which keeps
+    /// the heap from growing when the same keys are refreshed over and over.
+    fn compact(&mut self) {
+        if self.heap.len() > 2 * self.live.len().max(16) {
+            let live = &self.live;
+            self.heap
+                .retain(|Reverse((at, hash))| live.get(hash) == Some(at));
+        }
+    }
+
+    /// Pops the deadlines up to `now`, adding the hashes of the ones that are not outdated to
+    /// `hashes`.
+    fn pop_expired(&mut self, now: Instant, hashes: &mut Vec<u64>) {
+        while let Some(&Reverse((at, hash))) = self.heap.peek() {
+            if at > now {
+                break;
+            }
+            self.heap.pop();
+            if self.live.get(&hash) == Some(&at) {
+                self.live.remove(&hash);
+                hashes.push(hash);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.heap.clear();
+        self.live.clear();
+    }
+}
+
+struct Shared<K, V, S, C> {
+    map: ShardMap<K, Timed<V>, S>,
+    clock: C,
+    /// One queue for each shard the map started out with, selected by hash like the shards.
+    queues: Box<[CachePadded<Mutex<ExpiryQueue>>]>,
+    shift: usize,
+}
+
+impl<K, V, S, C> Shared<K, V, S, C> {
+    fn queue(&self, hash: u64) -> &Mutex<ExpiryQueue> {
+        &self.queues[shard_for_hash(hash as usize, self.shift)]
+    }
+}
+
+/// A concurrent hashmap whose entries can expire after a time-to-live.
+///
+/// Entries inserted with [`ExpiringShardMap::insert_with_ttl`] become invisible to every read once
+/// their time-to-live has elapsed. Entries inserted with [`ExpiringShardMap::insert`] never
+/// expire.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use whirlwind::ExpiringShardMap;
+///
+/// # #[tokio::main(flavor = "current_thread", start_paused = true)]
+/// # async fn main() {
+/// let map = ExpiringShardMap::new();
+/// let sweeper = map.spawn_sweeper(Duration::from_secs(1));
+///
+/// map.insert_with_ttl("foo", "bar", Duration::from_secs(5)).await;
+/// assert!(map.contains_key(&"foo").await);
+///
+/// tokio::time::advance(Duration::from_secs(5)).await;
+/// assert!(!map.contains_key(&"foo").await);
+///
+/// drop(map);
+/// sweeper.await.unwrap();
+/// # }
+/// ```
+pub struct ExpiringShardMap<K, V, S = RandomState, C = TokioClock> {
+    shared: Arc<Shared<K, V, S, C>>,
+}
+
+impl<K, V, S, C> Clone for ExpiringShardMap<K, V, S, C> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<K, V> Default for ExpiringShardMap<K, V, RandomState, TokioClock>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> ExpiringShardMap<K, V, RandomState, TokioClock>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+{
+    /// Creates a new `ExpiringShardMap` that follows tokio's clock.
+    pub fn new() -> Self {
+        Self::with_clock(TokioClock)
+    }
+
+    /// Creates a new `ExpiringShardMap` with the provided number of shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is not a power of two greater than one.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::from_map(ShardMap::with_shards(shards), TokioClock)
+    }
+}
+
+impl<K, V, C> ExpiringShardMap<K, V, RandomState, C>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    C: Clock,
+{
+    /// Creates a new `ExpiringShardMap` that reads the time from `clock`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::{sync::{Arc, Mutex}, time::Duration};
+    /// use tokio::time::Instant;
+    /// use whirlwind::ExpiringShardMap;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let now = Arc::new(Mutex::new(Instant::now()));
+    /// let clock = {
+    ///     let now = Arc::clone(&now);
+    ///     move || *now.lock().unwrap()
+    /// };
+    ///
+    /// let map = ExpiringShardMap::with_clock(clock);
+    /// map.insert_with_ttl(1, 1, Duration::from_secs(10)).await;
+    ///
+    /// *now.lock().unwrap() += Duration::from_secs(10);
+    /// assert!(map.get(&1).await.is_none());
+    /// # }
+    /// ```
+    pub fn with_clock(clock: C) -> Self {
+        Self::from_map(ShardMap::new(), clock)
+    }
+}
+
+impl<K, V, S, C> ExpiringShardMap<K, V, S, C>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    S: BuildHasher,
+    C: Clock,
+{
+    fn from_map(map: ShardMap<K, Timed<V>, S>, clock: C) -> Self {
+        let shards = map.shard_count();
+        Self {
+            shared: Arc::new(Shared {
+                map,
+                clock,
+                queues: (0..shards).map(|_| CachePadded::default()).collect(),
+                shift: shard_shift(shards),
+            }),
+        }
+    }
+
+    pub(crate) fn from_builder(builder: ShardMapBuilder<S>, clock: C) -> Result<Self, BuildError> {
+        builder.build().map(|map| Self::from_map(map, clock))
+    }
+
+    fn now(&self) -> Instant {
+        self.shared.clock.now()
+    }
+
+    /// Inserts a key-value pair that never expires. If the key already exists, the value is
+    /// updated and the old value is returned, unless it had already expired.
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        self.insert_timed(key, Timed::new(value, None)).await
+    }
+
+    /// Inserts a key-value pair that expires once `ttl` has elapsed. If the key already exists,
+    /// the value and its expiry are replaced, and the old value is returned unless it had already
+    /// expired.
+    pub async fn insert_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<V> {
+        let expires_at = self.now() + ttl;
+        self.insert_timed(key, Timed::new(value, Some(expires_at)))
+            .await
+    }
+
+    async fn insert_timed(&self, key: K, value: Timed<V>) -> Option<V> {
+        let hash = self.shared.map.hash(&key);
+        let expires_at = value.expires_at;
+
+        // The deadline is scheduled while the entry is locked, so that it matches the entry.
+        let (old, _entry) = match self.shared.map.entry(key).await {
+            Entry::Occupied(mut entry) => (Some(entry.insert(value)), entry),
+            Entry::Vacant(entry) => (None, entry.insert_entry(value)),
+        };
+        let mut queue = self.shared.queue(hash).lock();
+        match expires_at {
+            Some(at) => queue.schedule(hash, at),
+            None => queue.unschedule(hash),
+        }
+        drop(queue);
+
+        old?.live(self.now())
+    }
+
+    /// Returns a reference to the value associated with the key, if it has not expired.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn get<'a, Q>(&'a self, key: &Q) -> Option<ExpiringRef<'a, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let entry = self.shared.map.get(key).await?;
+        (!entry.value().is_expired(self.now())).then_some(ExpiringRef { entry })
+    }
+
+    /// Returns a mutable reference to the value associated with the key, if it has not expired.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn get_mut<'a, Q>(&'a self, key: &Q) -> Option<ExpiringRefMut<'a, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.shared.map.hash(key);
+        let entry = self.shared.map.get_mut(key).await?;
+        (!entry.value().is_expired(self.now())).then_some(ExpiringRefMut {
+            entry,
+            queue: self.shared.queue(hash),
+            hash,
+        })
+    }
+
+    /// Returns `true` if the map contains the key and it has not expired.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        self.get(key).await.is_some()
+    }
+
+    /// Returns the time left before the key expires.
+    ///
+    /// Returns `None` if the key is missing or has already expired, and `Some(None)` if it never
+    /// expires.
+    pub async fn ttl<Q>(&self, key: &Q) -> Option<Option<Duration>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let entry = self.get(key).await?;
+        let now = self.now();
+        Some(
+            entry
+                .expires_at()
+                .map(|at| at.saturating_duration_since(now)),
+        )
+    }
+
+    /// Removes a key from the map, returning its value if it had not expired.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let old = self.shared.map.remove(key).await?;
+        old.live(self.now())
+    }
+
+    /// Returns the number of entries in the map that have not expired.
+    ///
+    /// Expired entries are purged first, as with [`ExpiringShardMap::purge_expired`], so this takes
+    /// time proportional to the number of entries that expired since the last purge.
+    pub async fn len(&self) -> usize {
+        self.purge_expired().await;
+        self.shared.map.len().await
+    }
+
+    /// Returns `true` if every entry in the map has expired, or the map is empty.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Removes all entries from the map.
+    pub async fn clear(&self) {
+        // Emptied first, as the deadlines of entries inserted meanwhile are kept until the map is
+        // cleared.
+        for queue in self.shared.queues.iter() {
+            queue.lock().clear();
+        }
+        self.shared.map.clear().await;
+    }
+
+    /// Reclaims the memory used by expired entries, returning how many were removed.
+    ///
+    /// The deadlines that passed are taken from the expiry queues, and only the shards holding
+    /// those entries are locked, one at a time. This takes time proportional to the number of
+    /// entries that expired, not to the size of the map.
+    pub async fn purge_expired(&self) -> usize {
+        let now = self.now();
+        let mut hashes = Vec::new();
+        for queue in self.shared.queues.iter() {
+            queue.lock().pop_expired(now, &mut hashes);
+        }
+
+        if hashes.is_empty() {
+            return 0;
+        }
+        self.shared
+            .map
+            .remove_hashes(&hashes, |value| value.is_expired(now))
+            .await
+    }
+}
+
+impl<K, V, S, C> ExpiringShardMap<K, V, S, C>
+where
+    K: Eq + Hash + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    S: BuildHasher + Send + Sync + 'static,
+    C: Clock,
+{
+    /// Spawns a task on the current tokio runtime that calls [`ExpiringShardMap::purge_expired`]
+    /// every `period`.
+    ///
+    /// The period is measured by tokio's timer, not by the [`Clock`] of the map. With a custom
+    /// clock, the sweeper still wakes up every `period` of tokio time, and purges the entries
+    /// that have expired according to the clock.
+    ///
+    /// The task only holds a weak reference to the map, and exits on its own once every clone of
+    /// the map has been dropped. Abort the returned handle to stop it sooner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, or if `period` is zero.
+    pub fn spawn_sweeper(&self, period: Duration) -> JoinHandle<()> {
+        let shared = Arc::downgrade(&self.shared);
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+
+                let Some(shared) = Weak::upgrade(&shared) else {
+                    break;
+                };
+                ExpiringShardMap { shared }.purge_expired().await;
+            }
+        })
+    }
+}
+
+/// A reference to an unexpired key-value pair in an [`ExpiringShardMap`].
+///
+/// Holds a shared (read-only) lock on the shard associated with the key. Dropping this
+/// reference will release the lock.
+pub struct ExpiringRef<'a, K, V> {
+    entry: MapRef<'a, K, Timed<V>>,
+}
+
+impl<K, V> std::ops::Deref for ExpiringRef<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V> ExpiringRef<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        &self.entry.value().value
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+
+    /// Returns the time the entry expires at, or `None` if it never expires.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.entry.value().expires_at
+    }
+}
+
+/// A mutable reference to an unexpired key-value pair in an [`ExpiringShardMap`].
+///
+/// Holds an exclusive lock on the shard associated with the key. Dropping this
+/// reference will release the lock.
+pub struct ExpiringRefMut<'a, K, V> {
+    entry: MapRefMut<'a, K, Timed<V>>,
+    queue: &'a Mutex<ExpiryQueue>,
+    hash: u64,
+}
+
+impl<K, V> std::ops::Deref for ExpiringRefMut<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V> std::ops::DerefMut for ExpiringRefMut<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.value_mut()
+    }
+}
+
+impl<K, V> ExpiringRefMut<'_, K, V>
+where
+    K: Eq + Hash,
+{
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.entry.key()
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        &self.entry.value().value
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn value_mut(&mut self) -> &mut V {
+        &mut self.entry.value_mut().value
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key(), self.value())
+    }
+
+    /// Returns the time the entry expires at, or `None` if it never expires.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.entry.value().expires_at
+    }
+
+    /// Sets the time the entry expires at. Passing `None` makes the entry never expire.
+    pub fn set_expires_at(&mut self, expires_at: Option<Instant>) {
+        self.entry.value_mut().expires_at = expires_at;
+        let mut queue = self.queue.lock();
+        match expires_at {
+            Some(at) => queue.schedule(self.hash, at),
+            None => queue.unschedule(self.hash),
+        }
+    }
+}
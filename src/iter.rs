@@ -0,0 +1,89 @@
+//! Owning iterators over the contents of a [`crate::ShardMap`] or [`crate::ShardSet`].
+//!
+//! # Example
+//! ```
+//! use whirlwind::ShardMap;
+//! use tokio::runtime::Runtime;
+//!
+//! let rt = Runtime::new().unwrap();
+//! let map = ShardMap::new();
+//! rt.block_on(async {
+//!     map.insert("foo", 1).await;
+//!     map.insert("bar", 2).await;
+//! });
+//!
+//! let mut pairs: Vec<_> = map.into_iter().collect();
+//! pairs.sort();
+//!
+//! assert_eq!(pairs, [("bar", 2), ("foo", 1)]);
+//! ```
+use std::iter::FusedIterator;
+
+use crate::table::Table;
+
+/// An owning iterator over the key-value pairs of a [`crate::ShardMap`].
+///
+/// Created by the [`IntoIterator`] implementation for [`crate::ShardMap`], or by
+/// [`crate::ShardMap::try_into_iter`].
+pub struct IntoIter<K, V> {
+    inner: std::iter::Flatten<std::vec::IntoIter<Table<(K, V)>>>,
+    remaining: usize,
+}
+
+impl<K, V> IntoIter<K, V> {
+    pub(crate) fn new(tables: Vec<Table<(K, V)>>) -> Self {
+        let remaining = tables.iter().map(Table::len).sum();
+        Self {
+            inner: tables.into_iter().flatten(),
+            remaining,
+        }
+    }
+}
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pair = self.inner.next()?;
+        self.remaining -= 1;
+        Some(pair)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for IntoIter<K, V> {}
+
+impl<K, V> FusedIterator for IntoIter<K, V> {}
+
+/// An owning iterator over the values of a [`crate::ShardSet`].
+///
+/// Created by the [`IntoIterator`] implementation for [`crate::ShardSet`], or by
+/// [`crate::ShardSet::try_into_iter`].
+pub struct SetIntoIter<T> {
+    inner: IntoIter<T, ()>,
+}
+
+impl<T> SetIntoIter<T> {
+    pub(crate) fn new(inner: IntoIter<T, ()>) -> Self {
+        Self { inner }
+    }
+}
+
+impl<T> Iterator for SetIntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(value, ())| value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for SetIntoIter<T> {}
+
+impl<T> FusedIterator for SetIntoIter<T> {}
@@ -6,6 +6,11 @@
 //!
 //! - [`ShardMap`]: A concurrent hashmap using a sharding strategy.
 //! - [`ShardSet`]: A concurrent set based on a [`ShardMap`] with values of `()`.
+//! - [`ExpiringShardMap`]: A [`ShardMap`] whose entries can expire after a time-to-live. Needs the
+//!   `tokio` feature, which is enabled by default.
+//! - [`CacheShardMap`]: A [`ShardMap`] with a maximum size, which evicts entries once it is full.
+//! - [`SyncShardMap`]: A variant of [`ShardMap`] with synchronous methods, for CPU-bound code.
+//! - [`RcuShardMap`]: A read-optimized variant of [`ShardMap`], whose readers never block.
 //!
 //! ## ShardMap
 //!
@@ -53,10 +58,40 @@
 //!
 //! See the documentation for each data structure for more information.
 
+mod builder;
+pub mod cache;
+pub mod changes;
+pub mod entry;
+pub mod error;
+#[cfg(feature = "tokio")]
+pub mod expiring;
+pub mod iter;
+#[cfg(feature = "tokio")]
+mod loader;
 pub mod mapref;
+#[cfg(feature = "persistence")]
+pub mod persistence;
+mod policy;
+pub mod rcu;
+#[cfg(feature = "serde")]
+mod serde;
 mod shard;
 mod shard_map;
 mod shard_set;
+pub mod snapshot;
+pub mod sync;
+mod table;
+pub mod transaction;
+pub mod watch;
 
+pub use builder::ShardMapBuilder;
+pub use cache::CacheShardMap;
+#[cfg(feature = "tokio")]
+pub use expiring::ExpiringShardMap;
+pub use hashbrown::Equivalent;
+pub use rcu::RcuShardMap;
+#[cfg(feature = "serde")]
+pub use serde::TrySerialize;
 pub use shard_map::ShardMap;
 pub use shard_set::ShardSet;
+pub use sync::SyncShardMap;
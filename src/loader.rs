@@ -0,0 +1,98 @@
+//! The registry of in-flight loads behind [`crate::ShardMap::get_or_try_insert_with`], which lets
+//! concurrent callers for the same key share a single loader.
+use std::{
+    any::Any,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use hashbrown::HashTable;
+use tokio::sync::watch;
+
+/// The outcome of a load, published once the loader finishes. Errors are type-erased, as callers
+/// for the same key may in principle use different error types.
+pub(crate) type Outcome = Result<(), Arc<dyn Any + Send + Sync>>;
+
+type Slot = Option<Outcome>;
+
+/// The keys currently being loaded, along with a receiver that resolves when each load finishes.
+pub(crate) struct Loads<K> {
+    table: Mutex<HashTable<(u64, K, watch::Receiver<Slot>)>>,
+}
+
+/// What a caller should do after asking to load a key.
+pub(crate) enum Join<'a, K> {
+    /// No one else is loading the key, so the caller has to.
+    Leader(LoadGuard<'a, K>),
+    /// Someone else is already loading the key.
+    Waiter(watch::Receiver<Slot>),
+}
+
+impl<K> Default for Loads<K> {
+    fn default() -> Self {
+        Self {
+            table: Mutex::new(HashTable::new()),
+        }
+    }
+}
+
+impl<K: Eq + Clone> Loads<K> {
+    fn table(&self) -> MutexGuard<'_, HashTable<(u64, K, watch::Receiver<Slot>)>> {
+        self.table.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Joins the in-flight load for `key`, or registers a new one led by the caller.
+    pub(crate) fn join(&self, hash: u64, key: &K) -> Join<'_, K> {
+        let mut table = self.table();
+
+        if let Some((_, _, rx)) = table.find(hash, |(_, k, _)| k == key) {
+            return Join::Waiter(rx.clone());
+        }
+
+        let (tx, rx) = watch::channel(None);
+        table.insert_unique(hash, (hash, key.clone(), rx.clone()), |(hash, _, _)| *hash);
+
+        Join::Leader(LoadGuard {
+            loads: self,
+            hash,
+            tx,
+            rx,
+        })
+    }
+}
+
+/// Waits for the leader of a load to finish. Returns `None` if the leader gave up without
+/// finishing, for example because its future was dropped.
+pub(crate) async fn wait(mut rx: watch::Receiver<Slot>) -> Option<Outcome> {
+    let slot = rx.wait_for(Option::is_some).await.ok()?;
+    slot.clone()
+}
+
+/// Held by the caller that is running the loader for a key. Dropping the guard unregisters the
+/// load, and wakes up the waiters so that one of them can take over if it did not finish.
+pub(crate) struct LoadGuard<'a, K> {
+    loads: &'a Loads<K>,
+    hash: u64,
+    tx: watch::Sender<Slot>,
+    rx: watch::Receiver<Slot>,
+}
+
+impl<K> LoadGuard<'_, K> {
+    /// Publishes the outcome of the load to every waiter.
+    pub(crate) fn finish(self, outcome: Outcome) {
+        self.tx.send_replace(Some(outcome));
+    }
+}
+
+impl<K> Drop for LoadGuard<'_, K> {
+    fn drop(&mut self) {
+        let mut table = self
+            .loads
+            .table
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if let Ok(entry) = table.find_entry(self.hash, |(_, _, rx)| rx.same_channel(&self.rx)) {
+            entry.remove();
+        }
+    }
+}
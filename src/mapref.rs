@@ -26,7 +26,17 @@
 //!     assert_eq!(mr.value(), &"baz");
 //! });
 
-use crate::shard::{ShardReader, ShardWriter};
+use std::{hash::RandomState, sync::Arc};
+
+use crate::{
+    shard::{OwnedShardReader, OwnedShardWriter, ShardReader, ShardWriter},
+    shard_map::Inner,
+    table::Slot,
+    watch::ChangeTracker,
+};
+
+/// Owned references find their entry again on every access, by its position in the locked shard.
+const IN_PLACE: &str = "entries stay in place while their shard is locked";
 
 /// A reference to a key-value pair in a [`crate::ShardMap`].
 ///
@@ -81,10 +91,17 @@ where
 pub struct MapRefMut<'a, K, V> {
     key: &'a K,
     value: &'a mut V,
+    tracker: ChangeTracker<'a, K, V>,
     #[allow(unused)]
     writer: ShardWriter<'a, K, V>,
 }
 
+impl<K, V> Drop for MapRefMut<'_, K, V> {
+    fn drop(&mut self) {
+        self.tracker.flush(self.key, self.value);
+    }
+}
+
 impl<'a, K, V> std::ops::Deref for MapRefMut<'a, K, V>
 where
     K: Eq + std::hash::Hash,
@@ -101,7 +118,7 @@ where
     K: Eq + std::hash::Hash,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.value
+        self.value_mut()
     }
 }
 
@@ -109,8 +126,18 @@ impl<'a, K, V> MapRefMut<'a, K, V>
 where
     K: Eq + std::hash::Hash,
 {
-    pub(crate) fn new(writer: ShardWriter<'a, K, V>, key: &'a K, value: &'a mut V) -> Self {
-        Self { writer, key, value }
+    pub(crate) fn new(
+        writer: ShardWriter<'a, K, V>,
+        key: &'a K,
+        value: &'a mut V,
+        tracker: ChangeTracker<'a, K, V>,
+    ) -> Self {
+        Self {
+            writer,
+            key,
+            value,
+            tracker,
+        }
     }
 
     /// Returns a reference to the key.
@@ -125,6 +152,7 @@ where
 
     /// Returns a mutable reference to the value.
     pub fn value_mut(&mut self) -> &mut V {
+        self.tracker.mark();
         self.value
     }
 
@@ -135,6 +163,499 @@ where
 
     /// Returns a reference to the key-value pair, with a mutable reference to the value.
     pub fn pair_mut(&mut self) -> (&K, &mut V) {
+        self.tracker.mark();
+        (self.key, self.value)
+    }
+}
+
+/// A reference to a key-value pair yielded while iterating over a [`crate::ShardMap`].
+///
+/// Holds a shared (read-only) lock on the shard being iterated, which is shared with every other
+/// reference yielded from the same shard. The lock is released once all of them are dropped and
+/// the iterator has moved on to the next shard.
+pub struct MapRefMulti<'a, K, V> {
+    key: &'a K,
+    value: &'a V,
+    reader: Arc<ShardReader<'a, K, V>>,
+}
+
+impl<K, V> std::ops::Deref for MapRefMulti<'_, K, V>
+where
+    K: Eq + std::hash::Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<'a, K, V> MapRefMulti<'a, K, V>
+where
+    K: Eq + std::hash::Hash,
+{
+    pub(crate) fn new(reader: Arc<ShardReader<'a, K, V>>, key: &'a K, value: &'a V) -> Self {
+        Self { reader, key, value }
+    }
+
+    pub(crate) fn into_key_ref(self) -> KeyRef<'a, K, V> {
+        KeyRef {
+            key: self.key,
+            reader: self.reader,
+        }
+    }
+
+    pub(crate) fn into_value_ref(self) -> ValueRef<'a, K, V> {
+        ValueRef {
+            value: self.value,
+            reader: self.reader,
+        }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        self.value
+    }
+
+    /// Returns a reference to the key-value pair
+    pub fn pair(&self) -> (&K, &V) {
         (self.key, self.value)
     }
 }
+
+/// A mutable reference to a key-value pair yielded while iterating over a [`crate::ShardMap`].
+///
+/// Holds an exclusive lock on the shard being iterated, which is shared with every other
+/// reference yielded from the same shard. The lock is released once all of them are dropped and
+/// the iterator has moved on to the next shard.
+pub struct MapRefMutMulti<'a, K, V> {
+    key: &'a K,
+    value: &'a mut V,
+    tracker: ChangeTracker<'a, K, V>,
+    #[allow(unused)]
+    writer: Arc<ShardWriter<'a, K, V>>,
+}
+
+impl<K, V> Drop for MapRefMutMulti<'_, K, V> {
+    fn drop(&mut self) {
+        self.tracker.flush(self.key, self.value);
+    }
+}
+
+impl<K, V> std::ops::Deref for MapRefMutMulti<'_, K, V>
+where
+    K: Eq + std::hash::Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<K, V> std::ops::DerefMut for MapRefMutMulti<'_, K, V>
+where
+    K: Eq + std::hash::Hash,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.value_mut()
+    }
+}
+
+impl<'a, K, V> MapRefMutMulti<'a, K, V>
+where
+    K: Eq + std::hash::Hash,
+{
+    pub(crate) fn new(
+        writer: Arc<ShardWriter<'a, K, V>>,
+        key: &'a K,
+        value: &'a mut V,
+        tracker: ChangeTracker<'a, K, V>,
+    ) -> Self {
+        Self {
+            writer,
+            key,
+            value,
+            tracker,
+        }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        self.value
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn value_mut(&mut self) -> &mut V {
+        self.tracker.mark();
+        self.value
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        (self.key, self.value)
+    }
+
+    /// Returns a reference to the key-value pair, with a mutable reference to the value.
+    pub fn pair_mut(&mut self) -> (&K, &mut V) {
+        self.tracker.mark();
+        (self.key, self.value)
+    }
+}
+
+/// A reference to a key yielded by [`crate::ShardMap::keys`].
+///
+/// Holds a shared (read-only) lock on the shard being iterated, like [`MapRefMulti`].
+pub struct KeyRef<'a, K, V> {
+    key: &'a K,
+    #[allow(unused)]
+    reader: Arc<ShardReader<'a, K, V>>,
+}
+
+impl<K, V> std::ops::Deref for KeyRef<'_, K, V> {
+    type Target = K;
+
+    fn deref(&self) -> &Self::Target {
+        self.key
+    }
+}
+
+impl<K, V> KeyRef<'_, K, V> {
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.key
+    }
+}
+
+/// A reference to a value yielded by [`crate::ShardMap::values`].
+///
+/// Holds a shared (read-only) lock on the shard being iterated, like [`MapRefMulti`].
+pub struct ValueRef<'a, K, V> {
+    value: &'a V,
+    #[allow(unused)]
+    reader: Arc<ShardReader<'a, K, V>>,
+}
+
+impl<K, V> std::ops::Deref for ValueRef<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<K, V> ValueRef<'_, K, V> {
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        self.value
+    }
+}
+
+/// An owned reference to a key-value pair in a [`crate::ShardMap`].
+///
+/// Unlike [`MapRef`], this does not borrow the map, so it can be stored or moved into a spawned
+/// task. It keeps the map alive and holds a shared (read-only) lock on the shard associated with
+/// the key. Dropping this reference will release the lock.
+pub struct OwnedMapRef<K, V, S = RandomState> {
+    slot: Slot,
+    reader: OwnedShardReader<K, V>,
+    // Owned references keep the whole map alive, so that it is never taken apart under them.
+    #[allow(unused)]
+    map: Arc<Inner<K, V, S>>,
+}
+
+impl<K, V, S> std::ops::Deref for OwnedMapRef<K, V, S>
+where
+    K: Eq + std::hash::Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V, S> OwnedMapRef<K, V, S>
+where
+    K: Eq + std::hash::Hash,
+{
+    pub(crate) fn new(
+        map: Arc<Inner<K, V, S>>,
+        reader: OwnedShardReader<K, V>,
+        slot: Slot,
+    ) -> Self {
+        Self { slot, reader, map }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.pair().0
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        self.pair().1
+    }
+
+    /// Returns a reference to the key-value pair
+    pub fn pair(&self) -> (&K, &V) {
+        let (key, value) = self.reader.get(self.slot).expect(IN_PLACE);
+        (key, value)
+    }
+}
+
+/// Reports an update to a key, given the id of its shard and its hash.
+type NotifyUpdate<K, V, S> = fn(&Inner<K, V, S>, usize, u64, &K, &V);
+
+/// An owned mutable reference to a key-value pair in a [`crate::ShardMap`].
+///
+/// Unlike [`MapRefMut`], this does not borrow the map, so it can be stored or moved into a
+/// spawned task. It keeps the map alive and holds an exclusive lock on the shard associated with
+/// the key. Dropping this reference will release the lock.
+pub struct OwnedMapRefMut<K, V, S = RandomState> {
+    slot: Slot,
+    shard: usize,
+    hash: u64,
+    dirty: bool,
+    // Guards are dropped without any bounds on `K`, so `Inner::notify_update` is captured up
+    // front.
+    notify: NotifyUpdate<K, V, S>,
+    writer: OwnedShardWriter<K, V>,
+    map: Arc<Inner<K, V, S>>,
+}
+
+impl<K, V, S> Drop for OwnedMapRefMut<K, V, S> {
+    fn drop(&mut self) {
+        if self.dirty {
+            let (key, value) = self.writer.get(self.slot).expect(IN_PLACE);
+            (self.notify)(&self.map, self.shard, self.hash, key, value);
+        }
+    }
+}
+
+impl<K, V, S> std::ops::Deref for OwnedMapRefMut<K, V, S>
+where
+    K: Eq + std::hash::Hash,
+{
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V, S> std::ops::DerefMut for OwnedMapRefMut<K, V, S>
+where
+    K: Eq + std::hash::Hash,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.value_mut()
+    }
+}
+
+impl<K, V, S> OwnedMapRefMut<K, V, S>
+where
+    K: Eq + std::hash::Hash,
+{
+    pub(crate) fn new(
+        map: Arc<Inner<K, V, S>>,
+        writer: OwnedShardWriter<K, V>,
+        slot: Slot,
+        shard: usize,
+        hash: u64,
+    ) -> Self {
+        Self {
+            slot,
+            shard,
+            hash,
+            dirty: false,
+            notify: Inner::notify_update,
+            writer,
+            map,
+        }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        self.pair().0
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        self.pair().1
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn value_mut(&mut self) -> &mut V {
+        self.pair_mut().1
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        let (key, value) = self.writer.get(self.slot).expect(IN_PLACE);
+        (key, value)
+    }
+
+    /// Returns a reference to the key-value pair, with a mutable reference to the value.
+    pub fn pair_mut(&mut self) -> (&K, &mut V) {
+        self.dirty = true;
+        let (key, value) = self.writer.get_mut(self.slot).expect(IN_PLACE);
+        (key, value)
+    }
+}
+
+/// Mutable references to several key-value pairs in a [`crate::ShardMap`], returned by
+/// [`crate::ShardMap::get_many_mut`] and [`crate::ShardMap::get_many_mut_vec`].
+///
+/// Holds an exclusive lock on every shard associated with the keys. Pairs are indexed in the
+/// order their keys were given. Dropping this reference will release the locks.
+pub struct MapRefManyMut<'a, K, V, P = Vec<(&'a K, &'a mut V)>>
+where
+    P: AsMut<[(&'a K, &'a mut V)]>,
+{
+    pairs: P,
+    /// One tracker per pair, in the same order.
+    trackers: Vec<ChangeTracker<'a, K, V>>,
+    #[allow(unused)]
+    writers: Vec<ShardWriter<'a, K, V>>,
+}
+
+impl<'a, K, V, P> Drop for MapRefManyMut<'a, K, V, P>
+where
+    P: AsMut<[(&'a K, &'a mut V)]>,
+{
+    fn drop(&mut self) {
+        for (tracker, (k, v)) in self.trackers.iter_mut().zip(self.pairs.as_mut()) {
+            tracker.flush(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, P> MapRefManyMut<'a, K, V, P>
+where
+    K: Eq + std::hash::Hash,
+    P: AsRef<[(&'a K, &'a mut V)]> + AsMut<[(&'a K, &'a mut V)]>,
+{
+    pub(crate) fn new(
+        writers: Vec<ShardWriter<'a, K, V>>,
+        pairs: P,
+        trackers: Vec<ChangeTracker<'a, K, V>>,
+    ) -> Self {
+        Self {
+            pairs,
+            trackers,
+            writers,
+        }
+    }
+
+    fn mark_all(&mut self) {
+        self.trackers.iter_mut().for_each(ChangeTracker::mark);
+    }
+
+    /// Returns the number of key-value pairs.
+    pub fn len(&self) -> usize {
+        self.pairs.as_ref().len()
+    }
+
+    /// Returns `true` if there are no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.as_ref().is_empty()
+    }
+
+    /// Returns a reference to the key at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn key(&self, index: usize) -> &K {
+        self.pairs.as_ref()[index].0
+    }
+
+    /// Returns a reference to the value at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn value(&self, index: usize) -> &V {
+        self.pairs.as_ref()[index].1
+    }
+
+    /// Returns a mutable reference to the value at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn value_mut(&mut self, index: usize) -> &mut V {
+        let value = &mut *self.pairs.as_mut()[index].1;
+        self.trackers[index].mark();
+        value
+    }
+
+    /// Returns an iterator over the key-value pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + use<'_, 'a, K, V, P> {
+        self.pairs.as_ref().iter().map(|(k, v)| (&**k, &**v))
+    }
+
+    /// Returns an iterator over the key-value pairs, with mutable references to the values.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> + use<'_, 'a, K, V, P> {
+        self.mark_all();
+        self.pairs
+            .as_mut()
+            .iter_mut()
+            .map(|(k, v)| (&**k, &mut **v))
+    }
+}
+
+impl<'a, K, V, const N: usize> MapRefManyMut<'a, K, V, [(&'a K, &'a mut V); N]>
+where
+    K: Eq + std::hash::Hash,
+{
+    /// Returns mutable references to all values, in the order their keys were given.
+    pub fn values_mut(&mut self) -> [&mut V; N] {
+        self.mark_all();
+        self.pairs.each_mut().map(|(_, v)| &mut **v)
+    }
+}
+
+impl<'a, K, V> MapRefManyMut<'a, K, V, Vec<(&'a K, &'a mut V)>>
+where
+    K: Eq + std::hash::Hash,
+{
+    /// Returns mutable references to all values, in the order their keys were given.
+    pub fn values_mut(&mut self) -> Vec<&mut V> {
+        self.mark_all();
+        self.pairs.iter_mut().map(|(_, v)| &mut **v).collect()
+    }
+}
+
+impl<'a, K, V, P> std::ops::Index<usize> for MapRefManyMut<'a, K, V, P>
+where
+    K: Eq + std::hash::Hash,
+    P: AsRef<[(&'a K, &'a mut V)]> + AsMut<[(&'a K, &'a mut V)]>,
+{
+    type Output = V;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.value(index)
+    }
+}
+
+impl<'a, K, V, P> std::ops::IndexMut<usize> for MapRefManyMut<'a, K, V, P>
+where
+    K: Eq + std::hash::Hash,
+    P: AsRef<[(&'a K, &'a mut V)]> + AsMut<[(&'a K, &'a mut V)]>,
+{
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.value_mut(index)
+    }
+}
@@ -0,0 +1,838 @@
+//! Crash recovery for a [`ShardMap`], enabled by the `persistence` feature.
+//!
+//! A persisted map keeps two files in a directory:
+//!
+//! - `snapshot`: every key-value pair of the map at the time of the last checkpoint.
+//! - `wal`: a write-ahead log of every change made since then.
+//!
+//! [`ShardMap::persist`] starts persisting a map, and [`ShardMap::recover`] loads the snapshot
+//! and replays the log on top of it. Changes are appended to the log while the changed shard is
+//! still locked, so the log always holds them in the order they were made, including changes made
+//! through [`crate::mapref::MapRefMut`] and the other mutable references. They are only encoded
+//! into a buffer at that point: a dedicated thread writes the buffer to the file, and flushes it
+//! to disk as controlled by [`FsyncPolicy`]. [`Persister::sync`] waits for the changes made
+//! before it to be written and flushed, and dropping the [`Persister`] waits for every change to
+//! be written.
+//!
+//! [`Persister::checkpoint`] compacts the log by writing a new snapshot and emptying the log.
+//!
+//! # Format
+//!
+//! Both files start with the magic bytes `WHRL`, a little-endian `u16` format version and a byte
+//! identifying the kind of file, then a little-endian `u64` generation. The snapshot then holds the
+//! number of entries as a little-endian `u64`. The rest of each file is a sequence of records, each made of the length and CRC-32 of
+//! its payload as little-endian `u32`s, then the payload: a tag byte followed by the key and, for
+//! insertions, the value, each encoded with [`postcard`].
+//!
+//! A record that was only partially written when the process crashed ends the log, and is
+//! ignored during recovery. So is the whole log if its generation differs from the snapshot's:
+//! each snapshot gets a new generation, and is written before the log is emptied and stamped with
+//! it, so this means the process crashed in between, and the snapshot already holds every change
+//! of the log.
+//!
+//! # Example
+//! ```
+//! use whirlwind::{persistence::FsyncPolicy, ShardMap};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> std::io::Result<()> {
+//! let dir = tempfile::tempdir()?;
+//!
+//! let map: ShardMap<String, u64> = ShardMap::recover(dir.path())?;
+//! let persister = map.persist(dir.path(), FsyncPolicy::Always).await?;
+//! map.insert("visits".to_string(), 1).await;
+//! *map.get_mut("visits").await.unwrap() += 1;
+//! drop(persister);
+//!
+//! let recovered: ShardMap<String, u64> = ShardMap::recover(dir.path())?;
+//! assert_eq!(recovered.get("visits").await.unwrap().value(), &2);
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    fs::{self, File, OpenOptions},
+    future::Future,
+    hash::{BuildHasher, Hash, RandomState},
+    io::{self, BufReader, Read, Seek, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{snapshot::Snapshot, watch::Journal, ShardMap};
+
+const MAGIC: [u8; 4] = *b"WHRL";
+const VERSION: u16 = 2;
+
+const SNAPSHOT_KIND: u8 = 0;
+const LOG_KIND: u8 = 1;
+
+const INSERT_TAG: u8 = 0;
+const REMOVE_TAG: u8 = 1;
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+const LOG_FILE: &str = "wal";
+
+/// How often the write-ahead log is flushed to disk with `fsync`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Flush after every batch of changes written to the log. Changes survive a power loss as
+    /// soon as they are written, shortly after they are made, at the cost of a disk flush per
+    /// batch.
+    #[default]
+    Always,
+    /// Flush when changes are written at least this long after the previous flush. Changes survive
+    /// a crash of the process as soon as they are written, but the most recent ones may be lost on
+    /// power loss until the next flush or [`Persister::sync`].
+    Interval(Duration),
+    /// Never flush explicitly, and leave it to the operating system.
+    Never,
+}
+
+fn invalid_data(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn write_header(writer: &mut impl Write, kind: u8) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&[kind])
+}
+
+fn read_header(reader: &mut impl Read, kind: u8) -> io::Result<()> {
+    let mut header = [0; 7];
+    reader.read_exact(&mut header)?;
+
+    if header[..4] != MAGIC {
+        return Err(invalid_data("not a whirlwind persistence file"));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(invalid_data(format!(
+            "unsupported persistence format version {version}"
+        )));
+    }
+    if header[6] != kind {
+        return Err(invalid_data("unexpected kind of persistence file"));
+    }
+
+    Ok(())
+}
+
+/// Appends a record for the new value of `key`, or its removal if `value` is `None`, to `buf`.
+fn encode_record<K, V>(buf: &mut Vec<u8>, key: &K, value: Option<&V>) -> io::Result<()>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 8]);
+
+    buf.push(match value {
+        Some(_) => INSERT_TAG,
+        None => REMOVE_TAG,
+    });
+    let mut payload = postcard::to_extend(key, std::mem::take(buf)).map_err(invalid_data)?;
+    if let Some(value) = value {
+        payload = postcard::to_extend(value, payload).map_err(invalid_data)?;
+    }
+    *buf = payload;
+
+    let len = u32::try_from(buf.len() - start - 8)
+        .map_err(|_| invalid_data("record is larger than 4 GiB"))?;
+    let crc = crc32fast::hash(&buf[start + 8..]);
+    buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+    buf[start + 4..start + 8].copy_from_slice(&crc.to_le_bytes());
+
+    Ok(())
+}
+
+/// Reads the payload of the next record. Returns `Ok(None)` at the end of the file, and an error
+/// of kind [`io::ErrorKind::UnexpectedEof`] or [`io::ErrorKind::InvalidData`] if the record is
+/// incomplete or corrupt.
+fn read_record(reader: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<Option<()>> {
+    let mut header = [0; 8];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => filled += n,
+        }
+    }
+
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+    buf.clear();
+    reader.take(len as u64).read_to_end(buf)?;
+    if buf.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if crc32fast::hash(buf) != crc {
+        return Err(invalid_data("record checksum mismatch"));
+    }
+
+    Ok(Some(()))
+}
+
+/// Decodes the payload of a record into a key and its new value, or `None` if it was removed.
+fn decode_record<K, V>(payload: &[u8]) -> io::Result<(K, Option<V>)>
+where
+    K: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    let (&tag, rest) = payload
+        .split_first()
+        .ok_or_else(|| invalid_data("empty record"))?;
+    let (key, rest) = postcard::take_from_bytes(rest).map_err(invalid_data)?;
+
+    match tag {
+        INSERT_TAG => Ok((key, Some(postcard::from_bytes(rest).map_err(invalid_data)?))),
+        REMOVE_TAG => Ok((key, None)),
+        _ => Err(invalid_data(format!("unknown record tag {tag}"))),
+    }
+}
+
+/// Encodes `snapshot` into the contents of a snapshot file of the given generation.
+fn encode_snapshot<K, V, S>(
+    snapshot: &Snapshot<'_, K, V, S>,
+    generation: u64,
+) -> io::Result<Vec<u8>>
+where
+    K: Eq + Hash + Serialize + 'static,
+    V: Serialize + 'static,
+    S: BuildHasher,
+{
+    let mut buf = Vec::new();
+    write_header(&mut buf, SNAPSHOT_KIND)?;
+    buf.extend_from_slice(&generation.to_le_bytes());
+    buf.extend_from_slice(&(snapshot.len() as u64).to_le_bytes());
+    for (key, value) in snapshot.iter() {
+        encode_record(&mut buf, key, Some(value))?;
+    }
+    Ok(buf)
+}
+
+/// Writes `contents` to the snapshot file in `dir`, replacing it atomically.
+fn write_snapshot(dir: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = dir.join(SNAPSHOT_TMP_FILE);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, dir.join(SNAPSHOT_FILE))?;
+    sync_dir(dir)
+}
+
+/// Makes a rename in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Reads the generation of the persistence file at `path`, or returns 0 if it is missing or
+/// unreadable.
+fn read_generation(path: &Path, kind: u8) -> u64 {
+    let read = || -> io::Result<u64> {
+        let mut file = File::open(path)?;
+        read_header(&mut file, kind)?;
+        let mut generation = [0; 8];
+        file.read_exact(&mut generation)?;
+        Ok(u64::from_le_bytes(generation))
+    };
+    read().unwrap_or(0)
+}
+
+/// Copies an error that is kept to be reported again later.
+fn copy_error(err: &io::Error) -> io::Error {
+    io::Error::new(err.kind(), err.to_string())
+}
+
+/// Runs blocking file IO on the blocking thread pool of the current tokio runtime. The IO starts
+/// right away, and runs to completion even if the returned future is dropped.
+fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> impl Future<Output = T> {
+    let handle = tokio::task::spawn_blocking(f);
+    async move {
+        match handle.await {
+            Ok(value) => value,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+}
+
+/// The part of the write-ahead log that changes as records are appended and written. Offsets
+/// count every byte ever appended to the log.
+struct LogState {
+    /// The records that were appended but not handed to the writer thread yet.
+    pending: Vec<u8>,
+    /// The offset of the end of `pending`.
+    appended: u64,
+    /// The offset of the start of `pending`.
+    taken: u64,
+    /// The offset up to which records were written, or dropped because a snapshot covers them.
+    done: u64,
+    /// Whether the writer thread is writing records it took from `pending`.
+    writing: bool,
+    /// Whether the writer thread holds off while a checkpoint writes its snapshot, and the offset
+    /// the snapshot was taken at.
+    paused: Option<u64>,
+    /// Whether a checkpoint is writing its snapshot, after which it resumes the log. The writer
+    /// thread keeps running until then, even once the log is closed.
+    checkpointing: bool,
+    /// The generation of the snapshot that the log file applies to.
+    generation: u64,
+    /// Whether the log file was emptied and stamped with `generation`. Until then, it still holds
+    /// the log of whatever was persisted in the directory before, which must not be written to.
+    stamped: bool,
+    closed: bool,
+    /// The first error hit while appending to the log, with the offset it was hit at. No more
+    /// changes are written after an error, as they could not be replayed past a missing record
+    /// anyway.
+    error: Option<(io::Error, u64)>,
+}
+
+struct LogShared {
+    state: Mutex<LogState>,
+    /// Notified whenever `state` changes.
+    changed: Condvar,
+    file: Mutex<File>,
+    policy: FsyncPolicy,
+}
+
+impl LogShared {
+    fn state(&self) -> MutexGuard<'_, LogState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, LogState>) -> MutexGuard<'a, LogState> {
+        self.changed
+            .wait(state)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn file(&self) -> MutexGuard<'_, File> {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Writes the pending records to the file until the log is closed. This runs on a dedicated
+    /// thread, so that no file IO happens while a shard is locked.
+    fn run_writer(&self) {
+        let mut last_sync = Instant::now();
+        let mut state = self.state();
+        loop {
+            if state.paused.is_some() || state.error.is_some() || state.pending.is_empty() {
+                if state.closed && !state.checkpointing {
+                    return;
+                }
+                state = self.wait(state);
+                continue;
+            }
+
+            let batch = std::mem::take(&mut state.pending);
+            state.taken += batch.len() as u64;
+            state.writing = true;
+            drop(state);
+
+            let result = self.write_batch(&batch, &mut last_sync);
+
+            state = self.state();
+            state.writing = false;
+            state.done += batch.len() as u64;
+            if let Err(err) = result {
+                let at = state.done;
+                state.error.get_or_insert((err, at));
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    fn write_batch(&self, batch: &[u8], last_sync: &mut Instant) -> io::Result<()> {
+        let mut file = self.file();
+        file.write_all(batch)?;
+        match self.policy {
+            FsyncPolicy::Always => file.sync_data(),
+            FsyncPolicy::Interval(interval) if last_sync.elapsed() >= interval => {
+                file.sync_data()?;
+                *last_sync = Instant::now();
+                Ok(())
+            }
+            FsyncPolicy::Interval(_) | FsyncPolicy::Never => Ok(()),
+        }
+    }
+
+    /// Holds off writing the log while a checkpoint writes a snapshot, and returns the
+    /// generation of that snapshot. This must be called while the snapshot is held, so that no
+    /// change is made in between.
+    fn pause(&self) -> u64 {
+        let mut state = self.state();
+        state.paused = Some(state.appended);
+        state.checkpointing = true;
+        state.generation + 1
+    }
+
+    /// Finishes a checkpoint started with [`LogShared::pause`], once writing its snapshot ended
+    /// with `snapshot`, and resumes writing the log.
+    ///
+    /// If the snapshot was written, the log file is emptied and stamped with its generation, and
+    /// the pending records that it covers are dropped. Otherwise, the log carries on as if the
+    /// checkpoint never happened, unless it was never stamped, in which case the error is kept
+    /// so that the log file left by an earlier run is not written to.
+    fn finish_checkpoint(&self, generation: u64, snapshot: io::Result<()>) -> io::Result<()> {
+        let reset = snapshot.as_ref().ok().map(|()| {
+            let mut state = self.state();
+            while state.writing {
+                state = self.wait(state);
+            }
+            // Changes keep being appended meanwhile, but are not written until the log resumes.
+            drop(state);
+            self.reset(generation)
+        });
+
+        let mut state = self.state();
+        let covered = state
+            .paused
+            .take()
+            .expect("the log is paused during a checkpoint");
+        state.checkpointing = false;
+        match &reset {
+            Some(Ok(())) => {
+                let dropped = (covered - state.taken) as usize;
+                state.pending.drain(..dropped);
+                state.taken = covered;
+                state.done = state.done.max(covered);
+                state.generation = generation;
+                state.stamped = true;
+                if state.error.as_ref().is_some_and(|&(_, at)| at <= covered) {
+                    state.error = None;
+                }
+            }
+            // The new snapshot is in place, but the log could not be stamped with its
+            // generation, so the changes written from now on would never be replayed.
+            Some(Err(err)) => {
+                state.error.get_or_insert((copy_error(err), covered));
+            }
+            None if !state.stamped => {
+                if let Err(err) = &snapshot {
+                    state.error.get_or_insert((copy_error(err), covered));
+                }
+            }
+            None => {}
+        }
+        self.changed.notify_all();
+        drop(state);
+
+        snapshot.and(reset.unwrap_or(Ok(())))
+    }
+
+    /// Empties the log file and stamps it with `generation`.
+    fn reset(&self, generation: u64) -> io::Result<()> {
+        let mut file = self.file();
+        file.set_len(0)?;
+        file.rewind()?;
+        write_header(&mut *file, LOG_KIND)?;
+        file.write_all(&generation.to_le_bytes())?;
+        file.sync_all()
+    }
+
+    /// Records an error that stops the log from being written until the next checkpoint.
+    fn fail(&self, err: io::Error) {
+        let mut state = self.state();
+        let at = state.appended;
+        state.error.get_or_insert((err, at));
+        self.changed.notify_all();
+    }
+
+    /// Waits for every record appended so far to be written, and flushes them to disk.
+    fn sync(&self) -> io::Result<()> {
+        let mut state = self.state();
+        let target = state.appended;
+        while state.done < target && state.error.is_none() {
+            state = self.wait(state);
+        }
+        if let Some((err, _)) = &state.error {
+            return Err(copy_error(err));
+        }
+        drop(state);
+
+        self.file().sync_data()
+    }
+}
+
+/// The write-ahead log of a map, which records every change to it.
+///
+/// Changes are encoded into a buffer while their shard is locked, which keeps them in order, and
+/// a dedicated thread writes the buffer to the file. The thread is stopped once every change is
+/// written when the log is dropped, which waits for a running checkpoint to write its snapshot
+/// first.
+struct Log<K, V> {
+    shared: Arc<LogShared>,
+    writer: Option<thread::JoinHandle<()>>,
+    _marker: PhantomData<fn(&K, &V)>,
+}
+
+impl<K, V> Log<K, V> {
+    /// Opens the log file at `path` without changing it, and starts its writer thread. Nothing is
+    /// written until the first checkpoint stamps the log with the generation of its snapshot.
+    fn open(path: &Path, policy: FsyncPolicy, generation: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let shared = Arc::new(LogShared {
+            state: Mutex::new(LogState {
+                pending: Vec::new(),
+                appended: 0,
+                taken: 0,
+                done: 0,
+                writing: false,
+                paused: Some(0),
+                checkpointing: false,
+                generation,
+                stamped: false,
+                closed: false,
+                error: None,
+            }),
+            changed: Condvar::new(),
+            file: Mutex::new(file),
+            policy,
+        });
+        let writer = thread::Builder::new()
+            .name("whirlwind-wal".to_string())
+            .spawn({
+                let shared = shared.clone();
+                move || shared.run_writer()
+            })?;
+
+        Ok(Self {
+            shared,
+            writer: Some(writer),
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<K, V> Drop for Log<K, V> {
+    fn drop(&mut self) {
+        self.shared.state().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl<K, V> Journal<K, V> for Log<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    fn record(&self, key: &K, value: Option<&V>) {
+        let mut state = self.shared.state();
+        // Changes made during a checkpoint go to the new log even if the old one failed.
+        if state.error.is_some() && state.paused.is_none() {
+            return;
+        }
+
+        let start = state.pending.len();
+        match encode_record(&mut state.pending, key, value) {
+            Ok(()) => {
+                state.appended += (state.pending.len() - start) as u64;
+                self.shared.changed.notify_all();
+            }
+            Err(err) => {
+                state.pending.truncate(start);
+                let at = state.appended;
+                state.error.get_or_insert((err, at));
+            }
+        }
+    }
+}
+
+impl<K, V, S> ShardMap<K, V, S>
+where
+    K: Eq + Hash + DeserializeOwned + 'static,
+    V: DeserializeOwned + 'static,
+    S: BuildHasher + Default,
+{
+    /// Recovers a map persisted in `dir` with [`ShardMap::persist`], by loading its snapshot and
+    /// replaying its write-ahead log. Returns an empty map if nothing was persisted in `dir` yet.
+    ///
+    /// A record that was only partially written to the end of the log, because the process
+    /// crashed while writing it, is ignored. Any other corruption is reported as an error of kind
+    /// [`io::ErrorKind::InvalidData`].
+    ///
+    /// This performs blocking file IO.
+    pub fn recover(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let map = Self::with_hasher(S::default());
+        let mut writers = map.write_unshared();
+        let mut buf = Vec::new();
+        let mut generation = 0;
+
+        match File::open(dir.join(SNAPSHOT_FILE)) {
+            Ok(file) => {
+                let mut reader = BufReader::new(file);
+                read_header(&mut reader, SNAPSHOT_KIND)?;
+
+                let mut word = [0; 8];
+                reader.read_exact(&mut word)?;
+                generation = u64::from_le_bytes(word);
+                reader.read_exact(&mut word)?;
+                for _ in 0..u64::from_le_bytes(word) {
+                    read_record(&mut reader, &mut buf)?
+                        .ok_or_else(|| invalid_data("snapshot is missing entries"))?;
+                    let (key, value) = decode_record::<K, V>(&buf)?;
+                    let value = value.ok_or_else(|| invalid_data("removal in snapshot"))?;
+
+                    let (idx, hash) = map.locate(&key);
+                    map.insert_locked(map.shard_at(idx), &mut writers[idx], hash, key, value);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        match File::open(dir.join(LOG_FILE)) {
+            Ok(file) => 'log: {
+                let mut reader = BufReader::new(file);
+                let mut word = [0; 8];
+                match read_header(&mut reader, LOG_KIND).and_then(|()| reader.read_exact(&mut word))
+                {
+                    Ok(()) => {}
+                    // The log was being emptied when the process crashed.
+                    Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break 'log,
+                    Err(err) => return Err(err),
+                }
+                // The process crashed after writing a new snapshot, but before emptying the log
+                // of the previous one.
+                if u64::from_le_bytes(word) != generation {
+                    break 'log;
+                }
+
+                loop {
+                    match read_record(&mut reader, &mut buf) {
+                        Ok(Some(())) => {}
+                        Ok(None) => break,
+                        // A torn write at the end of the log.
+                        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                        Err(err) => return Err(err),
+                    }
+
+                    let (key, value) = decode_record::<K, V>(&buf)?;
+                    let (idx, hash) = map.locate(&key);
+                    match value {
+                        Some(value) => {
+                            map.insert_locked(
+                                map.shard_at(idx),
+                                &mut writers[idx],
+                                hash,
+                                key,
+                                value,
+                            );
+                        }
+                        None => {
+                            map.remove_locked(map.shard_at(idx), &mut writers[idx], hash, &key);
+                        }
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        drop(writers);
+        Ok(map)
+    }
+}
+
+impl<K, V, S> ShardMap<K, V, S>
+where
+    K: Eq + Hash + Serialize + Send + Sync + 'static,
+    V: Serialize + Send + Sync + 'static,
+    S: BuildHasher + Send + Sync + 'static,
+{
+    /// Starts persisting the map to `dir`, creating it if needed.
+    ///
+    /// This writes a snapshot of the whole map and starts a new write-ahead log, to which every
+    /// change made to the map is appended until the returned [`Persister`] is dropped. Anything
+    /// previously persisted in `dir` is replaced, so recover it with [`ShardMap::recover`] first.
+    ///
+    /// Writes to the map wait while the snapshot is encoded in memory, which is then written to
+    /// disk on the blocking thread pool of the current tokio runtime. Returns an error of kind
+    /// [`io::ErrorKind::AlreadyExists`] if the map is already being persisted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub async fn persist(
+        &self,
+        dir: impl AsRef<Path>,
+        policy: FsyncPolicy,
+    ) -> io::Result<Persister<K, V, S>> {
+        let dir = dir.as_ref().to_path_buf();
+        let log = blocking({
+            let dir = dir.clone();
+            move || {
+                fs::create_dir_all(&dir)?;
+                // A new generation keeps the log left in `dir` from being replayed on top of the
+                // new snapshot, if the process crashes before it is emptied.
+                let generation = read_generation(&dir.join(SNAPSHOT_FILE), SNAPSHOT_KIND)
+                    .max(read_generation(&dir.join(LOG_FILE), LOG_KIND));
+                Log::open(&dir.join(LOG_FILE), policy, generation)
+            }
+        })
+        .await?;
+        let log = Arc::new(log);
+
+        // No changes can be made while the snapshot is held, so the log starts exactly where the
+        // snapshot ends.
+        let snapshot = self.snapshot().await;
+        if !self.watchers().attach_journal(log.clone()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the map is already being persisted",
+            ));
+        }
+        let generation = log.shared.pause();
+        let contents = encode_snapshot(&snapshot, generation);
+        drop(snapshot);
+
+        // Dropping the persister detaches the log again, if the snapshot cannot be written or
+        // this future is dropped.
+        let shared = log.shared.clone();
+        let persister = Persister {
+            shared: Arc::new(Shared {
+                map: self.clone(),
+                dir: dir.clone(),
+                log,
+                checkpoint: Arc::default(),
+            }),
+        };
+        blocking(move || {
+            let snapshot = contents.and_then(|contents| write_snapshot(&dir, &contents));
+            shared.finish_checkpoint(generation, snapshot)
+        })
+        .await?;
+
+        Ok(persister)
+    }
+}
+
+struct Shared<K, V, S> {
+    map: ShardMap<K, V, S>,
+    dir: PathBuf,
+    log: Arc<Log<K, V>>,
+    /// Held by the running checkpoint, until its snapshot is written.
+    checkpoint: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<K, V, S> Drop for Shared<K, V, S> {
+    fn drop(&mut self) {
+        self.map.watchers().detach_journal();
+    }
+}
+
+/// Persists a [`ShardMap`] to disk, created by [`ShardMap::persist`].
+///
+/// The map stops being persisted once the persister is dropped.
+pub struct Persister<K, V, S = RandomState> {
+    shared: Arc<Shared<K, V, S>>,
+}
+
+impl<K, V, S> Persister<K, V, S>
+where
+    K: Eq + Hash + Serialize + Send + Sync + 'static,
+    V: Serialize + Send + Sync + 'static,
+    S: BuildHasher + Send + Sync + 'static,
+{
+    /// Compacts the write-ahead log, by writing a new snapshot of the map and emptying the log.
+    ///
+    /// Writes to the map wait while the snapshot is encoded in memory, which is then written to
+    /// disk on the blocking thread pool of the current tokio runtime. Changes made meanwhile are
+    /// held in memory until the log is emptied. A checkpoint also recovers from an earlier error
+    /// writing to the log, since the new snapshot includes every change the log missed.
+    ///
+    /// The checkpoint completes even if the returned future is dropped once the snapshot is
+    /// taken.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub async fn checkpoint(&self) -> io::Result<()> {
+        let Shared {
+            map,
+            dir,
+            log,
+            checkpoint,
+        } = &*self.shared;
+        let running = checkpoint.clone().lock_owned().await;
+
+        // Changes are only appended to the log while their shard is locked for writing, so none
+        // are appended while the snapshot is held. The new snapshot gets a new generation, so
+        // that if the process crashes before the log is emptied, the log is not replayed on top
+        // of it.
+        let snapshot = map.snapshot().await;
+        let generation = log.shared.pause();
+        let contents = encode_snapshot(&snapshot, generation);
+        drop(snapshot);
+
+        let shared = log.shared.clone();
+        let dir = dir.clone();
+        blocking(move || {
+            let snapshot = contents.and_then(|contents| write_snapshot(&dir, &contents));
+            let result = shared.finish_checkpoint(generation, snapshot);
+            drop(running);
+            result
+        })
+        .await
+    }
+
+    /// Waits for every change made so far to be written to the write-ahead log, and flushes the
+    /// log to disk, blocking the current thread. Returns the error that stopped changes from
+    /// being appended to the log, if there was one, in which case [`Persister::checkpoint`]
+    /// should be called to persist the map again.
+    pub fn sync(&self) -> io::Result<()> {
+        self.shared.log.shared.sync()
+    }
+
+    /// Spawns a task on the current tokio runtime that calls [`Persister::checkpoint`] every
+    /// `period`.
+    ///
+    /// The task only holds a weak reference to the persister, and exits on its own once it has
+    /// been dropped. Errors are reported by the next call to [`Persister::sync`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime, or if `period` is zero.
+    pub fn spawn_checkpoints(&self, period: Duration) -> JoinHandle<()> {
+        let shared = Arc::downgrade(&self.shared);
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+
+                let Some(shared) = Weak::upgrade(&shared) else {
+                    break;
+                };
+                let persister = Persister { shared };
+                if let Err(err) = persister.checkpoint().await {
+                    persister.shared.log.shared.fail(err);
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,264 @@
+//! Per-shard bookkeeping for [`crate::CacheShardMap`]: recency ordering and the frequency sketch
+//! used for TinyLFU admission.
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::cache::EvictionPolicy;
+
+/// Which recency queue an entry is in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Segment {
+    /// Recently inserted entries that have not been admitted to the main space yet.
+    Window,
+    /// Entries that have been admitted.
+    Main,
+}
+
+/// A cached value along with the bookkeeping needed to evict it.
+pub(crate) struct CacheEntry<V> {
+    pub(crate) value: V,
+    pub(crate) weight: u64,
+    /// Position in the entry's recency queue. This is updated while holding a read lock on the
+    /// shard, but only ever with the shard's policy locked as well.
+    stamp: AtomicU64,
+    segment: Segment,
+}
+
+impl<V> CacheEntry<V> {
+    pub(crate) fn new(value: V, weight: u64) -> Self {
+        Self {
+            value,
+            weight,
+            stamp: AtomicU64::new(0),
+            segment: Segment::Main,
+        }
+    }
+
+    pub(crate) fn stamp(&self) -> u64 {
+        self.stamp.load(Ordering::Relaxed)
+    }
+}
+
+/// A count-min sketch of 4-bit-ish access frequencies, halved periodically so that old
+/// popularity fades.
+pub(crate) struct FrequencySketch {
+    counters: Box<[u8]>,
+    mask: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+const SEEDS: [u64; 4] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0x85eb_ca77_c2b2_ae63,
+];
+const MAX_FREQUENCY: u8 = 15;
+
+impl FrequencySketch {
+    fn new(capacity: u64) -> Self {
+        let width = (capacity.clamp(16, 1 << 20) as usize).next_power_of_two();
+
+        Self {
+            counters: vec![0; width * SEEDS.len()].into_boxed_slice(),
+            mask: width - 1,
+            additions: 0,
+            sample_size: width * 10,
+        }
+    }
+
+    fn indexes(&self, hash: u64) -> impl Iterator<Item = usize> + '_ {
+        let width = self.mask + 1;
+        SEEDS.iter().enumerate().map(move |(row, seed)| {
+            let mixed = (hash ^ seed).wrapping_mul(SEEDS[0]);
+            row * width + ((mixed >> 32) as usize & self.mask)
+        })
+    }
+
+    pub(crate) fn frequency(&self, hash: u64) -> u8 {
+        self.indexes(hash)
+            .map(|idx| self.counters[idx])
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn increment(&mut self, hash: u64) {
+        let indexes: [usize; SEEDS.len()] = {
+            let mut iter = self.indexes(hash);
+            std::array::from_fn(|_| iter.next().unwrap_or(0))
+        };
+
+        let mut incremented = false;
+        for idx in indexes {
+            if self.counters[idx] < MAX_FREQUENCY {
+                self.counters[idx] += 1;
+                incremented = true;
+            }
+        }
+
+        if incremented {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.reset();
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for counter in self.counters.iter_mut() {
+            *counter /= 2;
+        }
+        self.additions /= 2;
+    }
+}
+
+/// The eviction state of a single shard.
+pub(crate) struct ShardPolicy {
+    pub(crate) capacity: u64,
+    window_capacity: u64,
+    window_weight: u64,
+    main_weight: u64,
+    /// Stamp to hash, oldest first.
+    window: BTreeMap<u64, u64>,
+    main: BTreeMap<u64, u64>,
+    next_stamp: u64,
+    sketch: Option<FrequencySketch>,
+}
+
+impl ShardPolicy {
+    pub(crate) fn new(policy: EvictionPolicy, capacity: u64) -> Self {
+        let (window_capacity, sketch) = match policy {
+            EvictionPolicy::Lru => (0, None),
+            // 1% of the space goes to the admission window, as in W-TinyLFU. Shards too small
+            // for that have no window, so new entries compete for admission straight away.
+            EvictionPolicy::TinyLfu => (capacity / 100, Some(FrequencySketch::new(capacity))),
+        };
+
+        Self {
+            capacity,
+            window_capacity,
+            window_weight: 0,
+            main_weight: 0,
+            window: BTreeMap::new(),
+            main: BTreeMap::new(),
+            next_stamp: 0,
+            sketch,
+        }
+    }
+
+    pub(crate) fn weight(&self) -> u64 {
+        self.window_weight + self.main_weight
+    }
+
+    pub(crate) fn is_over_capacity(&self) -> bool {
+        self.weight() > self.capacity
+    }
+
+    pub(crate) fn is_window_over_capacity(&self) -> bool {
+        self.sketch.is_some() && self.window_weight > self.window_capacity
+    }
+
+    /// Records an access to a key for the purposes of admission, whether it was present or not.
+    pub(crate) fn record(&mut self, hash: u64) {
+        if let Some(sketch) = &mut self.sketch {
+            sketch.increment(hash);
+        }
+    }
+
+    pub(crate) fn frequency(&self, hash: u64) -> u8 {
+        self.sketch
+            .as_ref()
+            .map_or(0, |sketch| sketch.frequency(hash))
+    }
+
+    fn queue(&mut self, segment: Segment) -> (&mut BTreeMap<u64, u64>, &mut u64) {
+        match segment {
+            Segment::Window => (&mut self.window, &mut self.window_weight),
+            Segment::Main => (&mut self.main, &mut self.main_weight),
+        }
+    }
+
+    /// Starts tracking a newly inserted entry, as the most recently used entry of its queue.
+    pub(crate) fn add<V>(&mut self, hash: u64, entry: &mut CacheEntry<V>) {
+        entry.segment = match self.sketch {
+            Some(_) => Segment::Window,
+            None => Segment::Main,
+        };
+        self.push(hash, entry);
+    }
+
+    fn push<V>(&mut self, hash: u64, entry: &CacheEntry<V>) {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+
+        let (queue, weight) = self.queue(entry.segment);
+        queue.insert(stamp, hash);
+        *weight += entry.weight;
+        entry.stamp.store(stamp, Ordering::Relaxed);
+    }
+
+    /// Stops tracking an entry that is being removed from the shard.
+    pub(crate) fn remove<V>(&mut self, entry: &CacheEntry<V>) {
+        let (queue, weight) = self.queue(entry.segment);
+        queue.remove(&entry.stamp());
+        *weight -= entry.weight;
+    }
+
+    /// Marks an entry as the most recently used entry of its queue.
+    pub(crate) fn touch<V>(&mut self, hash: u64, entry: &CacheEntry<V>) {
+        self.remove(entry);
+        self.push(hash, entry);
+    }
+
+    /// Replaces the value and weight of an entry, marking it as the most recently used entry of its
+    /// queue. Returns the old value.
+    pub(crate) fn replace<V>(
+        &mut self,
+        hash: u64,
+        entry: &mut CacheEntry<V>,
+        value: V,
+        weight: u64,
+    ) -> V {
+        self.remove(entry);
+        entry.weight = weight;
+        let old = std::mem::replace(&mut entry.value, value);
+        self.push(hash, entry);
+        old
+    }
+
+    /// Moves an entry from the window to the main space.
+    pub(crate) fn promote<V>(&mut self, hash: u64, entry: &mut CacheEntry<V>) {
+        self.remove(entry);
+        entry.segment = Segment::Main;
+        self.push(hash, entry);
+    }
+
+    /// Returns the stamp and hash of the least recently used entry in the window.
+    pub(crate) fn window_candidate(&self) -> Option<(u64, u64)> {
+        self.window.first_key_value().map(|(&s, &h)| (s, h))
+    }
+
+    /// Returns the stamp and hash of the least recently used entry in the main space, or in the
+    /// window if the main space is empty.
+    pub(crate) fn victim(&self) -> Option<(u64, u64)> {
+        self.main
+            .first_key_value()
+            .or_else(|| self.window.first_key_value())
+            .map(|(&s, &h)| (s, h))
+    }
+
+    /// Returns the stamp and hash of the least recently used entry in the main space.
+    pub(crate) fn main_victim(&self) -> Option<(u64, u64)> {
+        self.main.first_key_value().map(|(&s, &h)| (s, h))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.window.clear();
+        self.main.clear();
+        self.window_weight = 0;
+        self.main_weight = 0;
+    }
+}
@@ -0,0 +1,542 @@
+//! A read-optimized variant of [`crate::ShardMap`], whose readers never block.
+//!
+//! [`RcuShardMap`] uses the same sharding strategy as [`crate::ShardMap`], but each shard is an
+//! immutable table published through an [`ArcSwap`] instead of a table behind a lock. Readers load
+//! the current table without taking any lock, so they never wait for writers or for each other.
+//! Writers copy the table, change the copy and publish it (read-copy-update). Entries are shared
+//! between copies, so a copy costs one reference count increment per entry in the shard rather
+//! than a clone of every key and value, but writes are still much more expensive than with
+//! [`crate::ShardMap`]. Use [`RcuShardMap::extend`] to publish many changes to a shard at once.
+//!
+//! Lookups return a [`ReadRef`], which keeps the entry alive without holding any lock, so readers
+//! holding one never get in the way of writers. A `ReadRef` always shows the entry as it was when
+//! it was looked up, even if the key has been updated or removed since.
+//!
+//! # Example
+//! ```
+//! use whirlwind::RcuShardMap;
+//!
+//! let map = RcuShardMap::new();
+//! map.insert("foo", 1);
+//!
+//! let old = map.get(&"foo").unwrap();
+//! map.insert("foo", 2);
+//!
+//! // The old entry stays readable, and new lookups see the update.
+//! assert_eq!(old.value(), &1);
+//! assert_eq!(map.get(&"foo").unwrap().value(), &2);
+//! ```
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_utils::CachePadded;
+use hashbrown::{hash_table::Entry, Equivalent, HashTable};
+use parking_lot::Mutex;
+
+use crate::{
+    error::BuildError,
+    shard_map::{shard_capacity, shard_count, shard_for_hash, shard_shift},
+    ShardMapBuilder,
+};
+
+type Table<K, V> = HashTable<Arc<(K, V)>>;
+type Batch<K, V> = Vec<(u64, Arc<(K, V)>)>;
+
+struct Shard<K, V> {
+    table: ArcSwap<Table<K, V>>,
+    /// Held by writers while they copy, update and publish the table, so that concurrent writes
+    /// to the shard are not lost. Readers never take it.
+    writer: Mutex<()>,
+}
+
+struct Inner<K, V, S> {
+    shards: Box<[CachePadded<Shard<K, V>>]>,
+    hasher: S,
+    shift: usize,
+}
+
+/// A reference to a key-value pair in a [`RcuShardMap`].
+///
+/// Unlike [`crate::mapref::MapRef`], this does not hold a lock, and shows the pair as it was when
+/// it was looked up. It can be cloned cheaply and outlive the map.
+pub struct ReadRef<K, V> {
+    pair: Arc<(K, V)>,
+}
+
+impl<K, V> Clone for ReadRef<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            pair: Arc::clone(&self.pair),
+        }
+    }
+}
+
+impl<K, V> std::ops::Deref for ReadRef<K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V> ReadRef<K, V> {
+    fn new(pair: Arc<(K, V)>) -> Self {
+        Self { pair }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        &self.pair.0
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        &self.pair.1
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        (&self.pair.0, &self.pair.1)
+    }
+}
+
+/// A concurrent hashmap using a sharding strategy, whose readers never block. See the
+/// [module documentation](self) for how it works.
+///
+/// Cloning the map is cheap, and the clone refers to the same underlying map.
+///
+/// # Examples
+/// ```
+/// use whirlwind::RcuShardMap;
+///
+/// let map = RcuShardMap::new();
+/// map.insert("foo", "bar");
+/// assert_eq!(map.len(), 1);
+/// assert_eq!(map.contains_key(&"foo"), true);
+///
+/// assert_eq!(map.get(&"foo").unwrap().value(), &"bar");
+/// assert_eq!(map.remove(&"foo").unwrap().value(), &"bar");
+/// ```
+pub struct RcuShardMap<K, V, S = RandomState> {
+    inner: Arc<Inner<K, V, S>>,
+}
+
+impl<K, V, S> Clone for RcuShardMap<K, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<K, V> Default for RcuShardMap<K, V, RandomState>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for RcuShardMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let map = Self::with_hasher(S::default());
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V> RcuShardMap<K, V, RandomState>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new `RcuShardMap` with the default hasher.
+    pub fn new() -> Self {
+        Self::with_shards(shard_count())
+    }
+
+    /// Creates a new `RcuShardMap` with the default hasher and `shards` shards.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+
+    /// Creates a new `RcuShardMap` with the default hasher and space for at least `cap` elements.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+
+    /// Creates a new `RcuShardMap` with the default hasher, `shards` shards, and space for at
+    /// least `cap` elements.
+    pub fn with_shards_and_capacity(shards: usize, cap: usize) -> Self {
+        Self::with_shards_and_capacity_and_hasher(shards, cap, RandomState::new())
+    }
+}
+
+impl<K, V, S: BuildHasher> RcuShardMap<K, V, S>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new `RcuShardMap` with the provided hasher `S`.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_shards_and_hasher(shard_count(), hasher)
+    }
+
+    /// Creates a new `RcuShardMap` with the provided hasher `S` and space for at least `cap`
+    /// elements.
+    pub fn with_capacity_and_hasher(cap: usize, hasher: S) -> Self {
+        Self::with_shards_and_capacity_and_hasher(shard_count(), cap, hasher)
+    }
+
+    /// Creates a new `RcuShardMap` with the provided hasher `S` and `shards` shards.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        Self::with_shards_and_capacity_and_hasher(shards, 4, hasher)
+    }
+
+    /// Creates a new `RcuShardMap` with the provided hasher `S`, `shards` shards, and space for
+    /// at least `cap` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is not a power of two greater than one. Use [`ShardMapBuilder`] to
+    /// handle invalid configurations without panicking.
+    pub fn with_shards_and_capacity_and_hasher(shards: usize, cap: usize, hasher: S) -> Self {
+        ShardMapBuilder::new()
+            .shards(shards)
+            .capacity(cap)
+            .hasher(hasher)
+            .build_rcu()
+            .unwrap_or_else(|err| panic!("invalid `RcuShardMap` configuration: {err}"))
+    }
+
+    pub(crate) fn from_builder(builder: ShardMapBuilder<S>) -> Result<Self, BuildError> {
+        let shards = builder.validate_shards()?;
+        let shift = shard_shift(shards);
+        let shard_capacity = shard_capacity(builder.capacity, shards);
+
+        let shards = std::iter::repeat_with(|| {
+            CachePadded::new(Shard {
+                table: ArcSwap::from_pointee(Table::with_capacity(shard_capacity)),
+                writer: Mutex::new(()),
+            })
+        })
+        .take(shards)
+        .collect();
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                shards,
+                hasher: builder.hasher,
+                shift,
+            }),
+        })
+    }
+
+    /// Returns the number of shards in the map.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map = RcuShardMap::<u32, u32>::with_shards(8);
+    /// assert_eq!(map.shard_count(), 8);
+    /// ```
+    pub fn shard_count(&self) -> usize {
+        self.inner.shards.len()
+    }
+
+    #[inline]
+    fn locate<Q>(&self, key: &Q) -> (usize, u64)
+    where
+        Q: ?Sized + Hash,
+    {
+        let hash = self.inner.hasher.hash_one(key);
+
+        (shard_for_hash(hash as usize, self.inner.shift), hash)
+    }
+
+    /// Publishes a copy of the table of the shard at `idx`, modified by `f`, unless `f` returns
+    /// `None`, in which case the table is left as it is.
+    fn update_shard<F, R>(&self, idx: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Table<K, V>) -> Option<R>,
+    {
+        let shard = &self.inner.shards[idx];
+        let _writer = shard.writer.lock();
+
+        let mut table = Table::clone(&shard.table.load());
+        let result = f(&mut table)?;
+        shard.table.store(Arc::new(table));
+
+        Some(result)
+    }
+
+    fn insert_into(
+        &self,
+        table: &mut Table<K, V>,
+        hash: u64,
+        pair: Arc<(K, V)>,
+    ) -> Option<Arc<(K, V)>> {
+        match table.entry(
+            hash,
+            |other| other.0 == pair.0,
+            |other| self.inner.hasher.hash_one(&other.0),
+        ) {
+            Entry::Occupied(mut entry) => Some(std::mem::replace(entry.get_mut(), pair)),
+            Entry::Vacant(slot) => {
+                slot.insert(pair);
+                None
+            }
+        }
+    }
+
+    /// Inserts a key-value pair into the map. If the key already exists, the value is updated and
+    /// the old pair is returned.
+    ///
+    /// This copies the table of the key's shard. See [`RcuShardMap::extend`] to insert many pairs
+    /// at once.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map = RcuShardMap::new();
+    /// assert!(map.insert("foo", "bar").is_none());
+    /// assert_eq!(map.insert("foo", "baz").unwrap().value(), &"bar");
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> Option<ReadRef<K, V>> {
+        let (idx, hash) = self.locate(&key);
+        let pair = Arc::new((key, value));
+
+        self.update_shard(idx, |table| Some(self.insert_into(table, hash, pair)))
+            .flatten()
+            .map(ReadRef::new)
+    }
+
+    /// Inserts all key-value pairs from `iter` into the map, overwriting the values of existing
+    /// keys.
+    ///
+    /// Pairs are grouped by shard, so that each shard is copied and published only once no matter
+    /// how many pairs belong to it.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map = RcuShardMap::new();
+    /// map.extend((0..100).map(|i| (i, i * 2)));
+    ///
+    /// assert_eq!(map.len(), 100);
+    /// assert_eq!(map.get(&21).unwrap().value(), &42);
+    /// ```
+    pub fn extend<I>(&self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut batches: Vec<Batch<K, V>> = std::iter::repeat_with(Vec::new)
+            .take(self.shard_count())
+            .collect();
+        for (key, value) in iter {
+            let (idx, hash) = self.locate(&key);
+            batches[idx].push((hash, Arc::new((key, value))));
+        }
+
+        for (idx, batch) in batches.into_iter().enumerate() {
+            if batch.is_empty() {
+                continue;
+            }
+            self.update_shard(idx, |table| {
+                table.reserve(batch.len(), |other| self.inner.hasher.hash_one(&other.0));
+                for (hash, pair) in batch {
+                    self.insert_into(table, hash, pair);
+                }
+                Some(())
+            });
+        }
+    }
+
+    /// Replaces the value associated with the key with the one returned by `f`, and returns the
+    /// new pair. If the key is not in the map, `f` is not called and `None` is returned.
+    ///
+    /// Concurrent updates to keys of the same shard are serialized, so `f` always sees the latest
+    /// value.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map = RcuShardMap::new();
+    /// map.insert("hits", 1);
+    ///
+    /// assert_eq!(map.update(&"hits", |hits| hits + 1).unwrap().value(), &2);
+    /// assert!(map.update(&"misses", |misses| misses + 1).is_none());
+    /// ```
+    pub fn update<Q, F>(&self, key: &Q, f: F) -> Option<ReadRef<K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+        K: Clone,
+        F: FnOnce(&V) -> V,
+    {
+        let (idx, hash) = self.locate(key);
+        if !self.contains_key_at(idx, hash, key) {
+            return None;
+        }
+
+        self.update_shard(idx, |table| {
+            let pair = table.find_mut(hash, |other| key.equivalent(&other.0))?;
+            *pair = Arc::new((pair.0.clone(), f(&pair.1)));
+            Some(ReadRef::new(Arc::clone(pair)))
+        })
+    }
+
+    /// Returns a reference to the value associated with the key, without taking any lock. If the
+    /// key is not in the map, `None` is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map = RcuShardMap::new();
+    /// map.insert("foo", "bar");
+    ///
+    /// let entry = map.get(&"foo").unwrap();
+    /// map.remove(&"foo");
+    ///
+    /// assert_eq!(entry.value(), &"bar");
+    /// assert!(map.get(&"foo").is_none());
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<ReadRef<K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (idx, hash) = self.locate(key);
+
+        self.inner.shards[idx]
+            .table
+            .load()
+            .find(hash, |pair| key.equivalent(&pair.0))
+            .map(|pair| ReadRef::new(Arc::clone(pair)))
+    }
+
+    fn contains_key_at<Q>(&self, idx: usize, hash: u64, key: &Q) -> bool
+    where
+        Q: ?Sized + Equivalent<K>,
+    {
+        self.inner.shards[idx]
+            .table
+            .load()
+            .find(hash, |pair| key.equivalent(&pair.0))
+            .is_some()
+    }
+
+    /// Returns `true` if the map contains the key, without taking any lock.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (idx, hash) = self.locate(key);
+        self.contains_key_at(idx, hash, key)
+    }
+
+    /// Removes a key from the map and returns the pair that was removed. If the key is not in the
+    /// map, `None` is returned and nothing is copied.
+    pub fn remove<Q>(&self, key: &Q) -> Option<ReadRef<K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (idx, hash) = self.locate(key);
+        if !self.contains_key_at(idx, hash, key) {
+            return None;
+        }
+
+        self.update_shard(idx, |table| {
+            let entry = table
+                .find_entry(hash, |pair| key.equivalent(&pair.0))
+                .ok()?;
+            Some(ReadRef::new(entry.remove().0))
+        })
+    }
+
+    /// Returns the number of elements in the map, without taking any lock.
+    ///
+    /// Each shard is read in turn, so the result may be outdated if the map is modified
+    /// concurrently.
+    pub fn len(&self) -> usize {
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| shard.table.load().len())
+            .sum()
+    }
+
+    /// Returns `true` if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner
+            .shards
+            .iter()
+            .all(|shard| shard.table.load().is_empty())
+    }
+
+    /// Clears the map, removing all key-value pairs.
+    pub fn clear(&self) {
+        for shard in self.inner.shards.iter() {
+            let _writer = shard.writer.lock();
+            shard.table.store(Arc::new(Table::new()));
+        }
+    }
+
+    /// Retains only the key-value pairs for which `f` returns `true`.
+    ///
+    /// Each shard is copied and published once, and only if `f` removes one of its pairs.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map: RcuShardMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    /// map.retain(|_, v| *v % 2 == 0);
+    ///
+    /// assert_eq!(map.len(), 5);
+    /// ```
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        for idx in 0..self.shard_count() {
+            self.update_shard(idx, |table| {
+                let len = table.len();
+                table.retain(|pair| f(&pair.0, &pair.1));
+                (table.len() != len).then_some(())
+            });
+        }
+    }
+
+    /// Returns an iterator over every key-value pair in the map, in arbitrary order, without
+    /// taking any lock.
+    ///
+    /// Each shard is read as it was when the iterator reached it, so changes made to the map
+    /// while iterating may or may not be seen.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::RcuShardMap;
+    ///
+    /// let map: RcuShardMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    /// assert_eq!(map.iter().map(|entry| *entry.value()).sum::<u32>(), 45);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = ReadRef<K, V>> + '_ {
+        self.inner.shards.iter().flat_map(|shard| {
+            let table = shard.table.load();
+            table.iter().cloned().map(ReadRef::new).collect::<Vec<_>>()
+        })
+    }
+}
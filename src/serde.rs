@@ -0,0 +1,187 @@
+//! [`Serialize`] and [`Deserialize`] implementations, enabled by the `serde` feature.
+//!
+//! To serialize a [`ShardMap`], serialize a consistent [`Snapshot`] of it, taken with
+//! [`ShardMap::snapshot`] or [`ShardMap::blocking_snapshot`]; it serializes as a map. Wrapping a
+//! map or a [`ShardSet`] in [`TrySerialize`] serializes it in place instead, as a map or a
+//! sequence, without waiting for a lock, but fails if a shard is write-locked at the time.
+//! Deserializing loads every entry straight into its shard, using a default-constructed hasher.
+//!
+//! ```
+//! # #[cfg(feature = "tokio")]
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! use whirlwind::ShardMap;
+//!
+//! let map = ShardMap::new();
+//! map.insert("foo", 1).await;
+//!
+//! let json = serde_json::to_string(&map.snapshot().await).unwrap();
+//! assert_eq!(json, r#"{"foo":1}"#);
+//! # });
+//! ```
+use std::{
+    fmt,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+};
+
+use ::serde::{
+    de::{MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{snapshot::Snapshot, ShardMap, ShardSet};
+
+/// Caps the capacity reserved up front from an untrusted size hint.
+const MAX_PREALLOCATED: usize = 1 << 16;
+
+impl<K, V, S> Serialize for Snapshot<'_, K, V, S>
+where
+    K: Eq + Hash + Serialize + 'static,
+    V: Serialize + 'static,
+    S: BuildHasher,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Serializes a [`ShardMap`] or a [`ShardSet`] without waiting for its locks.
+///
+/// The wrapped collection serializes from a consistent snapshot, taken with
+/// [`ShardMap::try_snapshot`]. Serialize the result of [`ShardMap::snapshot`] to wait for the
+/// locks instead.
+///
+/// # Errors
+///
+/// Serializing fails if a shard is write-locked, or the collection is being resharded.
+///
+/// # Examples
+///
+/// ```
+/// use whirlwind::{ShardSet, TrySerialize};
+///
+/// let set: ShardSet<u32> = [1].into_iter().collect();
+/// assert_eq!(serde_json::to_string(&TrySerialize(&set)).unwrap(), "[1]");
+/// ```
+#[derive(Debug)]
+pub struct TrySerialize<'a, T>(pub &'a T);
+
+impl<K, V, S> Serialize for TrySerialize<'_, ShardMap<K, V, S>>
+where
+    K: Eq + Hash + Serialize + 'static,
+    V: Serialize + 'static,
+    S: BuildHasher,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        try_snapshot(self.0)?.serialize(serializer)
+    }
+}
+
+impl<T, S> Serialize for TrySerialize<'_, ShardSet<T, S>>
+where
+    T: Eq + Hash + Serialize + 'static,
+    S: BuildHasher,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let snapshot = try_snapshot(&self.0.inner)?;
+        let mut seq = serializer.serialize_seq(Some(snapshot.len()))?;
+        for value in snapshot.keys() {
+            seq.serialize_element(value)?;
+        }
+        seq.end()
+    }
+}
+
+fn try_snapshot<K, V, S, E>(map: &ShardMap<K, V, S>) -> Result<Snapshot<'_, K, V, S>, E>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    S: BuildHasher,
+    E: ::serde::ser::Error,
+{
+    map.try_snapshot()
+        .map_err(|_| E::custom("the map is locked for writing"))
+}
+
+struct MapVisitor<K, V, S>(PhantomData<(K, V, S)>);
+
+impl<'de, K, V, S> Visitor<'de> for MapVisitor<K, V, S>
+where
+    K: Eq + Hash + Deserialize<'de> + 'static,
+    V: Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    type Value = ShardMap<K, V, S>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let capacity = access.size_hint().unwrap_or(0).min(MAX_PREALLOCATED);
+        let map = ShardMap::with_capacity_and_hasher(capacity, S::default());
+
+        let mut writers = map.write_unshared();
+        while let Some((key, value)) = access.next_entry()? {
+            let (idx, hash) = map.locate(&key);
+            map.insert_locked(map.shard_at(idx), &mut writers[idx], hash, key, value);
+        }
+        drop(writers);
+
+        Ok(map)
+    }
+}
+
+impl<'de, K, V, S> Deserialize<'de> for ShardMap<K, V, S>
+where
+    K: Eq + Hash + Deserialize<'de> + 'static,
+    V: Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+struct SetVisitor<T, S>(PhantomData<(T, S)>);
+
+impl<'de, T, S> Visitor<'de> for SetVisitor<T, S>
+where
+    T: Eq + Hash + Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    type Value = ShardSet<T, S>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let capacity = access.size_hint().unwrap_or(0).min(MAX_PREALLOCATED);
+        let map = ShardMap::with_capacity_and_hasher(capacity, S::default());
+
+        let mut writers = map.write_unshared();
+        while let Some(value) = access.next_element()? {
+            let (idx, hash) = map.locate(&value);
+            map.insert_locked(map.shard_at(idx), &mut writers[idx], hash, value, ());
+        }
+        drop(writers);
+
+        Ok(ShardSet { inner: map })
+    }
+}
+
+impl<'de, T, S> Deserialize<'de> for ShardSet<T, S>
+where
+    T: Eq + Hash + Deserialize<'de> + 'static,
+    S: BuildHasher + Default,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SetVisitor(PhantomData))
+    }
+}
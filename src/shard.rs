@@ -1,43 +1,477 @@
-use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+};
 
-pub(crate) type Inner<K, V> = hashbrown::HashTable<(K, V)>;
-pub(crate) type ShardReader<'a, K, V> = RwLockReadGuard<'a, Inner<K, V>>;
-pub(crate) type ShardWriter<'a, K, V> = RwLockWriteGuard<'a, Inner<K, V>>;
+use parking_lot::{Condvar, Mutex};
 
-/// A shard in a [`crate::ShardMap`]. Each shard contains a [`hashbrown::HashTable`] of key-value pairs.
+// The read-write lock guarding each shard: `tokio::sync::RwLock` by default, or
+// `async_lock::RwLock` with the `async-lock` feature.
+#[cfg(not(any(feature = "tokio", feature = "async-lock")))]
+compile_error!("either the `tokio` or the `async-lock` feature must be enabled");
+#[cfg(feature = "async-lock")]
+use async_lock::{
+    RwLock as Lock, RwLockReadGuard as ReadGuard, RwLockReadGuardArc as OwnedReadGuard,
+    RwLockWriteGuard as WriteGuard, RwLockWriteGuardArc as OwnedWriteGuard,
+};
+#[cfg(all(feature = "tokio", not(feature = "async-lock")))]
+use tokio::sync::{
+    OwnedRwLockReadGuard as OwnedReadGuard, OwnedRwLockWriteGuard as OwnedWriteGuard,
+    RwLock as Lock, RwLockReadGuard as ReadGuard, RwLockWriteGuard as WriteGuard,
+};
+
+pub(crate) type Inner<K, V> = crate::table::Table<(K, V)>;
+pub(crate) type ShardReader<'a, K, V> = ReadGuard<'a, Inner<K, V>>;
+pub(crate) type ShardWriter<'a, K, V> = WriteGuard<'a, Inner<K, V>>;
+pub(crate) type OwnedShardReader<K, V> = OwnedReadGuard<Inner<K, V>>;
+pub(crate) type OwnedShardWriter<K, V> = OwnedWriteGuard<Inner<K, V>>;
+
+/// An asynchronous read-write lock that can guard a shard, so that shards are not tied to a
+/// single async runtime.
+pub(crate) trait ShardLock<T> {
+    type ReadGuard<'a>: Deref<Target = T>
+    where
+        Self: 'a;
+    type WriteGuard<'a>: DerefMut<Target = T>
+    where
+        Self: 'a;
+    /// A read guard that keeps the lock alive instead of borrowing it.
+    type OwnedReadGuard: Deref<Target = T>;
+    /// A write guard that keeps the lock alive instead of borrowing it.
+    type OwnedWriteGuard: DerefMut<Target = T>;
+
+    fn new(value: T) -> Self;
+
+    async fn read(&self) -> Self::ReadGuard<'_>;
+
+    async fn write(&self) -> Self::WriteGuard<'_>;
+
+    async fn read_owned(this: Arc<Self>) -> Self::OwnedReadGuard;
+
+    async fn write_owned(this: Arc<Self>) -> Self::OwnedWriteGuard;
+
+    /// Blocks the current thread until the lock is acquired for reading.
+    fn blocking_read(&self) -> Self::ReadGuard<'_>;
+
+    /// Blocks the current thread until the lock is acquired for writing.
+    fn blocking_write(&self) -> Self::WriteGuard<'_>;
+
+    fn try_read(&self) -> Option<Self::ReadGuard<'_>>;
+
+    fn try_write(&self) -> Option<Self::WriteGuard<'_>>;
+
+    /// Atomically turns a write lock into a read lock, without letting other writers in.
+    #[cfg(feature = "tokio")]
+    fn downgrade<'a>(writer: Self::WriteGuard<'a>) -> Self::ReadGuard<'a>
+    where
+        Self: 'a;
+
+    fn into_inner(self) -> T;
+}
+
+#[cfg(feature = "tokio")]
+impl<T> ShardLock<T> for tokio::sync::RwLock<T> {
+    type ReadGuard<'a>
+        = tokio::sync::RwLockReadGuard<'a, T>
+    where
+        T: 'a;
+    type WriteGuard<'a>
+        = tokio::sync::RwLockWriteGuard<'a, T>
+    where
+        T: 'a;
+    type OwnedReadGuard = tokio::sync::OwnedRwLockReadGuard<T>;
+    type OwnedWriteGuard = tokio::sync::OwnedRwLockWriteGuard<T>;
+
+    fn new(value: T) -> Self {
+        Self::new(value)
+    }
+
+    async fn read(&self) -> Self::ReadGuard<'_> {
+        self.read().await
+    }
+
+    async fn write(&self) -> Self::WriteGuard<'_> {
+        self.write().await
+    }
+
+    async fn read_owned(this: Arc<Self>) -> Self::OwnedReadGuard {
+        this.read_owned().await
+    }
+
+    async fn write_owned(this: Arc<Self>) -> Self::OwnedWriteGuard {
+        this.write_owned().await
+    }
+
+    fn blocking_read(&self) -> Self::ReadGuard<'_> {
+        self.blocking_read()
+    }
+
+    fn blocking_write(&self) -> Self::WriteGuard<'_> {
+        self.blocking_write()
+    }
+
+    fn try_read(&self) -> Option<Self::ReadGuard<'_>> {
+        self.try_read().ok()
+    }
+
+    fn try_write(&self) -> Option<Self::WriteGuard<'_>> {
+        self.try_write().ok()
+    }
+
+    fn downgrade<'a>(writer: Self::WriteGuard<'a>) -> Self::ReadGuard<'a>
+    where
+        Self: 'a,
+    {
+        writer.downgrade()
+    }
+
+    fn into_inner(self) -> T {
+        self.into_inner()
+    }
+}
+
+#[cfg(feature = "async-lock")]
+impl<T> ShardLock<T> for async_lock::RwLock<T> {
+    type ReadGuard<'a>
+        = async_lock::RwLockReadGuard<'a, T>
+    where
+        T: 'a;
+    type WriteGuard<'a>
+        = async_lock::RwLockWriteGuard<'a, T>
+    where
+        T: 'a;
+    type OwnedReadGuard = async_lock::RwLockReadGuardArc<T>;
+    type OwnedWriteGuard = async_lock::RwLockWriteGuardArc<T>;
+
+    fn new(value: T) -> Self {
+        Self::new(value)
+    }
+
+    async fn read(&self) -> Self::ReadGuard<'_> {
+        self.read().await
+    }
+
+    async fn write(&self) -> Self::WriteGuard<'_> {
+        self.write().await
+    }
+
+    async fn read_owned(this: Arc<Self>) -> Self::OwnedReadGuard {
+        this.read_arc().await
+    }
+
+    async fn write_owned(this: Arc<Self>) -> Self::OwnedWriteGuard {
+        this.write_arc().await
+    }
+
+    fn blocking_read(&self) -> Self::ReadGuard<'_> {
+        self.read_blocking()
+    }
+
+    fn blocking_write(&self) -> Self::WriteGuard<'_> {
+        self.write_blocking()
+    }
+
+    fn try_read(&self) -> Option<Self::ReadGuard<'_>> {
+        self.try_read()
+    }
+
+    fn try_write(&self) -> Option<Self::WriteGuard<'_>> {
+        self.try_write()
+    }
+
+    #[cfg(feature = "tokio")]
+    fn downgrade<'a>(writer: Self::WriteGuard<'a>) -> Self::ReadGuard<'a>
+    where
+        Self: 'a,
+    {
+        async_lock::RwLockWriteGuard::downgrade(writer)
+    }
+
+    fn into_inner(self) -> T {
+        self.into_inner()
+    }
+}
+
+/// A shard in a [`crate::ShardMap`]. Each shard contains a [`crate::table::Table`] of key-value
+/// pairs.
 pub(crate) struct Shard<K, V> {
-    data: RwLock<Inner<K, V>>,
+    /// Shared with the owned references into the shard, which keep the lock alive.
+    data: Arc<Lock<Inner<K, V>>>,
+    /// Incremented every time the entries of the shard change, so that optimistic readers can
+    /// detect whether the shard changed since they last saw it.
+    version: AtomicU64,
+    /// Identifies the shard among every shard the map ever had, including those that were
+    /// replaced by resharding.
+    id: usize,
+    /// Set once the entries of the shard have been moved to the shards that replace it. This is
+    /// only changed while the shard is locked for writing, and never unset.
+    retired: AtomicBool,
+    /// The sequence number of the next event of this shard in the change feed.
+    #[cfg(feature = "tokio")]
+    seq: AtomicU64,
 }
 
-impl<K, V> Shard<K, V>
-where
-    K: Eq + std::hash::Hash,
-{
-    pub fn with_capacity(capacity: usize) -> Self {
+impl<K, V> Shard<K, V> {
+    pub fn with_capacity(id: usize, capacity: usize, incremental: bool) -> Self {
         Self {
-            data: RwLock::new(Inner::with_capacity(capacity)),
+            data: Arc::new(ShardLock::new(Inner::with_capacity(capacity, incremental))),
+            version: AtomicU64::new(0),
+            id,
+            retired: AtomicBool::new(false),
+            #[cfg(feature = "tokio")]
+            seq: AtomicU64::new(0),
         }
     }
 
+    /// Returns the current version of the shard. This is only stable while the shard is locked.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    /// Records that the entries of the shard changed. This must be called while the shard is
+    /// locked for writing.
+    pub fn mark_changed(&self) {
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns `true` if the entries of the shard have moved elsewhere. A shard that is locked
+    /// and not retired stays that way until it is unlocked.
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::Relaxed)
+    }
+
+    /// Marks the shard as retired. This must be called while the shard is locked for writing.
+    pub fn retire(&self) {
+        self.retired.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the sequence number for a new event of the shard. This must be called while the
+    /// shard is locked for writing.
+    #[cfg(feature = "tokio")]
+    pub fn next_seq(&self) -> u64 {
+        self.seq.fetch_add(1, Ordering::Relaxed)
+    }
+
     pub async fn write<'a>(&'a self) -> ShardWriter<'a, K, V> {
-        self.data.write().await
+        ShardLock::write(&*self.data).await
     }
 
     pub async fn read<'a>(&'a self) -> ShardReader<'a, K, V> {
-        self.data.read().await
+        ShardLock::read(&*self.data).await
+    }
+
+    pub async fn write_owned(&self) -> OwnedShardWriter<K, V> {
+        ShardLock::write_owned(Arc::clone(&self.data)).await
+    }
+
+    pub async fn read_owned(&self) -> OwnedShardReader<K, V> {
+        ShardLock::read_owned(Arc::clone(&self.data)).await
+    }
+
+    pub fn blocking_write(&self) -> ShardWriter<'_, K, V> {
+        ShardLock::blocking_write(&*self.data)
+    }
+
+    pub fn blocking_read(&self) -> ShardReader<'_, K, V> {
+        ShardLock::blocking_read(&*self.data)
+    }
+
+    pub fn try_write(&self) -> Option<ShardWriter<'_, K, V>> {
+        ShardLock::try_write(&*self.data)
+    }
+
+    pub fn try_read(&self) -> Option<ShardReader<'_, K, V>> {
+        ShardLock::try_read(&*self.data)
+    }
+
+    /// Turns a write lock on a shard into a read lock, without letting other writers in.
+    #[cfg(feature = "tokio")]
+    pub fn downgrade<'a>(writer: ShardWriter<'a, K, V>) -> ShardReader<'a, K, V>
+    where
+        Self: 'a,
+    {
+        <Lock<_> as ShardLock<_>>::downgrade(writer)
+    }
+
+    pub fn into_inner(self) -> Inner<K, V> {
+        let data = Arc::into_inner(self.data)
+            .expect("owned references into a shard keep the whole map alive");
+        ShardLock::into_inner(data)
     }
 }
 
-impl<K, V> std::ops::Deref for Shard<K, V> {
-    type Target = RwLock<Inner<K, V>>;
+/// A lock with no data, which lets readers in whenever no writer holds it, even while a writer is
+/// waiting for it.
+///
+/// [`crate::ShardMap`] holds it exclusively while it moves entries between shards, and shares it
+/// with the operations that visit every shard. Those operations may wait for shard locks while
+/// they hold it, so it must not queue new readers behind a waiting writer the way the shard locks
+/// do: a task holding a shard lock could then wait for the gate behind a reshard that waits for a
+/// reader that waits for that task. The writer never waits for anything while it holds the gate,
+/// so readers are never held up for long, but a writer can be kept waiting for as long as readers
+/// keep overlapping.
+#[derive(Default)]
+pub(crate) struct Gate {
+    state: Mutex<GateState>,
+    /// Notified when the gate is released, for threads blocked in [`Gate::blocking_read`].
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct GateState {
+    readers: usize,
+    writer: bool,
+    /// The tasks waiting for the gate by the key of their [`Acquire`] future, all of which are
+    /// woken up when it is released.
+    waiters: HashMap<u64, Waker>,
+    next_waiter: u64,
+}
+
+/// A task waiting to take a [`Gate`]. Its waker stays registered under `key` until the gate is
+/// released or the future is dropped, and is replaced rather than added again when it is polled
+/// more than once in between.
+struct Acquire<'a> {
+    gate: &'a Gate,
+    write: bool,
+    key: Option<u64>,
+}
+
+impl Future for Acquire<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        this.gate
+            .poll_acquire(this.write, &mut this.key, cx.waker())
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.gate.state.lock().waiters.remove(&key);
+        }
+    }
+}
+
+/// Shared access to a [`Gate`], released on drop.
+pub(crate) struct GateReader<'a>(&'a Gate);
+
+/// Exclusive access to a [`Gate`], released on drop.
+pub(crate) struct GateWriter<'a>(&'a Gate);
+
+impl Gate {
+    /// Takes the gate if it is free, or registers `waker` under `key` to be woken up when it is
+    /// released.
+    fn poll_acquire(&self, write: bool, key: &mut Option<u64>, waker: &Waker) -> Poll<()> {
+        let mut state = self.state.lock();
+        let free = !state.writer && (!write || state.readers == 0);
+        if !free {
+            let registered = key.and_then(|key| state.waiters.get_mut(&key));
+            match registered {
+                Some(registered) => {
+                    if !registered.will_wake(waker) {
+                        *registered = waker.clone();
+                    }
+                }
+                None => {
+                    let next = state.next_waiter;
+                    state.next_waiter += 1;
+                    state.waiters.insert(next, waker.clone());
+                    *key = Some(next);
+                }
+            }
+            return Poll::Pending;
+        }
+
+        if let Some(key) = key.take() {
+            state.waiters.remove(&key);
+        }
+
+        if write {
+            state.writer = true;
+        } else {
+            state.readers += 1;
+        }
+        Poll::Ready(())
+    }
+
+    fn release(&self, write: bool) {
+        let waiters = {
+            let mut state = self.state.lock();
+            if write {
+                state.writer = false;
+            } else {
+                state.readers -= 1;
+                if state.readers > 0 {
+                    return;
+                }
+            }
+            std::mem::take(&mut state.waiters)
+        };
+
+        self.released.notify_all();
+        for waiter in waiters.into_values() {
+            waiter.wake();
+        }
+    }
+
+    fn acquire(&self, write: bool) -> Acquire<'_> {
+        Acquire {
+            gate: self,
+            write,
+            key: None,
+        }
+    }
+
+    pub async fn read(&self) -> GateReader<'_> {
+        self.acquire(false).await;
+        GateReader(self)
+    }
+
+    pub async fn write(&self) -> GateWriter<'_> {
+        self.acquire(true).await;
+        GateWriter(self)
+    }
+
+    pub fn try_read(&self) -> Option<GateReader<'_>> {
+        let mut state = self.state.lock();
+        if state.writer {
+            return None;
+        }
+        state.readers += 1;
+        Some(GateReader(self))
+    }
+
+    pub fn blocking_read(&self) -> GateReader<'_> {
+        let mut state = self.state.lock();
+        while state.writer {
+            self.released.wait(&mut state);
+        }
+        state.readers += 1;
+        GateReader(self)
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.data
+impl Drop for GateReader<'_> {
+    fn drop(&mut self) {
+        self.0.release(false);
     }
 }
 
-impl<K, V> std::ops::DerefMut for Shard<K, V> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.data
+impl Drop for GateWriter<'_> {
+    fn drop(&mut self) {
+        self.0.release(true);
     }
 }
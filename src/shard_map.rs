@@ -19,35 +19,289 @@
 //! });
 //! ```
 use std::{
-    hash::{BuildHasher, RandomState},
-    sync::{Arc, OnceLock},
+    collections::BTreeMap,
+    future::Future,
+    hash::{BuildHasher, Hash, RandomState},
+    ops::Range,
+    ptr::{self, NonNull},
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc, OnceLock,
+    },
 };
 
 use crossbeam_utils::CachePadded;
-use hashbrown::hash_table::Entry;
+use futures_util::{future::BoxFuture, stream, Stream, StreamExt};
+use hashbrown::{hash_table::Entry, Equivalent};
 
 use crate::{
-    mapref::{MapRef, MapRefMut},
-    shard::Shard,
+    changes::Change,
+    entry::{self, OccupiedEntry, VacantEntry},
+    error::{BuildError, TransactionError, WouldBlock},
+    iter::IntoIter,
+    mapref::{
+        KeyRef, MapRef, MapRefManyMut, MapRefMulti, MapRefMut, MapRefMutMulti, OwnedMapRef,
+        OwnedMapRefMut, ValueRef,
+    },
+    shard::{
+        self, Gate, GateReader, OwnedShardReader, OwnedShardWriter, Shard, ShardReader, ShardWriter,
+    },
+    snapshot::Snapshot,
+    table,
+    transaction::{self, Transaction, TxState},
+    watch::{ChangeTracker, Watchers},
+    ShardMapBuilder,
+};
+#[cfg(feature = "tokio")]
+use crate::{
+    changes::ChangeStream,
+    loader::{self, Join, Loads},
+    watch::KeyWatcher,
 };
 
-struct Inner<K, V, S = RandomState> {
-    shards: Box<[CachePadded<Shard<K, V>>]>,
+type Batch<K, V> = Vec<(usize, u64, K, V)>;
+type PairsMut<'a, K, V> = Vec<(&'a K, &'a mut V)>;
+type Shards<'a, K, V> = Option<(GateReader<'a>, std::vec::IntoIter<&'a Shard<K, V>>)>;
+type ReadCursor<'a, K, V> = Option<(table::Iter<'a, (K, V)>, Arc<ShardReader<'a, K, V>>)>;
+type WriteCursor<'a, K, V> = Option<(
+    &'a Shard<K, V>,
+    table::IterMut<'a, (K, V)>,
+    Arc<ShardWriter<'a, K, V>>,
+)>;
+
+pub(crate) struct Inner<K, V, S = RandomState> {
+    /// The shards the map was created with. Every reshard chains a new layout onto this one.
+    layout: Layout<K, V>,
+    /// The oldest layout that may still hold entries, or null for `layout`. Layouts are never
+    /// freed before the map is, since references into the map borrow their shards.
+    current: AtomicPtr<Layout<K, V>>,
+    /// Held exclusively while entries are moved from one shard to another, and shared by the
+    /// operations that visit every shard, so that they see each entry exactly once.
+    gate: Gate,
+    /// Held for the whole of a reshard, so that only one runs at a time.
+    resharding: Gate,
+    /// Whether the tables of the shards grow incrementally. See
+    /// [`ShardMapBuilder::incremental_resize`].
+    incremental_resize: bool,
     hasher: S,
-    shift: usize,
+    #[cfg(feature = "tokio")]
+    loads: Loads<K>,
+    watchers: Watchers<K, V>,
 }
 
-impl<K, V, S> std::ops::Deref for Inner<K, V, S> {
-    type Target = Box<[CachePadded<Shard<K, V>>]>;
+impl<K, V, S> Inner<K, V, S> {
+    fn layout(&self) -> &Layout<K, V> {
+        let current = self.current.load(Ordering::Acquire);
+        if current.is_null() {
+            &self.layout
+        } else {
+            // SAFETY: Non-null pointers come from `Layout::next`, which lives as long as `self`.
+            unsafe { &*current }
+        }
+    }
+
+    /// Returns the current layout followed by every newer one.
+    fn layouts(&self) -> impl Iterator<Item = &Layout<K, V>> {
+        std::iter::successors(Some(self.layout()), |layout| {
+            layout.next.get().map(|next| &**next)
+        })
+    }
+
+    /// Returns every shard of the current and newer layouts, in ascending order of their ids.
+    fn shards(&self) -> impl Iterator<Item = &Shard<K, V>> {
+        self.layouts()
+            .flat_map(|layout| layout.shards.iter().map(|shard| &**shard))
+    }
+
+    /// Returns every shard that may hold entries, in ascending order of their ids. The set of
+    /// live shards only changes while the gate is held exclusively.
+    fn live_shards(&self) -> impl Iterator<Item = &Shard<K, V>> {
+        self.shards().filter(|shard| !shard.is_retired())
+    }
+
+    /// Returns the shard with the given id, or `None` if it belongs to a layout that is no longer
+    /// current.
+    fn shard_by_id(&self, id: usize) -> Option<&Shard<K, V>> {
+        self.layouts().find_map(|layout| {
+            let first_id = layout.shards[0].id();
+            layout
+                .shards
+                .get(id.checked_sub(first_id)?)
+                .map(|shard| &**shard)
+        })
+    }
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.shards
+impl<K: Eq, V, S> Inner<K, V, S> {
+    /// Reports an update to `key`, made while its shard was locked for writing, to the watchers
+    /// of the map.
+    pub(crate) fn notify_update(&self, shard: usize, hash: u64, key: &K, value: &V) {
+        // A shard that is locked for writing cannot be retired, so it is still current.
+        if let Some(shard) = self.shard_by_id(shard) {
+            shard.mark_changed();
+            self.watchers
+                .notify(shard, hash, key, Change::Update(value));
+        }
     }
 }
 
-impl<K, V, S> std::ops::DerefMut for Inner<K, V, S> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.shards
+/// One generation of the shards of a map.
+///
+/// A map starts out with a single layout. [`ShardMap::reshard`] chains a new one onto it and
+/// moves the entries of each old shard into the new shards that its keys map to, retiring the old
+/// shard. A key therefore lives in the first shard along the chain that is not retired.
+struct Layout<K, V> {
+    shards: Box<[CachePadded<Shard<K, V>>]>,
+    shift: usize,
+    next: OnceLock<Box<Layout<K, V>>>,
+}
+
+impl<K, V> Layout<K, V> {
+    /// Creates a layout of `shards` shards, numbered from `first_id`.
+    fn new(first_id: usize, shards: usize, capacity: usize, incremental: bool) -> Self {
+        Self {
+            shards: (first_id..first_id + shards)
+                .map(|id| CachePadded::new(Shard::with_capacity(id, capacity, incremental)))
+                .collect(),
+            shift: shard_shift(shards),
+            next: OnceLock::new(),
+        }
+    }
+
+    /// Returns the shard that holds the keys with the given hash, starting from this layout,
+    /// without locking it. The result is stable while the gate is held, or while the returned
+    /// shard is locked.
+    fn live_shard(&self, hash: u64) -> &Shard<K, V> {
+        let mut layout = self;
+        loop {
+            let shard = layout.shard(hash);
+            if !shard.is_retired() {
+                return shard;
+            }
+            layout = layout.next();
+        }
+    }
+
+    /// Locks the shard that holds the keys with the given hash for reading.
+    async fn read_shard(&self, hash: u64) -> (&Shard<K, V>, ShardReader<'_, K, V>) {
+        let mut layout = self;
+        loop {
+            let shard = layout.shard(hash);
+            let reader = shard.read().await;
+            if !shard.is_retired() {
+                return (shard, reader);
+            }
+            layout = layout.next();
+        }
+    }
+
+    /// Locks the shard that holds the keys with the given hash for writing.
+    async fn write_shard(&self, hash: u64) -> (&Shard<K, V>, ShardWriter<'_, K, V>) {
+        let mut layout = self;
+        loop {
+            let shard = layout.shard(hash);
+            let writer = shard.write().await;
+            if !shard.is_retired() {
+                return (shard, writer);
+            }
+            layout = layout.next();
+        }
+    }
+
+    /// Locks the shard that holds the keys with the given hash for reading, with a guard that
+    /// does not borrow the shard.
+    async fn read_shard_owned(&self, hash: u64) -> (&Shard<K, V>, OwnedShardReader<K, V>) {
+        let mut layout = self;
+        loop {
+            let shard = layout.shard(hash);
+            let reader = shard.read_owned().await;
+            if !shard.is_retired() {
+                return (shard, reader);
+            }
+            layout = layout.next();
+        }
+    }
+
+    /// Locks the shard that holds the keys with the given hash for writing, with a guard that
+    /// does not borrow the shard.
+    async fn write_shard_owned(&self, hash: u64) -> (&Shard<K, V>, OwnedShardWriter<K, V>) {
+        let mut layout = self;
+        loop {
+            let shard = layout.shard(hash);
+            let writer = shard.write_owned().await;
+            if !shard.is_retired() {
+                return (shard, writer);
+            }
+            layout = layout.next();
+        }
+    }
+
+    /// Locks the shard that holds the keys with the given hash with `lock`, which either blocks
+    /// or gives up if the shard is locked.
+    fn lock_shard<'a, G>(
+        &'a self,
+        hash: u64,
+        mut lock: impl FnMut(&'a Shard<K, V>) -> Option<G>,
+    ) -> Option<(&'a Shard<K, V>, G)> {
+        let mut layout = self;
+        loop {
+            let shard = layout.shard(hash);
+            let guard = lock(shard)?;
+            if !shard.is_retired() {
+                return Some((shard, guard));
+            }
+            layout = layout.next();
+        }
+    }
+
+    fn blocking_read_shard(&self, hash: u64) -> (&Shard<K, V>, ShardReader<'_, K, V>) {
+        self.lock_shard(hash, |shard| Some(shard.blocking_read()))
+            .expect("blocking locks always succeed")
+    }
+
+    fn blocking_write_shard(&self, hash: u64) -> (&Shard<K, V>, ShardWriter<'_, K, V>) {
+        self.lock_shard(hash, |shard| Some(shard.blocking_write()))
+            .expect("blocking locks always succeed")
+    }
+
+    #[inline]
+    fn shard(&self, hash: u64) -> &Shard<K, V> {
+        let idx = shard_for_hash(hash as usize, self.shift);
+        // SAFETY: The shift limits the index to the number of shards.
+        unsafe { self.shards.get_unchecked(idx) }
+    }
+
+    fn next(&self) -> &Layout<K, V> {
+        self.next.get().expect("a retired shard has a newer layout")
+    }
+
+    /// Returns the id that the shards of a layout after this one start from.
+    fn end_id(&self) -> usize {
+        self.shards[self.shards.len() - 1].id() + 1
+    }
+
+    /// Returns the indices of the shards of `next` that the keys of shard `idx` map to. Shard
+    /// indices are the high bits of the hash, so these are contiguous.
+    fn targets(&self, idx: usize, next: &Layout<K, V>) -> Range<usize> {
+        if next.shift <= self.shift {
+            let bits = self.shift - next.shift;
+            idx << bits..(idx + 1) << bits
+        } else {
+            let bits = next.shift - self.shift;
+            idx >> bits..(idx >> bits) + 1
+        }
+    }
+
+    fn into_tables(self, tables: &mut Vec<shard::Inner<K, V>>) {
+        tables.extend(
+            self.shards
+                .into_vec()
+                .into_iter()
+                .map(|shard| CachePadded::into_inner(shard).into_inner()),
+        );
+        if let Some(next) = self.next.into_inner() {
+            next.into_tables(tables);
+        }
     }
 }
 
@@ -93,13 +347,90 @@ where
     }
 }
 
+impl<K, V, S> IntoIterator for ShardMap<K, V, S> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    /// Consumes the map, returning an iterator over its key-value pairs.
+    ///
+    /// Prefer [`ShardMap::try_into_iter`], which hands the map back instead of panicking, or
+    /// [`ShardMap::drain`], which takes the entries out of a map that is still shared.
+    ///
+    /// # Panics
+    ///
+    /// Panics if anything else still shares the map, which includes:
+    /// - other clones of the map, including those held by spawned tasks;
+    /// - [`OwnedMapRef`]s and [`OwnedMapRefMut`]s returned by [`ShardMap::get_owned`] and
+    ///   [`ShardMap::get_mut_owned`];
+    /// - a `Persister` of the map, with the `persistence` feature;
+    /// - a [`ShardMap::transaction`] that is running concurrently.
+    fn into_iter(self) -> Self::IntoIter {
+        self.try_into_iter().unwrap_or_else(|_| {
+            panic!(
+                "cannot consume a `ShardMap` that is still shared; use `try_into_iter` or `drain`"
+            )
+        })
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for ShardMap<K, V, S>
+where
+    K: Eq + std::hash::Hash + 'static,
+    V: 'static,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let map = Self::with_hasher(S::default());
+
+        let batches = map.batch_by_shard(iter);
+        let shards = map.inner.shards().zip(map.write_unshared());
+        for ((shard, mut writer), batch) in shards.zip(batches) {
+            map.insert_batch(shard, &mut writer, batch, |_, _| {});
+        }
+
+        map
+    }
+}
+
+impl<K, V, S> ShardMap<K, V, S> {
+    /// Consumes the map, returning an iterator over its key-value pairs, if nothing else shares the
+    /// map. Otherwise, the map is returned unchanged. See the [`IntoIterator`] implementation for
+    /// what can share a map.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    /// map.blocking_insert("foo", 1);
+    ///
+    /// let clone = map.clone();
+    /// let map = map.try_into_iter().err().unwrap();
+    /// drop(clone);
+    ///
+    /// assert_eq!(map.try_into_iter().ok().unwrap().collect::<Vec<_>>(), [("foo", 1)]);
+    /// ```
+    pub fn try_into_iter(self) -> Result<IntoIter<K, V>, Self> {
+        let inner = Arc::try_unwrap(self.inner).map_err(|inner| Self { inner })?;
+        let mut tables = Vec::new();
+        inner.layout.into_tables(&mut tables);
+
+        Ok(IntoIter::new(tables))
+    }
+
+    #[cfg(feature = "persistence")]
+    pub(crate) fn watchers(&self) -> &Watchers<K, V> {
+        &self.inner.watchers
+    }
+}
+
 #[inline(always)]
 fn calculate_shard_count() -> usize {
     (std::thread::available_parallelism().map_or(1, usize::from) * 4).next_power_of_two()
 }
 
 #[inline(always)]
-fn shard_count() -> usize {
+pub(crate) fn shard_count() -> usize {
     static SHARD_COUNT: OnceLock<usize> = OnceLock::new();
     *SHARD_COUNT.get_or_init(calculate_shard_count)
 }
@@ -134,6 +465,32 @@ fn ptr_size_bits() -> usize {
     std::mem::size_of::<*const ()>() * 8
 }
 
+/// Returns the shift that [`shard_for_hash`] uses to select one of `shards` shards, which must be
+/// a power of two.
+pub(crate) fn shard_shift(shards: usize) -> usize {
+    ptr_size_bits() - (shards.trailing_zeros() as usize)
+}
+
+/// Returns the index of the shard that a key with the given hash belongs to.
+#[inline]
+pub(crate) fn shard_for_hash(hash: usize, shift: usize) -> usize {
+    // 7 high bits for the HashBrown simd tag
+    (hash << 7) >> shift
+}
+
+/// Returns the capacity of each of `shards` shards, so that together they hold at least `cap`
+/// elements.
+pub(crate) fn shard_capacity(cap: usize, shards: usize) -> usize {
+    if cap == 0 {
+        return 0;
+    }
+    ((cap + (shards - 1)) & !(shards - 1)) / shards
+}
+
+/// The largest supported shard count. The 7 high bits of the hash are used by hashbrown, so only
+/// the remaining bits can be used to select a shard.
+pub(crate) const MAX_SHARDS: usize = 1 << (usize::BITS - 7);
+
 impl<K, V, S: BuildHasher> ShardMap<K, V, S>
 where
     K: Eq + std::hash::Hash + 'static,
@@ -156,67 +513,254 @@ where
 
     /// Creates a new `ShardMap` with the provided hasher `S`, `shards` shards, and space for at
     /// least `cap` elements.
-    pub fn with_shards_and_capacity_and_hasher(shards: usize, mut cap: usize, hasher: S) -> Self {
-        debug_assert!(shards > 1);
-        debug_assert!(shards.is_power_of_two());
-
-        let shift = ptr_size_bits() - (shards.trailing_zeros() as usize);
-
-        if cap != 0 {
-            cap = (cap + (shards - 1)) & !(shards - 1);
-        }
-        let shard_capacity = cap / shards;
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is not a power of two greater than one. Use [`ShardMapBuilder`] to
+    /// handle invalid configurations without panicking.
+    pub fn with_shards_and_capacity_and_hasher(shards: usize, cap: usize, hasher: S) -> Self {
+        ShardMapBuilder::new()
+            .shards(shards)
+            .capacity(cap)
+            .hasher(hasher)
+            .build()
+            .unwrap_or_else(|err| panic!("invalid `ShardMap` configuration: {err}"))
+    }
 
-        let shards = std::iter::repeat(())
-            .take(shards)
-            .map(|_| CachePadded::new(Shard::with_capacity(shard_capacity)))
-            .collect();
+    pub(crate) fn from_builder(builder: ShardMapBuilder<S>) -> Result<Self, BuildError> {
+        let shards = builder.validate_shards()?;
+        let shard_capacity = shard_capacity(builder.capacity, shards);
 
-        Self {
+        Ok(Self {
             inner: Arc::new(Inner {
-                shards,
-                shift,
-                hasher,
+                layout: Layout::new(0, shards, shard_capacity, builder.incremental_resize),
+                current: AtomicPtr::new(ptr::null_mut()),
+                gate: Gate::default(),
+                resharding: Gate::default(),
+                incremental_resize: builder.incremental_resize,
+                hasher: builder.hasher,
+                #[cfg(feature = "tokio")]
+                loads: Loads::default(),
+                watchers: Watchers::new(shards),
             }),
-        }
-    }
-
-    #[inline]
-    fn shard_for_hash(&self, hash: usize) -> usize {
-        // 7 high bits for the HashBrown simd tag
-        (hash << 7) >> self.inner.shift
+        })
     }
 
-    #[inline]
-    fn shard(&self, key: &K) -> (&CachePadded<Shard<K, V>>, u64) {
-        let hash = self.inner.hasher.hash_one(key);
-
-        let shard_idx = self.shard_for_hash(hash as usize);
-
-        (unsafe { self.inner.shards.get_unchecked(shard_idx) }, hash)
+    /// Returns the number of shards in the map. While the map is being resharded, this is the
+    /// number of shards it is being resharded to.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::ShardMap;
+    ///
+    /// let map = ShardMap::<u32, u32>::with_shards(8);
+    /// assert_eq!(map.shard_count(), 8);
+    /// ```
+    pub fn shard_count(&self) -> usize {
+        let newest = self.inner.layouts().last();
+        newest.expect("a map has at least one layout").shards.len()
     }
 
-    /// Inserts a key-value pair into the map. If the key already exists, the value is updated and
-    /// the old value is returned.
+    /// Changes the number of shards of the map, moving every entry to the shard it belongs to
+    /// among the new ones.
+    ///
+    /// Entries are moved one old shard at a time, while the map stays usable: an operation on a
+    /// single key waits at most for the shard that holds the key to be moved. Operations that
+    /// visit every shard, such as [`ShardMap::len`], [`ShardMap::retain`] or the streams returned
+    /// by [`ShardMap::iter`], are never interleaved with a move, so they still see every entry
+    /// exactly once. Shards are not moved while such an operation runs or such a stream is alive,
+    /// so a task must not wait for a reshard while holding one, and a reshard can be delayed for
+    /// as long as those operations keep overlapping. They never wait for a pending reshard.
+    ///
+    /// Only one reshard runs at a time. If the returned future is dropped before it completes,
+    /// the map keeps working with part of its entries moved, and the next call finishes moving
+    /// them before resharding again.
+    ///
+    /// The tables of the old shards are freed as they are moved, but the shards themselves, with
+    /// their locks, stay allocated until the map is dropped, since references into the map may
+    /// borrow them. Every reshard therefore keeps about 128 bytes per old shard for the rest of
+    /// the life of the map. This is negligible for a map that is resharded a few times as it
+    /// grows, but a policy that reshards automatically, for example in response to contention or
+    /// to the size of the map, should limit how often it does so.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, leaving the map unchanged, if `shards` is not a power of two greater than
+    /// one, as required by [`ShardMapBuilder::shards`].
     ///
     /// # Example
     /// ```
     /// use tokio::runtime::Runtime;
-    /// use std::sync::Arc;
     /// use whirlwind::ShardMap;
+    ///
     /// let rt = Runtime::new().unwrap();
-    /// let map = Arc::new(ShardMap::new());
+    /// let map = ShardMap::with_shards(2);
+    ///
     /// rt.block_on(async {
-    ///     map.insert("foo", "bar").await;
+    ///     map.extend((0..1000).map(|i| (i, i))).await;
     ///
-    ///     assert_eq!(map.get(&"foo").await.unwrap().value(), &"bar");
+    ///     map.reshard(16).await.unwrap();
+    ///
+    ///     assert_eq!(map.shard_count(), 16);
+    ///     assert_eq!(map.len().await, 1000);
+    ///     assert_eq!(map.get(&500).await.unwrap().value(), &500);
     /// });
     /// ```
-    pub async fn insert(&self, key: K, value: V) -> Option<V> {
-        let (shard, hash) = self.shard(&key);
-        let mut writer = shard.write().await;
+    pub async fn reshard(&self, shards: usize) -> Result<(), BuildError> {
+        let shards = ShardMapBuilder::new().shards(shards).validate_shards()?;
+        let _resharding = self.inner.resharding.write().await;
+
+        loop {
+            let layout = self.inner.layout();
+            let Some(next) = layout.next.get() else {
+                if layout.shards.len() == shards {
+                    return Ok(());
+                }
+                let incremental = self.inner.incremental_resize;
+                layout
+                    .next
+                    .get_or_init(|| Box::new(Layout::new(layout.end_id(), shards, 0, incremental)));
+                continue;
+            };
+
+            for idx in 0..layout.shards.len() {
+                self.migrate(layout, idx, next).await;
+            }
+            self.inner.current.store(
+                ptr::from_ref::<Layout<K, V>>(next).cast_mut(),
+                Ordering::Release,
+            );
+        }
+    }
+
+    /// Moves the entries of shard `idx` of `layout` into the shards of `next` they belong to, and
+    /// retires the shard.
+    async fn migrate(&self, layout: &Layout<K, V>, idx: usize, next: &Layout<K, V>) {
+        let shard = &layout.shards[idx];
+        let range = layout.targets(idx, next);
+        let targets = &next.shards[range.clone()];
+
+        // Nothing is awaited while the gate is held exclusively: operations that hold it shared
+        // may be waiting for the very shard locks this needs. Busy shards are waited for without
+        // the gate instead, and the move is retried.
+        'retry: loop {
+            let gate = self.inner.gate.write().await;
+            if shard.is_retired() {
+                return;
+            }
+
+            let Some(mut from) = shard.try_write() else {
+                drop(gate);
+                drop(shard.write().await);
+                continue;
+            };
+
+            let mut writers = Vec::with_capacity(targets.len());
+            for target in targets {
+                match target.try_write() {
+                    Some(writer) => writers.push(writer),
+                    None => {
+                        drop((gate, from, writers));
+                        drop(target.write().await);
+                        continue 'retry;
+                    }
+                }
+            }
+
+            let hasher = &self.inner.hasher;
+            for writer in &mut writers {
+                writer.reserve(from.len() / targets.len(), |(k, _)| hasher.hash_one(k));
+            }
+            for (key, value) in std::mem::take(&mut *from) {
+                let hash = hasher.hash_one(&key);
+                let target = shard_for_hash(hash as usize, next.shift) - range.start;
+                writers[target].insert_unique(hash, (key, value), |(k, _)| hasher.hash_one(k));
+            }
+            shard.mark_changed();
+            shard.retire();
+            for target in targets {
+                target.mark_changed();
+            }
+            return;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn hash<Q>(&self, key: &Q) -> u64
+    where
+        Q: ?Sized + Hash,
+    {
+        self.inner.hasher.hash_one(key)
+    }
+
+    /// Returns the index of the shard the key belongs to, along with the hash of the key.
+    ///
+    /// Like [`ShardMap::shard_at`], this only considers the shards the map was created with, so it
+    /// must not be used on maps that may have been resharded.
+    #[inline]
+    pub(crate) fn locate<Q>(&self, key: &Q) -> (usize, u64)
+    where
+        Q: ?Sized + Hash,
+    {
+        let hash = self.hash(key);
+
+        (shard_for_hash(hash as usize, self.inner.layout.shift), hash)
+    }
+
+    #[inline]
+    pub(crate) fn shard_at(&self, idx: usize) -> &Shard<K, V> {
+        &self.inner.layout.shards[idx]
+    }
+
+    pub(crate) fn hasher(&self) -> &S {
+        &self.inner.hasher
+    }
+
+    /// Locks the shard that holds the keys with the given hash for reading.
+    pub(crate) async fn read_shard(&self, hash: u64) -> (&Shard<K, V>, ShardReader<'_, K, V>) {
+        self.inner.layout().read_shard(hash).await
+    }
+
+    /// Returns the shard that holds the keys with the given hash, without locking it. The result
+    /// is stable while the returned shard is locked.
+    pub(crate) fn live_shard(&self, hash: u64) -> &Shard<K, V> {
+        self.inner.layout().live_shard(hash)
+    }
 
-        let (old, slot) = match writer.entry(
+    /// Returns the next shard for a stream that visits every live shard. The gate is taken when
+    /// the stream starts, and held until it is dropped.
+    async fn next_shard<'a>(&'a self, shards: &mut Shards<'a, K, V>) -> Option<&'a Shard<K, V>> {
+        if shards.is_none() {
+            let gate = self.inner.gate.read().await;
+            let live: Vec<_> = self.inner.live_shards().collect();
+            *shards = Some((gate, live.into_iter()));
+        }
+        shards.as_mut()?.1.next()
+    }
+
+    /// Locks every shard of a map that is still being built, and so has not been shared yet.
+    pub(crate) fn write_unshared(&self) -> Vec<ShardWriter<'_, K, V>> {
+        self.inner
+            .layout
+            .shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .try_write()
+                    .expect("a newly created map is not shared")
+            })
+            .collect()
+    }
+
+    pub(crate) fn insert_locked(
+        &self,
+        shard: &Shard<K, V>,
+        table: &mut shard::Inner<K, V>,
+        hash: u64,
+        key: K,
+        value: V,
+    ) -> Option<V> {
+        let (old, slot) = match table.entry(
             hash,
             |(k, _)| k == &key,
             |(k, _)| self.inner.hasher.hash_one(k),
@@ -228,37 +772,63 @@ where
             Entry::Vacant(slot) => (None, slot),
         };
 
-        slot.insert((key, value));
+        let (key, value) = slot.insert((key, value)).into_mut();
+        shard.mark_changed();
+        let change = match old {
+            Some(_) => Change::Update(value),
+            None => Change::Insert(value),
+        };
+        self.inner.watchers.notify(shard, hash, key, change);
 
         old
     }
 
-    /// Returns a reference to the value associated with the key.
-    /// If the key is not in the map, `None` is returned.
-    ///
-    /// # Example
-    /// ```
-    /// use tokio::runtime::Runtime;
-    /// use std::sync::Arc;
-    /// use whirlwind::{ShardMap, mapref::MapRef};
-    ///
-    /// let rt = Runtime::new().unwrap();
-    /// let map = Arc::new(ShardMap::new());
-    ///
-    /// rt.block_on(async {
-    ///     map.insert("foo", "bar").await;
-    ///
-    ///     // `get` returns a `MapRef` which holds a read lock on the shard.
-    ///     let entry: MapRef<'_, _, _> = map.get(&"foo").await.unwrap();
-    ///
-    ///     assert_eq!(entry.value(), &"bar");
-    /// });
-    /// ```
-    pub async fn get<'a>(&'a self, key: &'a K) -> Option<MapRef<'a, K, V>> {
-        let (shard, hash) = self.shard(key);
-        let reader = shard.read().await;
+    /// Hashes every key up front and groups the pairs by the shard they belong to, keeping their
+    /// position in the input. Batches are listed in the same order as the shards of
+    /// [`Inner::shards`], and the result is only meaningful while the gate is held.
+    fn batch_by_shard<I>(&self, iter: I) -> Vec<Batch<K, V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let first_id = self.inner.layout().shards[0].id();
+        let mut batches: Vec<Batch<K, V>> = std::iter::repeat_with(Vec::new)
+            .take(self.inner.shards().count())
+            .collect();
+
+        for (idx, (key, value)) in iter.into_iter().enumerate() {
+            let hash = self.hash(&key);
+            let shard = self.inner.layout().live_shard(hash);
+            batches[shard.id() - first_id].push((idx, hash, key, value));
+        }
+
+        batches
+    }
 
-        if let Some((k, v)) = reader.find(hash, |(k, _)| k == key) {
+    fn insert_batch<F>(
+        &self,
+        shard: &Shard<K, V>,
+        table: &mut shard::Inner<K, V>,
+        batch: Batch<K, V>,
+        mut f: F,
+    ) where
+        F: FnMut(usize, Option<V>),
+    {
+        table.reserve(batch.len(), |(k, _)| self.inner.hasher.hash_one(k));
+
+        for (idx, hash, key, value) in batch {
+            f(idx, self.insert_locked(shard, table, hash, key, value));
+        }
+    }
+
+    fn get_locked<'a, Q>(
+        reader: ShardReader<'a, K, V>,
+        hash: u64,
+        key: &Q,
+    ) -> Option<MapRef<'a, K, V>>
+    where
+        Q: ?Sized + Equivalent<K>,
+    {
+        if let Some((k, v)) = reader.find(hash, |(k, _)| key.equivalent(k)) {
             let (k, v) = (k as *const K, v as *const V);
             // SAFETY: The key and value are guaranteed to be valid for the lifetime of the reader.
             unsafe { Some(MapRef::new(reader, &*k, &*v)) }
@@ -267,18 +837,569 @@ where
         }
     }
 
-    /// Returns a mutable reference to the value associated with the key.
-    /// If the key is not in the map, `None` is returned.
+    fn get_mut_locked<'a, Q>(
+        &'a self,
+        shard: &'a Shard<K, V>,
+        mut writer: ShardWriter<'a, K, V>,
+        hash: u64,
+        key: &Q,
+    ) -> Option<MapRefMut<'a, K, V>>
+    where
+        Q: ?Sized + Equivalent<K>,
+    {
+        if let Some((k, v)) = writer.find_mut(hash, |(k, _)| key.equivalent(k)) {
+            let (k, v) = (k as *const K, v as *mut V);
+            let tracker = ChangeTracker::new(&self.inner.watchers, shard, hash);
+            // SAFETY: The key and value are guaranteed to be valid for the lifetime of the writer.
+            unsafe { Some(MapRefMut::new(writer, &*k, &mut *v, tracker)) }
+        } else {
+            None
+        }
+    }
+
+    fn contains_key_locked<Q>(reader: &ShardReader<'_, K, V>, hash: u64, key: &Q) -> bool
+    where
+        Q: ?Sized + Equivalent<K>,
+    {
+        reader.find(hash, |(k, _)| key.equivalent(k)).is_some()
+    }
+
+    pub(crate) fn remove_locked<Q>(
+        &self,
+        shard: &Shard<K, V>,
+        writer: &mut ShardWriter<'_, K, V>,
+        hash: u64,
+        key: &Q,
+    ) -> Option<V>
+    where
+        Q: ?Sized + Equivalent<K>,
+    {
+        match writer.find_entry(hash, |(k, _)| key.equivalent(k)) {
+            Ok(occupied) => {
+                let ((k, v), _) = occupied.remove();
+                shard.mark_changed();
+                self.inner.watchers.notify(shard, hash, &k, Change::Remove);
+                Some(v)
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the pairs with any of the given hashes for which `f` returns `true`, locking each
+    /// shard they belong to once. Returns the number of pairs removed.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn remove_hashes<F>(&self, hashes: &[u64], mut f: F) -> usize
+    where
+        F: FnMut(&V) -> bool,
+    {
+        let _gate = self.inner.gate.read().await;
+        let mut shards: BTreeMap<usize, (&Shard<K, V>, Vec<u64>)> = BTreeMap::new();
+        for &hash in hashes {
+            let shard = self.inner.layout().live_shard(hash);
+            shards
+                .entry(shard.id())
+                .or_insert_with(|| (shard, Vec::new()))
+                .1
+                .push(hash);
+        }
+
+        let mut removed = 0;
+        for (shard, hashes) in shards.into_values() {
+            let mut writer = shard.write().await;
+            for hash in hashes {
+                while let Ok(occupied) = writer.find_entry(hash, |(_, v)| f(v)) {
+                    let ((k, _), _) = occupied.remove();
+                    shard.mark_changed();
+                    self.inner.watchers.notify(shard, hash, &k, Change::Remove);
+                    removed += 1;
+                }
+            }
+        }
+        removed
+    }
+
+    /// Reports that every key in the locked `shard` was removed.
+    fn notify_removed(&self, shard: &Shard<K, V>, table: &shard::Inner<K, V>) {
+        let watchers = &self.inner.watchers;
+        if !watchers.is_empty() {
+            for (k, _) in table.iter() {
+                watchers.notify_key(self.inner.hasher.hash_one(k), k, None);
+            }
+        }
+        watchers.notify_clear(shard);
+    }
+
+    /// Removes every pair from the locked `shard`, reporting the removal.
+    fn clear_locked(&self, shard: &Shard<K, V>, table: &mut shard::Inner<K, V>) {
+        if !table.is_empty() {
+            shard.mark_changed();
+        }
+        self.notify_removed(shard, table);
+        table.clear();
+    }
+
+    /// Retains the pairs of the locked `shard` for which `f` returns `true`, reporting the removed
+    /// keys as removed. Kept values are not reported, even if `f` modified them.
+    fn retain_locked<F>(&self, shard: &Shard<K, V>, table: &mut shard::Inner<K, V>, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        // `f` may modify any value it is handed.
+        if !table.is_empty() {
+            shard.mark_changed();
+        }
+
+        if self.inner.watchers.is_empty() {
+            table.retain(|(k, v)| f(k, v));
+            return;
+        }
+
+        table.retain(|(k, v)| {
+            let keep = f(k, v);
+            if !keep {
+                let hash = self.inner.hasher.hash_one(&*k);
+                self.inner.watchers.notify(shard, hash, k, Change::Remove);
+            }
+            keep
+        });
+    }
+
+    /// Inserts a key-value pair into the map. If the key already exists, the value is updated and
+    /// the old value is returned.
     ///
     /// # Example
     /// ```
     /// use tokio::runtime::Runtime;
     /// use std::sync::Arc;
-    /// use whirlwind::{ShardMap, mapref::MapRefMut};
-    ///
+    /// use whirlwind::ShardMap;
     /// let rt = Runtime::new().unwrap();
     /// let map = Arc::new(ShardMap::new());
-    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", "bar").await;
+    ///
+    ///     assert_eq!(map.get(&"foo").await.unwrap().value(), &"bar");
+    /// });
+    /// ```
+    pub async fn insert(&self, key: K, value: V) -> Option<V> {
+        let hash = self.hash(&key);
+        let (shard, mut writer) = self.inner.layout().write_shard(hash).await;
+
+        self.insert_locked(shard, &mut writer, hash, key, value)
+    }
+
+    /// Inserts all key-value pairs from `iter` into the map, overwriting the values of existing
+    /// keys.
+    ///
+    /// Keys are hashed up front and grouped by shard, so that each shard is locked for writing
+    /// only once no matter how many pairs belong to it.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.extend((0..1000).map(|i| (i, i * 2))).await;
+    ///
+    ///     assert_eq!(map.len().await, 1000);
+    ///     assert_eq!(map.get(&500).await.unwrap().value(), &1000);
+    /// });
+    /// ```
+    pub async fn extend<I>(&self, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let _gate = self.inner.gate.read().await;
+        for (shard, batch) in self.inner.shards().zip(self.batch_by_shard(iter)) {
+            if batch.is_empty() {
+                continue;
+            }
+
+            let mut writer = shard.write().await;
+            self.insert_batch(shard, &mut writer, batch, |_, _| {});
+        }
+    }
+
+    /// Inserts all key-value pairs from `iter` into the map, returning the old value of each key
+    /// in the order the pairs were given.
+    ///
+    /// Like [`ShardMap::extend`], each shard is locked for writing only once. If a key appears
+    /// more than once in `iter`, later pairs see the values inserted by earlier ones.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///
+    ///     let old = map.insert_many([("foo", 2), ("bar", 3), ("bar", 4)]).await;
+    ///
+    ///     assert_eq!(old, [Some(1), None, Some(3)]);
+    /// });
+    /// ```
+    pub async fn insert_many<I>(&self, iter: I) -> Vec<Option<V>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let _gate = self.inner.gate.read().await;
+        let batches = self.batch_by_shard(iter);
+        let mut old: Vec<Option<V>> = std::iter::repeat_with(|| None)
+            .take(batches.iter().map(Vec::len).sum())
+            .collect();
+
+        for (shard, batch) in self.inner.shards().zip(batches) {
+            if batch.is_empty() {
+                continue;
+            }
+
+            let mut writer = shard.write().await;
+            self.insert_batch(shard, &mut writer, batch, |idx, value| old[idx] = value);
+        }
+
+        old
+    }
+
+    /// Gets the entry for the given key for in-place manipulation. The shard associated with the
+    /// key stays locked for writing until the returned [`entry::Entry`] is dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     for word in ["apple", "banana", "apple"] {
+    ///         *map.entry(word).await.or_insert(0) += 1;
+    ///     }
+    ///
+    ///     assert_eq!(map.get(&"apple").await.unwrap().value(), &2);
+    ///     assert_eq!(map.get(&"banana").await.unwrap().value(), &1);
+    /// });
+    /// ```
+    pub async fn entry(&self, key: K) -> entry::Entry<'_, K, V, S> {
+        let hash = self.hash(&key);
+        let (shard, mut writer) = self.inner.layout().write_shard(hash).await;
+
+        match writer.find_mut(hash, |(k, _)| k == &key).map(NonNull::from) {
+            Some(pair) => entry::Entry::Occupied(OccupiedEntry::new(
+                writer,
+                pair,
+                shard,
+                hash,
+                &self.inner.watchers,
+            )),
+            None => entry::Entry::Vacant(VacantEntry::new(
+                writer,
+                key,
+                shard,
+                hash,
+                &self.inner.hasher,
+                &self.inner.watchers,
+            )),
+        }
+    }
+
+    /// Returns a [`KeyWatcher`] that receives an event every time the key is inserted, modified
+    /// or removed.
+    ///
+    /// Events are sent while the key's shard is still locked, so a watcher sees the changes to its
+    /// key in the order they were made. Only the latest event that was not received yet is kept,
+    /// so a watcher that falls behind skips to the newest change. A mutable reference to a value,
+    /// such as a [`MapRefMut`], counts as a change when it is dropped if it was ever used to get
+    /// mutable access to the value, even if the value was not actually modified.
+    ///
+    /// Watching a key does not require it to be in the map. The key stops being watched once all
+    /// of its watchers are dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::{watch::WatchEvent, ShardMap};
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     let mut watcher = map.watch(&"foo");
+    ///
+    ///     map.insert("foo", 1).await;
+    ///     assert_eq!(watcher.recv().await, Some(WatchEvent::Changed(1)));
+    ///
+    ///     map.entry("foo").await.and_modify(|v| *v += 1);
+    ///     map.insert("bar", 1).await;
+    ///     assert_eq!(watcher.recv().await, Some(WatchEvent::Changed(2)));
+    ///     assert_eq!(watcher.try_recv(), None);
+    /// });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn watch(&self, key: &K) -> KeyWatcher<V>
+    where
+        K: Clone + Send + Sync,
+        V: Clone + Send + Sync,
+        S: Send + Sync + 'static,
+    {
+        let hash = self.hash(key);
+        let rx = self.inner.watchers.watch(hash, key.clone());
+
+        let map = Arc::downgrade(&self.inner);
+        let key = key.clone();
+        let unwatch = Box::new(move || {
+            if let Some(map) = map.upgrade() {
+                map.watchers.unwatch(hash, &key);
+            }
+        });
+        KeyWatcher::new(rx, unwatch)
+    }
+
+    /// Returns a [`ChangeStream`] that receives every mutation applied to the map from now on.
+    ///
+    /// Maps start reporting changes once they are first subscribed to, and stop again while they
+    /// have no subscribers, so unobserved maps don't pay for cloning keys and values. See the
+    /// [`crate::changes`] module for the ordering and sequence numbering of events.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::{changes::ChangeKind, ShardMap};
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     let mut changes = map.subscribe();
+    ///
+    ///     map.insert("foo", 1).await;
+    ///     map.insert("foo", 2).await;
+    ///
+    ///     let first = changes.recv().await.unwrap();
+    ///     let second = changes.recv().await.unwrap();
+    ///     assert_eq!(first.kind, ChangeKind::Insert("foo", 1));
+    ///     assert_eq!(second.kind, ChangeKind::Update("foo", 2));
+    ///     assert_eq!(second.seq, first.seq + 1);
+    /// });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn subscribe(&self) -> ChangeStream<K, V>
+    where
+        K: Clone + Send + 'static,
+        V: Clone + Send + 'static,
+    {
+        self.inner.watchers.subscribe()
+    }
+
+    /// Returns a reference to the value associated with the key, loading and inserting it with `f`
+    /// if it is not in the map.
+    ///
+    /// Concurrent callers for the same key share a single load: the first caller runs `f`, and
+    /// the others wait for it to finish instead of running their own loader. No shard lock is held
+    /// while the loader runs. If the loader fails, its error is returned to every caller that was
+    /// waiting on it.
+    ///
+    /// If the caller running the loader is cancelled, one of the waiting callers takes over and
+    /// runs its own `f`. If the key is inserted by other means while the loader runs, the inserted
+    /// value is kept and the loaded one is dropped.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    /// let loads = Arc::new(AtomicUsize::new(0));
+    ///
+    /// rt.block_on(async {
+    ///     let mut tasks = Vec::new();
+    ///     for _ in 0..10 {
+    ///         let (map, loads) = (map.clone(), loads.clone());
+    ///         tasks.push(tokio::spawn(async move {
+    ///             let value = map
+    ///                 .get_or_try_insert_with("config", || async {
+    ///                     loads.fetch_add(1, Ordering::SeqCst);
+    ///                     tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    ///                     Ok::<_, String>(42)
+    ///                 })
+    ///                 .await
+    ///                 .unwrap();
+    ///             *value.value()
+    ///         }));
+    ///     }
+    ///
+    ///     for task in tasks {
+    ///         assert_eq!(task.await.unwrap(), 42);
+    ///     }
+    ///     assert_eq!(loads.load(Ordering::SeqCst), 1);
+    /// });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn get_or_try_insert_with<F, Fut, E>(
+        &self,
+        key: K,
+        f: F,
+    ) -> Result<MapRef<'_, K, V>, E>
+    where
+        K: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+        E: Clone + Send + Sync + 'static,
+    {
+        let hash = self.hash(&key);
+        let mut f = Some(f);
+
+        loop {
+            if let Some(found) =
+                Self::get_locked(self.inner.layout().read_shard(hash).await.1, hash, &key)
+            {
+                return Ok(found);
+            }
+
+            let guard = match self.inner.loads.join(hash, &key) {
+                Join::Leader(guard) => guard,
+                Join::Waiter(rx) => {
+                    if let Some(Err(err)) = loader::wait(rx).await {
+                        if let Some(err) = err.downcast_ref::<E>() {
+                            return Err(err.clone());
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            // A load that finished between the lookup above and registering this one has already
+            // inserted the value.
+            if let Some(found) =
+                Self::get_locked(self.inner.layout().read_shard(hash).await.1, hash, &key)
+            {
+                return Ok(found);
+            }
+
+            let f = f.take().expect("a caller leads at most one load");
+            match f().await {
+                Ok(value) => {
+                    let (shard, mut writer) = self.inner.layout().write_shard(hash).await;
+                    if writer.find(hash, |(k, _)| k == &key).is_none() {
+                        self.insert_locked(shard, &mut writer, hash, key.clone(), value);
+                    }
+                    let reader = Shard::downgrade(writer);
+                    guard.finish(Ok(()));
+
+                    return Ok(Self::get_locked(reader, hash, &key)
+                        .expect("the loaded value is in the map while its shard is locked"));
+                }
+                Err(err) => {
+                    guard.finish(Err(Arc::new(err.clone())));
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Returns a reference to the value associated with the key, loading and inserting it with `f`
+    /// if it is not in the map.
+    ///
+    /// This is the infallible version of [`ShardMap::get_or_try_insert_with`], and shares loads
+    /// between concurrent callers in the same way.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     let value = map.get_or_insert_with("foo", || async { 1 }).await;
+    ///     assert_eq!(value.value(), &1);
+    ///     drop(value);
+    ///
+    ///     let value = map.get_or_insert_with("foo", || async { 2 }).await;
+    ///     assert_eq!(value.value(), &1);
+    /// });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, f: F) -> MapRef<'_, K, V>
+    where
+        K: Clone,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let loaded = self
+            .get_or_try_insert_with(key, || async {
+                Ok::<_, std::convert::Infallible>(f().await)
+            })
+            .await;
+
+        match loaded {
+            Ok(found) => found,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Returns a reference to the value associated with the key.
+    /// If the key is not in the map, `None` is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::{ShardMap, mapref::MapRef};
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", "bar").await;
+    ///
+    ///     // `get` returns a `MapRef` which holds a read lock on the shard.
+    ///     let entry: MapRef<'_, _, _> = map.get(&"foo").await.unwrap();
+    ///
+    ///     assert_eq!(entry.value(), &"bar");
+    /// });
+    /// ```
+    pub async fn get<'a, Q>(&'a self, key: &Q) -> Option<MapRef<'a, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self.inner.layout().read_shard(hash).await;
+
+        Self::get_locked(reader, hash, key)
+    }
+
+    /// Returns a mutable reference to the value associated with the key.
+    /// If the key is not in the map, `None` is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::{ShardMap, mapref::MapRefMut};
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
     /// rt.block_on(async {
     ///     map.insert("foo", "bar").await;
     ///
@@ -289,23 +1410,574 @@ where
     ///     assert_eq!(entry.value(), &"baz");
     ///     drop(entry);
     ///
-    ///     assert_eq!(map.get(&"foo").await.unwrap().value(), &"baz");
+    ///     assert_eq!(map.get(&"foo").await.unwrap().value(), &"baz");
+    /// });
+    /// ```
+    pub async fn get_mut<'a, Q>(&'a self, key: &Q) -> Option<MapRefMut<'a, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, writer) = self.inner.layout().write_shard(hash).await;
+
+        self.get_mut_locked(shard, writer, hash, key)
+    }
+
+    /// Returns an owned reference to the value associated with the key.
+    /// If the key is not in the map, `None` is returned.
+    ///
+    /// Unlike [`ShardMap::get`], the returned [`OwnedMapRef`] does not borrow the map, so it can
+    /// be moved into a spawned task or stored in a struct.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", "bar").await;
+    ///
+    ///     let entry = map.get_owned(&"foo").await.unwrap();
+    ///     let value = tokio::spawn(async move { *entry.value() }).await.unwrap();
+    ///
+    ///     assert_eq!(value, "bar");
+    /// });
+    /// ```
+    pub async fn get_owned<Q>(&self, key: &Q) -> Option<OwnedMapRef<K, V, S>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self.inner.layout().read_shard_owned(hash).await;
+        let slot = reader.find_slot(hash, |(k, _)| key.equivalent(k))?;
+
+        Some(OwnedMapRef::new(self.inner.clone(), reader, slot))
+    }
+
+    /// Returns an owned mutable reference to the value associated with the key.
+    /// If the key is not in the map, `None` is returned.
+    ///
+    /// Unlike [`ShardMap::get_mut`], the returned [`OwnedMapRefMut`] does not borrow the map, so
+    /// it can be moved into a spawned task or stored in a struct.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///
+    ///     let mut entry = map.get_mut_owned(&"foo").await.unwrap();
+    ///     tokio::spawn(async move { *entry.value_mut() += 1 }).await.unwrap();
+    ///
+    ///     assert_eq!(map.get(&"foo").await.unwrap().value(), &2);
+    /// });
+    /// ```
+    pub async fn get_mut_owned<Q>(&self, key: &Q) -> Option<OwnedMapRefMut<K, V, S>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, writer) = self.inner.layout().write_shard_owned(hash).await;
+        let slot = writer.find_slot(hash, |(k, _)| key.equivalent(k))?;
+
+        Some(OwnedMapRefMut::new(
+            self.inner.clone(),
+            writer,
+            slot,
+            shard.id(),
+            hash,
+        ))
+    }
+
+    /// Returns mutable references to the values associated with several keys at once.
+    ///
+    /// The shards associated with the keys are locked for writing in ascending order, so
+    /// concurrent calls can never deadlock regardless of the order in which keys are given. Keys
+    /// that belong to the same shard share a single lock.
+    ///
+    /// Returns `None` if any of the keys is not in the map, or if any two keys refer to the same
+    /// entry.
+    ///
+    /// The keys may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("alice", 100).await;
+    ///     map.insert("bob", 50).await;
+    ///
+    ///     let mut accounts = map.get_many_mut([&"alice", &"bob"]).await.unwrap();
+    ///     let [alice, bob] = accounts.values_mut();
+    ///     *alice -= 30;
+    ///     *bob += 30;
+    ///     drop(accounts);
+    ///
+    ///     assert_eq!(map.get(&"alice").await.unwrap().value(), &70);
+    ///     assert_eq!(map.get(&"bob").await.unwrap().value(), &80);
+    ///
+    ///     assert!(map.get_many_mut([&"alice", &"alice"]).await.is_none());
+    ///     assert!(map.get_many_mut([&"alice", &"carol"]).await.is_none());
+    /// });
+    /// ```
+    pub async fn get_many_mut<'a, Q, const N: usize>(
+        &'a self,
+        keys: [&Q; N],
+    ) -> Option<MapRefManyMut<'a, K, V, [(&'a K, &'a mut V); N]>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (writers, pairs, trackers) = self.lock_many(&keys).await?;
+        let pairs = pairs
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("one pair is found per key"));
+
+        Some(MapRefManyMut::new(writers, pairs, trackers))
+    }
+
+    /// Returns mutable references to the values associated with a slice of keys.
+    ///
+    /// This behaves like [`ShardMap::get_many_mut`], for a number of keys that is not known at
+    /// compile time.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     for i in 0..10 {
+    ///         map.insert(i, i).await;
+    ///     }
+    ///
+    ///     let keys: Vec<_> = (0..10).filter(|i| i % 2 == 0).collect();
+    ///     let keys: Vec<_> = keys.iter().collect();
+    ///     let mut values = map.get_many_mut_vec(&keys).await.unwrap();
+    ///     for value in values.values_mut() {
+    ///         *value *= 10;
+    ///     }
+    ///     drop(values);
+    ///
+    ///     assert_eq!(map.get(&4).await.unwrap().value(), &40);
+    ///     assert_eq!(map.get(&5).await.unwrap().value(), &5);
+    /// });
+    /// ```
+    pub async fn get_many_mut_vec<'a, Q>(&'a self, keys: &[&Q]) -> Option<MapRefManyMut<'a, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (writers, pairs, trackers) = self.lock_many(keys).await?;
+
+        Some(MapRefManyMut::new(writers, pairs, trackers))
+    }
+
+    /// Locks the distinct shards of `keys` in ascending order and looks up each key, along with a
+    /// tracker for each pair.
+    async fn lock_many<'a, Q>(
+        &'a self,
+        keys: &[&Q],
+    ) -> Option<(
+        Vec<ShardWriter<'a, K, V>>,
+        PairsMut<'a, K, V>,
+        Vec<ChangeTracker<'a, K, V>>,
+    )>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let gate = self.inner.gate.read().await;
+        let located: Vec<(&Shard<K, V>, u64)> = keys
+            .iter()
+            .map(|key| {
+                let hash = self.hash(key);
+                (self.inner.layout().live_shard(hash), hash)
+            })
+            .collect();
+
+        let mut shards: Vec<&Shard<K, V>> = located.iter().map(|&(shard, _)| shard).collect();
+        shards.sort_unstable_by_key(|shard| shard.id());
+        shards.dedup_by_key(|shard| shard.id());
+
+        let mut writers = Vec::with_capacity(shards.len());
+        for shard in &shards {
+            writers.push(shard.write().await);
+        }
+        // The locked shards cannot be retired, so the gate is no longer needed.
+        drop(gate);
+
+        let trackers = located
+            .iter()
+            .map(|&(shard, hash)| ChangeTracker::new(&self.inner.watchers, shard, hash))
+            .collect();
+
+        let mut pairs = Vec::with_capacity(keys.len());
+        for (key, (shard, hash)) in keys.iter().zip(located) {
+            let idx = shards.binary_search_by_key(&shard.id(), |shard| shard.id());
+            let writer = &mut writers[idx.ok()?];
+            let pair = writer.find_mut(hash, |(k, _)| key.equivalent(k))?;
+            pairs.push(pair as *mut (K, V));
+        }
+
+        // Handing out two mutable references to the same entry would be unsound.
+        let mut sorted = pairs.clone();
+        sorted.sort_unstable();
+        if sorted.windows(2).any(|w| w[0] == w[1]) {
+            return None;
+        }
+
+        let pairs = pairs
+            .into_iter()
+            .map(|pair| {
+                // SAFETY: Each pair is distinct, and is valid for the lifetime of its writer.
+                let (k, v) = unsafe { &mut *pair };
+                (&*k, v)
+            })
+            .collect();
+
+        Some((writers, pairs, trackers))
+    }
+
+    /// Runs `f` as a transaction, committing all of its writes atomically.
+    ///
+    /// The closure is given a [`Transaction`] handle to read and write arbitrary keys through.
+    /// Writes are buffered, and are applied to the map all at once when the closure returns `Ok`.
+    /// If the closure returns `Err`, the transaction is aborted, none of its writes are applied,
+    /// and the error is returned as [`TransactionError::Aborted`].
+    ///
+    /// Before committing or aborting, every shard the transaction read from is checked for writes
+    /// made by others since it was read. If there were any, the closure saw a torn view of the map
+    /// and is run again from scratch, so it may be called more than once and should not have side
+    /// effects outside of the transaction. Retries back off by yielding to the executor, and after
+    /// [`transaction::MAX_ATTEMPTS`] conflicting attempts the transaction fails with
+    /// [`TransactionError::Conflict`]. Note that any write to a shard, including through
+    /// [`ShardMap::get_mut`], counts as a conflict for transactions that read from that shard.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("alice", 100).await;
+    ///
+    ///     let moved = map
+    ///         .transaction(|tx| async move {
+    ///             let alice = tx.get(&"alice").await.unwrap_or(0);
+    ///             if alice < 30 {
+    ///                 return Err("insufficient funds");
+    ///             }
+    ///             let bob = tx.get(&"bob").await.unwrap_or(0);
+    ///             tx.insert("alice", alice - 30);
+    ///             tx.insert("bob", bob + 30);
+    ///             Ok(30)
+    ///         })
+    ///         .await;
+    ///
+    ///     assert_eq!(moved, Ok(30));
+    ///     assert_eq!(map.get(&"bob").await.unwrap().value(), &30);
+    /// });
+    /// ```
+    pub async fn transaction<F, Fut, T, E>(&self, mut f: F) -> Result<T, TransactionError<E>>
+    where
+        F: FnMut(Transaction<K, V, S>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        for attempt in 0..transaction::MAX_ATTEMPTS {
+            if attempt > 0 {
+                transaction::backoff(attempt - 1).await;
+            }
+
+            let tx = Transaction::new(self.clone());
+            let result = f(tx.clone()).await;
+            let state = tx.finish();
+
+            match result {
+                Ok(value) => {
+                    if self.commit(state).await {
+                        return Ok(value);
+                    }
+                }
+                Err(err) => {
+                    if self.validate(&state).await {
+                        return Err(TransactionError::Aborted(err));
+                    }
+                }
+            }
+        }
+
+        Err(TransactionError::Conflict)
+    }
+
+    /// Checks that none of the shards a transaction read from have changed since, which means
+    /// that everything it read was in the map at once, just after its last read. Returns `false`
+    /// if the transaction conflicted.
+    async fn validate(&self, state: &TxState<K, V>) -> bool {
+        if state.conflict {
+            return false;
+        }
+
+        for (&id, &version) in &state.reads {
+            let Some(shard) = self.inner.shard_by_id(id) else {
+                return false;
+            };
+            let _reader = shard.read().await;
+            if shard.is_retired() || shard.version() != version {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Locks every shard touched by a transaction in ascending order, and applies its writes if
+    /// none of the shards it read from have changed. Returns `false` if the transaction conflicted.
+    async fn commit(&self, state: TxState<K, V>) -> bool {
+        if state.conflict {
+            return false;
+        }
+
+        let _gate = self.inner.gate.read().await;
+        let mut shards = BTreeMap::new();
+        let mut batches: BTreeMap<usize, Vec<(u64, K, Option<V>)>> = BTreeMap::new();
+        for (hash, key, value) in state.writes {
+            let shard = self.inner.layout().live_shard(hash);
+            shards.insert(shard.id(), shard);
+            batches
+                .entry(shard.id())
+                .or_default()
+                .push((hash, key, value));
+        }
+
+        for &id in state.reads.keys() {
+            // The keys read from a shard that was retired since may have moved anywhere.
+            match self
+                .inner
+                .shard_by_id(id)
+                .filter(|shard| !shard.is_retired())
+            {
+                Some(shard) => shards.insert(id, shard),
+                None => return false,
+            };
+        }
+
+        let mut readers = Vec::new();
+        let mut writers = Vec::with_capacity(batches.len());
+        for (id, shard) in shards {
+            let read_at = state.reads.get(&id).copied();
+
+            if batches.contains_key(&id) {
+                let writer = shard.write().await;
+                if read_at.is_some_and(|version| shard.version() != version) {
+                    return false;
+                }
+                writers.push((shard, writer));
+            } else {
+                let reader = shard.read().await;
+                if read_at != Some(shard.version()) {
+                    return false;
+                }
+                readers.push(reader);
+            }
+        }
+
+        for (shard, mut writer) in writers {
+            for (hash, key, value) in batches.remove(&shard.id()).unwrap_or_default() {
+                match value {
+                    Some(value) => {
+                        self.insert_locked(shard, &mut writer, hash, key, value);
+                    }
+                    None => {
+                        self.remove_locked(shard, &mut writer, hash, &key);
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Returns `true` if the map contains the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", "bar").await;
+    ///
+    ///     assert_eq!(map.contains_key(&"foo").await, true);
+    ///
+    ///     assert_eq!(map.contains_key(&"bar").await, false);
+    /// });
+    /// ```
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self.inner.layout().read_shard(hash).await;
+
+        Self::contains_key_locked(&reader, hash, key)
+    }
+
+    /// Removes a key from the map and returns the value associated with the key.
+    /// If the key is not in the map, `None` is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", "bar").await;
+    ///
+    ///     assert_eq!(map.contains_key(&"foo").await, true);
+    ///
+    ///     let value = map.remove(&"foo").await;
+    ///
+    ///     assert_eq!(value, Some("bar"));
+    ///
+    ///     assert_eq!(map.contains_key(&"foo").await, false);
+    /// });
+    /// ```
+    pub async fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, mut writer) = self.inner.layout().write_shard(hash).await;
+
+        self.remove_locked(shard, &mut writer, hash, key)
+    }
+
+    /// Attempts to insert a key-value pair into the map without waiting for the shard lock. If the
+    /// key already exists, the value is updated and the old value is returned.
+    ///
+    /// If the shard associated with the key is currently locked, the key and value are handed back
+    /// in a [`WouldBlock`] error.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     assert_eq!(map.try_insert("foo", "bar"), Ok(None));
+    ///
+    ///     let guard = map.get(&"foo").await.unwrap();
+    ///     let err = map.try_insert("foo", "baz").unwrap_err();
+    ///     assert_eq!(err.into_inner(), ("foo", "baz"));
+    ///     drop(guard);
+    ///
+    ///     assert_eq!(map.try_insert("foo", "baz"), Ok(Some("bar")));
+    /// });
+    /// ```
+    pub fn try_insert(&self, key: K, value: V) -> Result<Option<V>, WouldBlock<(K, V)>> {
+        let hash = self.hash(&key);
+        let Some((shard, mut writer)) = self.inner.layout().lock_shard(hash, Shard::try_write)
+        else {
+            return Err(WouldBlock((key, value)));
+        };
+
+        Ok(self.insert_locked(shard, &mut writer, hash, key, value))
+    }
+
+    /// Attempts to get a reference to the value associated with the key without waiting for the
+    /// shard lock. If the key is not in the map, `Ok(None)` is returned.
+    ///
+    /// If the shard associated with the key is currently locked for writing, [`WouldBlock`] is
+    /// returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", "bar").await;
+    ///
+    ///     assert_eq!(map.try_get(&"foo").unwrap().unwrap().value(), &"bar");
+    ///
+    ///     let guard = map.get_mut(&"foo").await.unwrap();
+    ///     assert!(map.try_get(&"foo").is_err());
+    ///     drop(guard);
     /// });
     /// ```
-    pub async fn get_mut<'a>(&'a self, key: &'a K) -> Option<MapRefMut<'a, K, V>> {
-        let (shard, hash) = self.shard(key);
-        let mut writer = shard.write().await;
+    pub fn try_get<Q>(&self, key: &Q) -> Result<Option<MapRef<'_, K, V>>, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self
+            .inner
+            .layout()
+            .lock_shard(hash, Shard::try_read)
+            .ok_or(WouldBlock(()))?;
 
-        if let Some((k, v)) = writer.find_mut(hash, |(k, _)| k == key) {
-            let (k, v) = (k as *const K, v as *mut V);
-            // SAFETY: The key and value are guaranteed to be valid for the lifetime of the writer.
-            unsafe { Some(MapRefMut::new(writer, &*k, &mut *v)) }
-        } else {
-            None
-        }
+        Ok(Self::get_locked(reader, hash, key))
     }
 
-    /// Returns `true` if the map contains the key.
+    /// Attempts to get a mutable reference to the value associated with the key without waiting
+    /// for the shard lock. If the key is not in the map, `Ok(None)` is returned.
+    ///
+    /// If the shard associated with the key is currently locked, [`WouldBlock`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
     ///
     /// # Example
     /// ```
@@ -317,23 +1989,57 @@ where
     /// let map = Arc::new(ShardMap::new());
     ///
     /// rt.block_on(async {
-    ///     map.insert("foo", "bar").await;
+    ///     map.insert("foo", 1).await;
     ///
-    ///     assert_eq!(map.contains_key(&"foo").await, true);
+    ///     *map.try_get_mut(&"foo").unwrap().unwrap() += 1;
     ///
-    ///     assert_eq!(map.contains_key(&"bar").await, false);
+    ///     let guard = map.get(&"foo").await.unwrap();
+    ///     assert!(map.try_get_mut(&"foo").is_err());
+    ///     assert_eq!(guard.value(), &2);
     /// });
     /// ```
-    pub async fn contains_key(&self, key: &K) -> bool {
-        let (shard, hash) = self.shard(key);
+    pub fn try_get_mut<Q>(&self, key: &Q) -> Result<Option<MapRefMut<'_, K, V>>, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, writer) = self
+            .inner
+            .layout()
+            .lock_shard(hash, Shard::try_write)
+            .ok_or(WouldBlock(()))?;
+
+        Ok(self.get_mut_locked(shard, writer, hash, key))
+    }
 
-        let reader = shard.read().await;
+    /// Attempts to check whether the map contains the key without waiting for the shard lock.
+    ///
+    /// If the shard associated with the key is currently locked for writing, [`WouldBlock`] is
+    /// returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub fn try_contains_key<Q>(&self, key: &Q) -> Result<bool, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self
+            .inner
+            .layout()
+            .lock_shard(hash, Shard::try_read)
+            .ok_or(WouldBlock(()))?;
 
-        reader.find(hash, |(k, _)| k == key).is_some()
+        Ok(Self::contains_key_locked(&reader, hash, key))
     }
 
-    /// Removes a key from the map and returns the value associated with the key.
-    /// If the key is not in the map, `None` is returned.
+    /// Attempts to remove a key from the map without waiting for the shard lock, returning the
+    /// value associated with the key if it was present.
+    ///
+    /// If the shard associated with the key is currently locked, [`WouldBlock`] is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
     ///
     /// # Example
     /// ```
@@ -347,24 +2053,236 @@ where
     /// rt.block_on(async {
     ///     map.insert("foo", "bar").await;
     ///
-    ///     assert_eq!(map.contains_key(&"foo").await, true);
+    ///     let guard = map.get(&"foo").await.unwrap();
+    ///     assert!(map.try_remove(&"foo").is_err());
+    ///     drop(guard);
     ///
-    ///     let value = map.remove(&"foo").await;
+    ///     assert_eq!(map.try_remove(&"foo"), Ok(Some("bar")));
+    /// });
+    /// ```
+    pub fn try_remove<Q>(&self, key: &Q) -> Result<Option<V>, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, mut writer) = self
+            .inner
+            .layout()
+            .lock_shard(hash, Shard::try_write)
+            .ok_or(WouldBlock(()))?;
+
+        Ok(self.remove_locked(shard, &mut writer, hash, key))
+    }
+
+    /// Attempts to take a [`Snapshot`] of the whole map without waiting for any lock.
     ///
-    ///     assert_eq!(value, Some("bar"));
+    /// If a shard is currently write-locked, or the map is being resharded, [`WouldBlock`] is
+    /// returned.
     ///
-    ///     assert_eq!(map.contains_key(&"foo").await, false);
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///     assert_eq!(map.try_snapshot().unwrap().get(&"foo"), Some(&1));
+    ///
+    ///     let guard = map.get_mut(&"foo").await.unwrap();
+    ///     assert!(map.try_snapshot().is_err());
+    ///     drop(guard);
     /// });
     /// ```
-    pub async fn remove(&self, key: &K) -> Option<V> {
-        let (shard, hash) = self.shard(key);
+    pub fn try_snapshot(&self) -> Result<Snapshot<'_, K, V, S>, WouldBlock> {
+        let _gate = self.inner.gate.try_read().ok_or(WouldBlock(()))?;
+        let readers = self
+            .inner
+            .live_shards()
+            .map(|shard| Some((shard, shard.try_read()?)))
+            .collect::<Option<_>>()
+            .ok_or(WouldBlock(()))?;
+        Ok(Snapshot::new(self, readers))
+    }
 
-        match shard.write().await.find_entry(hash, |(k, _)| k == key) {
-            Ok(occupied) => {
-                let ((_, v), _) = occupied.remove();
-                Some(v)
-            }
-            _ => None,
+    /// Inserts a key-value pair into the map, blocking the current thread until the shard lock is
+    /// acquired. If the key already exists, the value is updated and the old value is returned.
+    ///
+    /// This is intended for synchronous code, such as plain threads or FFI callbacks, that shares
+    /// the map with asynchronous code.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::ShardMap;
+    ///
+    /// let map = ShardMap::new();
+    ///
+    /// let handle = std::thread::spawn({
+    ///     let map = map.clone();
+    ///     move || map.blocking_insert("foo", "bar")
+    /// });
+    /// assert_eq!(handle.join().unwrap(), None);
+    ///
+    /// assert_eq!(map.blocking_get(&"foo").unwrap().value(), &"bar");
+    /// ```
+    pub fn blocking_insert(&self, key: K, value: V) -> Option<V> {
+        let hash = self.hash(&key);
+        let (shard, mut writer) = self.inner.layout().blocking_write_shard(hash);
+
+        self.insert_locked(shard, &mut writer, hash, key, value)
+    }
+
+    /// Returns a reference to the value associated with the key, blocking the current thread until
+    /// the shard lock is acquired. If the key is not in the map, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_get<Q>(&self, key: &Q) -> Option<MapRef<'_, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self.inner.layout().blocking_read_shard(hash);
+
+        Self::get_locked(reader, hash, key)
+    }
+
+    /// Returns a mutable reference to the value associated with the key, blocking the current
+    /// thread until the shard lock is acquired. If the key is not in the map, `None` is returned.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_get_mut<Q>(&self, key: &Q) -> Option<MapRefMut<'_, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, writer) = self.inner.layout().blocking_write_shard(hash);
+
+        self.get_mut_locked(shard, writer, hash, key)
+    }
+
+    /// Returns `true` if the map contains the key, blocking the current thread until the shard
+    /// lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (_, reader) = self.inner.layout().blocking_read_shard(hash);
+
+        Self::contains_key_locked(&reader, hash, key)
+    }
+
+    /// Removes a key from the map and returns the value associated with the key, blocking the
+    /// current thread until the shard lock is acquired. If the key is not in the map, `None` is
+    /// returned.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.hash(key);
+        let (shard, mut writer) = self.inner.layout().blocking_write_shard(hash);
+
+        self.remove_locked(shard, &mut writer, hash, key)
+    }
+
+    /// Returns the number of elements in the map, blocking the current thread while each shard
+    /// lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_len(&self) -> usize {
+        let _gate = self.inner.gate.blocking_read();
+        self.inner
+            .live_shards()
+            .map(|shard| shard.blocking_read().len())
+            .sum()
+    }
+
+    /// Returns `true` if the map is empty, blocking the current thread while each shard lock is
+    /// acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_is_empty(&self) -> bool {
+        self.blocking_len() == 0
+    }
+
+    /// Returns a [`Snapshot`] of the whole map at a single point in time, blocking the current
+    /// thread while the shard locks are acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_snapshot(&self) -> Snapshot<'_, K, V, S> {
+        let _gate = self.inner.gate.blocking_read();
+        let readers = self
+            .inner
+            .live_shards()
+            .map(|shard| (shard, shard.blocking_read()))
+            .collect();
+        Snapshot::new(self, readers)
+    }
+
+    /// Clears the map, removing all key-value pairs, blocking the current thread while each shard
+    /// lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_clear(&self) {
+        let _gate = self.inner.gate.blocking_read();
+        for shard in self.inner.live_shards() {
+            self.clear_locked(shard, &mut shard.blocking_write());
+        }
+    }
+
+    /// Retains only the key-value pairs for which `f` returns `true`, blocking the current thread
+    /// while each shard lock is acquired.
+    ///
+    /// Only removals are reported to watchers, change feeds and the persistence log. Values that
+    /// `f` modifies in place and keeps are not, so such edits should go through
+    /// [`ShardMap::get_mut`] instead when anyone observes the map.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let _gate = self.inner.gate.blocking_read();
+        for shard in self.inner.live_shards() {
+            self.retain_locked(shard, &mut shard.blocking_write(), &mut f);
         }
     }
 
@@ -387,8 +2305,9 @@ where
     /// });
     /// ```
     pub async fn len(&self) -> usize {
+        let _gate = self.inner.gate.read().await;
         let mut sum = 0;
-        for shard in self.inner.iter() {
+        for shard in self.inner.live_shards() {
             sum += shard.read().await.len();
         }
         sum
@@ -421,6 +2340,40 @@ where
         self.len().await == 0
     }
 
+    /// Returns a [`Snapshot`] of the whole map at a single point in time.
+    ///
+    /// Unlike [`ShardMap::len`] or [`ShardMap::iter`], which visit the shards one after another,
+    /// this locks every shard for reading (in ascending order, like every other operation that
+    /// locks several shards) before looking at any of them. The snapshot therefore reflects
+    /// exactly the writes that completed before it was taken. Writes to the map wait until the
+    /// snapshot is dropped, so it should not be held for long.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = ShardMap::new();
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("alice", 70).await;
+    ///     map.insert("bob", 80).await;
+    ///
+    ///     let snapshot = map.snapshot().await;
+    ///     assert_eq!(snapshot.values().sum::<i32>(), 150);
+    ///     assert_eq!(map.get(&"bob").await.unwrap().value(), &80);
+    /// });
+    /// ```
+    pub async fn snapshot(&self) -> Snapshot<'_, K, V, S> {
+        let _gate = self.inner.gate.read().await;
+        let mut readers = Vec::new();
+        for shard in self.inner.live_shards() {
+            readers.push((shard, shard.read().await));
+        }
+        Snapshot::new(self, readers)
+    }
+
     /// Clears the map, removing all key-value pairs.
     ///
     /// # Example
@@ -444,8 +2397,333 @@ where
     ///    assert_eq!(map.is_empty().await, true);
     /// });
     pub async fn clear(&self) {
-        for shard in self.inner.iter() {
-            shard.write().await.clear();
+        let _gate = self.inner.gate.read().await;
+        for shard in self.inner.live_shards() {
+            self.clear_locked(shard, &mut *shard.write().await);
+        }
+    }
+
+    /// Retains only the key-value pairs for which `f` returns `true`, removing all others.
+    ///
+    /// Each shard is locked for writing in turn while `f` is called on its entries.
+    ///
+    /// Only removals are reported to watchers, change feeds and the persistence log. Values that
+    /// `f` modifies in place and keeps are not, so such edits should go through
+    /// [`ShardMap::get_mut`] instead when anyone observes the map.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     for i in 0..10 {
+    ///         map.insert(i, i).await;
+    ///     }
+    ///
+    ///     map.retain(|k, _| k % 2 == 0).await;
+    ///
+    ///     assert_eq!(map.len().await, 5);
+    ///     assert_eq!(map.contains_key(&1).await, false);
+    /// });
+    /// ```
+    pub async fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        let _gate = self.inner.gate.read().await;
+        for shard in self.inner.live_shards() {
+            self.retain_locked(shard, &mut *shard.write().await, &mut f);
+        }
+    }
+
+    /// Retains only the key-value pairs for which the future returned by `f` resolves to `true`,
+    /// removing all others.
+    ///
+    /// The future may borrow the key and value it was created for, and modify the value. It is
+    /// boxed so that its type can depend on those borrows, which usually just means wrapping an
+    /// `async move` block in [`Box::pin`].
+    ///
+    /// Each shard is locked for writing in turn, and stays locked while the futures for all of its
+    /// entries are awaited one after another, so every other access to that shard waits until
+    /// they are done. The futures should therefore be short, and must not access the map
+    /// themselves, which deadlocks as soon as they touch the shard being retained.
+    ///
+    /// Only removals are reported to watchers, change feeds and the persistence log. Values that
+    /// `f` modifies in place and keeps are not, so such edits should go through
+    /// [`ShardMap::get_mut`] instead when anyone observes the map.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     for i in 0..10 {
+    ///         map.insert(i, i).await;
+    ///     }
+    ///
+    ///     map.retain_async(|_, v| Box::pin(async move {
+    ///         tokio::task::yield_now().await;
+    ///         *v >= 5
+    ///     })).await;
+    ///
+    ///     assert_eq!(map.len().await, 5);
+    ///     assert_eq!(map.contains_key(&4).await, false);
+    /// });
+    /// ```
+    pub async fn retain_async<F>(&self, mut f: F)
+    where
+        F: for<'a> FnMut(&'a K, &'a mut V) -> BoxFuture<'a, bool>,
+    {
+        let _gate = self.inner.gate.read().await;
+        for shard in self.inner.live_shards() {
+            let mut writer = shard.write().await;
+            // `f` may modify any value it is handed.
+            if !writer.is_empty() {
+                shard.mark_changed();
+            }
+
+            // Entries don't move while the shard is locked, so their addresses identify them.
+            let mut removed = Vec::new();
+            for pair in writer.iter_mut() {
+                if !f(&pair.0, &mut pair.1).await {
+                    removed.push(&pair.0 as *const K as usize);
+                }
+            }
+
+            if !removed.is_empty() {
+                removed.sort_unstable();
+                self.retain_locked(shard, &mut writer, |k, _| {
+                    removed.binary_search(&(k as *const K as usize)).is_err()
+                });
+            }
         }
     }
+
+    /// Returns a [`Stream`] over all key-value pairs in the map.
+    ///
+    /// Shards are visited one at a time, and only the shard currently being iterated is locked
+    /// for reading. Each yielded [`MapRefMulti`] shares that lock, so holding on to items will
+    /// keep their shard locked after the stream has moved on.
+    ///
+    /// Since shards are locked one after another, the stream does not observe a consistent view
+    /// of the whole map when it is concurrently modified.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use futures_util::StreamExt;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///     map.insert("bar", 2).await;
+    ///
+    ///     let sum = map.iter().fold(0, |acc, r| async move { acc + *r.value() }).await;
+    ///
+    ///     assert_eq!(sum, 3);
+    /// });
+    /// ```
+    pub fn iter<'a>(&'a self) -> impl Stream<Item = MapRefMulti<'a, K, V>> + 'a {
+        stream::unfold(
+            (None, None),
+            move |(mut shards, mut cursor): (Shards<'a, K, V>, ReadCursor<'a, K, V>)| async move {
+                loop {
+                    if let Some((iter, reader)) = cursor.as_mut() {
+                        if let Some((k, v)) = iter.next() {
+                            let item = MapRefMulti::new(Arc::clone(reader), k, v);
+                            return Some((item, (shards, cursor)));
+                        }
+                    }
+                    // Release the previous shard before locking the next one.
+                    drop(cursor.take());
+
+                    let shard = self.next_shard(&mut shards).await?;
+                    let reader = Arc::new(shard.read().await);
+                    // SAFETY: The table lives in the shard, which outlives `'a`, and stays
+                    // locked for as long as `reader` (or any item sharing it) is alive.
+                    let table = unsafe { &*(&**reader as *const shard::Inner<K, V>) };
+                    cursor = Some((table.iter(), reader));
+                }
+            },
+        )
+    }
+
+    /// Returns a [`Stream`] over all key-value pairs in the map, with mutable references to the
+    /// values.
+    ///
+    /// Shards are visited one at a time, and only the shard currently being iterated is locked
+    /// for writing. Each yielded [`MapRefMutMulti`] shares that lock, so holding on to items will
+    /// keep their shard locked after the stream has moved on.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use futures_util::StreamExt;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///     map.insert("bar", 2).await;
+    ///
+    ///     map.iter_mut().for_each(|mut r| async move { *r.value_mut() *= 10 }).await;
+    ///
+    ///     assert_eq!(map.get(&"foo").await.unwrap().value(), &10);
+    ///     assert_eq!(map.get(&"bar").await.unwrap().value(), &20);
+    /// });
+    /// ```
+    pub fn iter_mut<'a>(&'a self) -> impl Stream<Item = MapRefMutMulti<'a, K, V>> + 'a {
+        stream::unfold(
+            (None, None),
+            move |(mut shards, mut cursor): (Shards<'a, K, V>, WriteCursor<'a, K, V>)| async move {
+                loop {
+                    if let Some((shard, iter, writer)) = cursor.as_mut() {
+                        if let Some((k, v)) = iter.next() {
+                            let tracker = if self.inner.watchers.is_empty() {
+                                ChangeTracker::untracked(shard)
+                            } else {
+                                let hash = self.hash(&*k);
+                                ChangeTracker::new(&self.inner.watchers, shard, hash)
+                            };
+                            let item = MapRefMutMulti::new(Arc::clone(writer), k, v, tracker);
+                            return Some((item, (shards, cursor)));
+                        }
+                    }
+                    // Release the previous shard before locking the next one.
+                    drop(cursor.take());
+
+                    let shard = self.next_shard(&mut shards).await?;
+                    let mut writer = shard.write().await;
+                    let table = &mut *writer as *mut shard::Inner<K, V>;
+                    let writer = Arc::new(writer);
+                    // SAFETY: The table lives in the shard, which outlives `'a`, and stays
+                    // locked for as long as `writer` (or any item sharing it) is alive. Each
+                    // entry is yielded exactly once, so the mutable references never alias.
+                    cursor = Some((shard, unsafe { (*table).iter_mut() }, writer));
+                }
+            },
+        )
+    }
+
+    /// Returns a [`Stream`] over all keys in the map.
+    ///
+    /// See [`ShardMap::iter`] for details on locking.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use futures_util::StreamExt;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///     map.insert("bar", 2).await;
+    ///
+    ///     let mut keys: Vec<_> = map.keys().map(|k| *k).collect().await;
+    ///     keys.sort();
+    ///
+    ///     assert_eq!(keys, ["bar", "foo"]);
+    /// });
+    /// ```
+    pub fn keys<'a>(&'a self) -> impl Stream<Item = KeyRef<'a, K, V>> + 'a {
+        self.iter().map(MapRefMulti::into_key_ref)
+    }
+
+    /// Returns a [`Stream`] over all values in the map.
+    ///
+    /// See [`ShardMap::iter`] for details on locking.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use futures_util::StreamExt;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///     map.insert("bar", 2).await;
+    ///
+    ///     let mut values: Vec<_> = map.values().map(|v| *v).collect().await;
+    ///     values.sort();
+    ///
+    ///     assert_eq!(values, [1, 2]);
+    /// });
+    /// ```
+    pub fn values<'a>(&'a self) -> impl Stream<Item = ValueRef<'a, K, V>> + 'a {
+        self.iter().map(MapRefMulti::into_value_ref)
+    }
+
+    /// Returns a [`Stream`] that removes every key-value pair from the map and yields it.
+    ///
+    /// Shards are drained one at a time when the stream reaches them: each shard is locked for
+    /// writing just long enough to take its entries out. Entries inserted into a shard after it
+    /// has been drained remain in the map.
+    ///
+    /// # Example
+    /// ```
+    /// use tokio::runtime::Runtime;
+    /// use std::sync::Arc;
+    /// use futures_util::StreamExt;
+    /// use whirlwind::ShardMap;
+    ///
+    /// let rt = Runtime::new().unwrap();
+    /// let map = Arc::new(ShardMap::new());
+    ///
+    /// rt.block_on(async {
+    ///     map.insert("foo", 1).await;
+    ///     map.insert("bar", 2).await;
+    ///
+    ///     let mut drained: Vec<_> = map.drain().collect().await;
+    ///     drained.sort();
+    ///
+    ///     assert_eq!(drained, [("bar", 2), ("foo", 1)]);
+    ///     assert_eq!(map.is_empty().await, true);
+    /// });
+    /// ```
+    pub fn drain(&self) -> impl Stream<Item = (K, V)> + '_ {
+        stream::unfold(
+            (None, shard::Inner::default().into_iter()),
+            move |(mut shards, mut iter)| async move {
+                loop {
+                    if let Some(pair) = iter.next() {
+                        return Some((pair, (shards, iter)));
+                    }
+
+                    let shard = self.next_shard(&mut shards).await?;
+                    let mut writer = shard.write().await;
+                    let table = writer.take();
+                    if !table.is_empty() {
+                        shard.mark_changed();
+                    }
+                    self.notify_removed(shard, &table);
+                    iter = table.into_iter();
+                }
+            },
+        )
+    }
 }
@@ -25,7 +25,12 @@
 //!
 use std::hash::{BuildHasher, Hash, RandomState};
 
-use crate::shard_map::ShardMap;
+use futures_util::Stream;
+use hashbrown::Equivalent;
+
+use futures_util::StreamExt;
+
+use crate::{error::WouldBlock, iter::SetIntoIter, mapref::KeyRef, shard_map::ShardMap};
 
 /// A concurrent set based on a [`ShardMap`] with values of `()`.
 ///
@@ -51,7 +56,7 @@ use crate::shard_map::ShardMap;
 /// ```
 ///
 pub struct ShardSet<T, S = RandomState> {
-    inner: ShardMap<T, (), S>,
+    pub(crate) inner: ShardMap<T, (), S>,
 }
 
 impl<T: Eq + Hash + 'static> Default for ShardSet<T, RandomState> {
@@ -60,6 +65,47 @@ impl<T: Eq + Hash + 'static> Default for ShardSet<T, RandomState> {
     }
 }
 
+impl<T, S> IntoIterator for ShardSet<T, S> {
+    type Item = T;
+    type IntoIter = SetIntoIter<T>;
+
+    /// Consumes the set, returning an iterator over its values.
+    ///
+    /// Prefer [`ShardSet::try_into_iter`], which hands the set back instead of panicking, or
+    /// [`ShardSet::drain`], which takes the values out of a set that is still shared.
+    ///
+    /// # Panics
+    ///
+    /// Panics if anything else still shares the set, such as another clone of it, including one
+    /// held by a spawned task.
+    fn into_iter(self) -> Self::IntoIter {
+        SetIntoIter::new(self.inner.into_iter())
+    }
+}
+
+impl<T, S> ShardSet<T, S> {
+    /// Consumes the set, returning an iterator over its values, if nothing else shares the set.
+    /// Otherwise, the set is returned unchanged.
+    pub fn try_into_iter(self) -> Result<SetIntoIter<T>, Self> {
+        self.inner
+            .try_into_iter()
+            .map(SetIntoIter::new)
+            .map_err(|inner| Self { inner })
+    }
+}
+
+impl<T, S> FromIterator<T> for ShardSet<T, S>
+where
+    T: Eq + Hash + 'static,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self {
+            inner: iter.into_iter().map(|value| (value, ())).collect(),
+        }
+    }
+}
+
 impl<T: Eq + Hash + 'static> ShardSet<T, RandomState> {
     pub fn new() -> Self {
         Self {
@@ -79,6 +125,10 @@ where
     T: Eq + std::hash::Hash + 'static,
     S: BuildHasher,
 {
+    pub(crate) fn from_map(inner: ShardMap<T, (), S>) -> Self {
+        Self { inner }
+    }
+
     pub fn new_with_hasher(hasher: S) -> Self {
         Self {
             inner: ShardMap::with_hasher(hasher),
@@ -96,16 +146,162 @@ where
         self.inner.insert(value, ()).await;
     }
 
+    /// Inserts all values from `iter` into the set.
+    ///
+    /// Values are hashed up front and grouped by shard, so that each shard is locked for writing
+    /// only once no matter how many values belong to it.
+    pub async fn extend<I>(&self, iter: I)
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.inner
+            .extend(iter.into_iter().map(|value| (value, ())))
+            .await;
+    }
+
+    /// Inserts all values from `iter` into the set, returning whether each value was newly
+    /// inserted, in the order the values were given.
+    ///
+    /// Like [`ShardSet::extend`], each shard is locked for writing only once.
+    pub async fn insert_many<I>(&self, iter: I) -> Vec<bool>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        self.inner
+            .insert_many(iter.into_iter().map(|value| (value, ())))
+            .await
+            .into_iter()
+            .map(|old| old.is_none())
+            .collect()
+    }
+
     /// Returns `true` if the set contains the specified value.
-    pub async fn contains(&self, value: &T) -> bool {
+    ///
+    /// The value may be any borrowed form of the set's value type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the value type.
+    pub async fn contains<Q>(&self, value: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<T>,
+    {
         self.inner.contains_key(value).await
     }
 
-    /// Removes a value from the set.
-    pub async fn remove(&self, value: &T) -> bool {
+    /// Removes a value from the set. Returns `true` if the value was present.
+    ///
+    /// The value may be any borrowed form of the set's value type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the value type.
+    pub async fn remove<Q>(&self, value: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<T>,
+    {
         self.inner.remove(value).await.is_some()
     }
 
+    /// Attempts to insert a value into the set without waiting for the shard lock.
+    ///
+    /// If the shard associated with the value is currently locked, the value is handed back in a
+    /// [`WouldBlock`] error.
+    pub fn try_insert(&self, value: T) -> Result<(), WouldBlock<T>> {
+        self.inner
+            .try_insert(value, ())
+            .map(|_| ())
+            .map_err(|WouldBlock((value, ()))| WouldBlock(value))
+    }
+
+    /// Attempts to check whether the set contains the specified value without waiting for the
+    /// shard lock.
+    ///
+    /// If the shard associated with the value is currently locked for writing, [`WouldBlock`] is
+    /// returned.
+    pub fn try_contains<Q>(&self, value: &Q) -> Result<bool, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<T>,
+    {
+        self.inner.try_contains_key(value)
+    }
+
+    /// Attempts to remove a value from the set without waiting for the shard lock. Returns
+    /// `Ok(true)` if the value was present.
+    ///
+    /// If the shard associated with the value is currently locked, [`WouldBlock`] is returned.
+    pub fn try_remove<Q>(&self, value: &Q) -> Result<bool, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<T>,
+    {
+        self.inner.try_remove(value).map(|v| v.is_some())
+    }
+
+    /// Inserts a value into the set, blocking the current thread until the shard lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_insert(&self, value: T) {
+        self.inner.blocking_insert(value, ());
+    }
+
+    /// Returns `true` if the set contains the specified value, blocking the current thread until
+    /// the shard lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_contains<Q>(&self, value: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<T>,
+    {
+        self.inner.blocking_contains_key(value)
+    }
+
+    /// Removes a value from the set, blocking the current thread until the shard lock is acquired.
+    /// Returns `true` if the value was present.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_remove<Q>(&self, value: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<T>,
+    {
+        self.inner.blocking_remove(value).is_some()
+    }
+
+    /// Returns the number of elements in the set, blocking the current thread while each shard
+    /// lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_len(&self) -> usize {
+        self.inner.blocking_len()
+    }
+
+    /// Returns `true` if the set is empty, blocking the current thread while each shard lock is
+    /// acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_is_empty(&self) -> bool {
+        self.inner.blocking_is_empty()
+    }
+
+    /// Clears the set, removing all values, blocking the current thread while each shard lock is
+    /// acquired.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if called within an asynchronous execution context. With the
+    /// `async-lock` feature, it blocks the thread of the executor instead.
+    pub fn blocking_clear(&self) {
+        self.inner.blocking_clear();
+    }
+
     /// Returns the number of elements in the set.
     pub async fn len(&self) -> usize {
         self.inner.len().await
@@ -120,4 +316,30 @@ where
     pub async fn clear(&self) {
         self.inner.clear().await;
     }
+
+    /// Retains only the values for which `f` returns `true`, removing all others.
+    ///
+    /// Each shard is locked for writing in turn while `f` is called on its values.
+    pub async fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.inner.retain(|value, _| f(value)).await;
+    }
+
+    /// Returns a [`Stream`] over all values in the set.
+    ///
+    /// Shards are visited one at a time, and only the shard currently being iterated is locked
+    /// for reading. See [`ShardMap::iter`] for details.
+    pub fn iter<'a>(&'a self) -> impl Stream<Item = KeyRef<'a, T, ()>> + 'a {
+        self.inner.keys()
+    }
+
+    /// Returns a [`Stream`] that removes every value from the set and yields it.
+    ///
+    /// Shards are drained one at a time when the stream reaches them. See [`ShardMap::drain`] for
+    /// details.
+    pub fn drain(&self) -> impl Stream<Item = T> + '_ {
+        self.inner.drain().map(|(value, ())| value)
+    }
 }
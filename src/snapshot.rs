@@ -0,0 +1,145 @@
+//! This module contains the [`Snapshot`] returned by [`crate::ShardMap::snapshot`], a frozen,
+//! read-only view of the whole map at a single point in time.
+//!
+//! # Example
+//! ```
+//! use whirlwind::ShardMap;
+//! use tokio::runtime::Runtime;
+//!
+//! let rt = Runtime::new().unwrap();
+//! let map = ShardMap::new();
+//! rt.block_on(async {
+//!     map.insert("foo", 1).await;
+//!     map.insert("bar", 2).await;
+//!
+//!     let snapshot = map.snapshot().await;
+//!     assert_eq!(snapshot.len(), 2);
+//!     assert_eq!(snapshot.get(&"foo"), Some(&1));
+//!
+//!     // The view can be cloned out before releasing the map.
+//!     let checkpoint = snapshot.to_hash_map();
+//!     drop(snapshot);
+//!
+//!     map.clear().await;
+//!     assert_eq!(checkpoint.len(), 2);
+//! });
+//! ```
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hash, RandomState},
+};
+
+use hashbrown::Equivalent;
+
+use crate::{
+    shard::{Shard, ShardReader},
+    ShardMap,
+};
+
+type Readers<'a, K, V> = Vec<(&'a Shard<K, V>, ShardReader<'a, K, V>)>;
+
+/// A frozen, read-only view of every key-value pair in a [`crate::ShardMap`] at a single point in
+/// time.
+///
+/// The snapshot holds a read lock on every shard of the map, so no writes can be made to the map
+/// until it is dropped. Reads are not blocked.
+pub struct Snapshot<'a, K, V, S = RandomState> {
+    map: &'a ShardMap<K, V, S>,
+    /// A reader for every shard that held entries, in ascending order of shard id.
+    readers: Readers<'a, K, V>,
+    len: usize,
+}
+
+impl<'a, K, V, S> Snapshot<'a, K, V, S>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    S: BuildHasher,
+{
+    pub(crate) fn new(map: &'a ShardMap<K, V, S>, readers: Readers<'a, K, V>) -> Self {
+        let len = readers.iter().map(|(_, reader)| reader.len()).sum();
+        Self { map, readers, len }
+    }
+
+    /// Returns the number of elements in the map at the time of the snapshot.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the map was empty at the time of the snapshot.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the key-value pair corresponding to the supplied key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.map.hash(key);
+        // The locked shards cannot be retired, so the key's shard is one of them.
+        let id = self.map.live_shard(hash).id();
+        let idx = self
+            .readers
+            .binary_search_by_key(&id, |(shard, _)| shard.id());
+        self.readers[idx.ok()?]
+            .1
+            .find(hash, |(k, _)| key.equivalent(k))
+            .map(|(k, v)| (k, v))
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    /// Returns `true` if the map contained a value for the specified key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        self.get_key_value(key).is_some()
+    }
+
+    /// Returns an iterator over every key-value pair in the snapshot, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.readers
+            .iter()
+            .flat_map(|(_, reader)| reader.iter())
+            .map(|(k, v)| (k, v))
+    }
+
+    /// Returns an iterator over every key in the snapshot, in arbitrary order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> + '_ {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Returns an iterator over every value in the snapshot, in arbitrary order.
+    pub fn values(&self) -> impl Iterator<Item = &V> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Clones every key-value pair in the snapshot into a [`HashMap`], which outlives the
+    /// snapshot's locks.
+    pub fn to_hash_map(&self) -> HashMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut map = HashMap::with_capacity(self.len);
+        map.extend(self.iter().map(|(k, v)| (k.clone(), v.clone())));
+        map
+    }
+}
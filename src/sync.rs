@@ -0,0 +1,491 @@
+//! A synchronous variant of [`crate::ShardMap`], for CPU-bound code whose critical sections are
+//! too short to be worth an async lock.
+//!
+//! [`SyncShardMap`] uses the same sharding strategy and hash table layout as
+//! [`crate::ShardMap`], but guards each shard with a [`parking_lot::RwLock`] and only has plain,
+//! blocking methods. It is safe to use from async code as long as no reference into the map is
+//! held across an `.await`.
+//!
+//! # Example
+//! ```
+//! use std::{sync::Arc, thread};
+//! use whirlwind::SyncShardMap;
+//!
+//! let map = Arc::new(SyncShardMap::new());
+//!
+//! let handles: Vec<_> = (0..4)
+//!     .map(|i| {
+//!         let map = map.clone();
+//!         thread::spawn(move || {
+//!             for j in 0..100 {
+//!                 map.insert(i * 100 + j, j);
+//!             }
+//!         })
+//!     })
+//!     .collect();
+//! for handle in handles {
+//!     handle.join().unwrap();
+//! }
+//!
+//! assert_eq!(map.len(), 400);
+//! ```
+pub mod mapref;
+
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::Arc,
+};
+
+use crossbeam_utils::CachePadded;
+use hashbrown::{hash_table::Entry, Equivalent};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    error::{BuildError, WouldBlock},
+    shard::Inner as Table,
+    shard_map::{shard_capacity, shard_count, shard_for_hash, shard_shift},
+    ShardMapBuilder,
+};
+
+use self::mapref::{MapRef, MapRefMut};
+
+type Shard<K, V> = CachePadded<RwLock<Table<K, V>>>;
+
+struct Inner<K, V, S> {
+    shards: Box<[Shard<K, V>]>,
+    hasher: S,
+    shift: usize,
+}
+
+/// A concurrent hashmap using a sharding strategy, with synchronous methods.
+///
+/// Cloning the map is cheap, and the clone refers to the same underlying map.
+///
+/// # Examples
+/// ```
+/// use whirlwind::SyncShardMap;
+///
+/// let map = SyncShardMap::new();
+/// map.insert("foo", "bar");
+/// assert_eq!(map.len(), 1);
+/// assert_eq!(map.contains_key(&"foo"), true);
+/// assert_eq!(map.contains_key(&"bar"), false);
+///
+/// assert_eq!(map.get(&"foo").unwrap().value(), &"bar");
+/// assert_eq!(map.remove(&"foo"), Some("bar"));
+/// ```
+pub struct SyncShardMap<K, V, S = RandomState> {
+    inner: Arc<Inner<K, V, S>>,
+}
+
+impl<K, V, S> Clone for SyncShardMap<K, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<K, V> Default for SyncShardMap<K, V, RandomState>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for SyncShardMap<K, V, S>
+where
+    K: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let map = Self::with_hasher(S::default());
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<K, V> SyncShardMap<K, V, RandomState>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new `SyncShardMap` with the default hasher.
+    pub fn new() -> Self {
+        Self::with_shards(shard_count())
+    }
+
+    /// Creates a new `SyncShardMap` with the default hasher and `shards` shards.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+
+    /// Creates a new `SyncShardMap` with the default hasher and space for at least `cap`
+    /// elements.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+
+    /// Creates a new `SyncShardMap` with the default hasher, `shards` shards, and space for at
+    /// least `cap` elements.
+    pub fn with_shards_and_capacity(shards: usize, cap: usize) -> Self {
+        Self::with_shards_and_capacity_and_hasher(shards, cap, RandomState::new())
+    }
+}
+
+impl<K, V, S: BuildHasher> SyncShardMap<K, V, S>
+where
+    K: Eq + Hash,
+{
+    /// Creates a new `SyncShardMap` with the provided hasher `S`.
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_shards_and_hasher(shard_count(), hasher)
+    }
+
+    /// Creates a new `SyncShardMap` with the provided hasher `S` and space for at least `cap`
+    /// elements.
+    pub fn with_capacity_and_hasher(cap: usize, hasher: S) -> Self {
+        Self::with_shards_and_capacity_and_hasher(shard_count(), cap, hasher)
+    }
+
+    /// Creates a new `SyncShardMap` with the provided hasher `S` and `shards` shards.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        Self::with_shards_and_capacity_and_hasher(shards, 4, hasher)
+    }
+
+    /// Creates a new `SyncShardMap` with the provided hasher `S`, `shards` shards, and space for
+    /// at least `cap` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is not a power of two greater than one. Use [`ShardMapBuilder`] to
+    /// handle invalid configurations without panicking.
+    pub fn with_shards_and_capacity_and_hasher(shards: usize, cap: usize, hasher: S) -> Self {
+        ShardMapBuilder::new()
+            .shards(shards)
+            .capacity(cap)
+            .hasher(hasher)
+            .build_sync()
+            .unwrap_or_else(|err| panic!("invalid `SyncShardMap` configuration: {err}"))
+    }
+
+    pub(crate) fn from_builder(builder: ShardMapBuilder<S>) -> Result<Self, BuildError> {
+        let shards = builder.validate_shards()?;
+        let shift = shard_shift(shards);
+        let shard_capacity = shard_capacity(builder.capacity, shards);
+
+        let shards = std::iter::repeat_with(|| {
+            CachePadded::new(RwLock::new(Table::with_capacity(
+                shard_capacity,
+                builder.incremental_resize,
+            )))
+        })
+        .take(shards)
+        .collect();
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                shards,
+                hasher: builder.hasher,
+                shift,
+            }),
+        })
+    }
+
+    /// Returns the number of shards in the map.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map = SyncShardMap::<u32, u32>::with_shards(8);
+    /// assert_eq!(map.shard_count(), 8);
+    /// ```
+    pub fn shard_count(&self) -> usize {
+        self.inner.shards.len()
+    }
+
+    #[inline]
+    fn shard<Q>(&self, key: &Q) -> (&Shard<K, V>, u64)
+    where
+        Q: ?Sized + Hash,
+    {
+        let hash = self.inner.hasher.hash_one(key);
+        let idx = shard_for_hash(hash as usize, self.inner.shift);
+
+        (&self.inner.shards[idx], hash)
+    }
+
+    /// Inserts a key-value pair into the map. If the key already exists, the value is updated and
+    /// the old value is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map = SyncShardMap::new();
+    /// assert_eq!(map.insert("foo", "bar"), None);
+    /// assert_eq!(map.insert("foo", "baz"), Some("bar"));
+    /// ```
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let (shard, hash) = self.shard(&key);
+        let mut table = shard.write();
+
+        match table.entry(
+            hash,
+            |(k, _)| k == &key,
+            |(k, _)| self.inner.hasher.hash_one(k),
+        ) {
+            Entry::Occupied(mut entry) => {
+                let (_, old) = std::mem::replace(entry.get_mut(), (key, value));
+                Some(old)
+            }
+            Entry::Vacant(slot) => {
+                slot.insert((key, value));
+                None
+            }
+        }
+    }
+
+    /// Returns a reference to the value associated with the key. If the key is not in the map,
+    /// `None` is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::{sync::mapref::MapRef, SyncShardMap};
+    ///
+    /// let map = SyncShardMap::new();
+    /// map.insert("foo", "bar");
+    ///
+    /// // `get` returns a `MapRef` which holds a read lock on the shard.
+    /// let entry: MapRef<'_, _, _> = map.get(&"foo").unwrap();
+    /// assert_eq!(entry.value(), &"bar");
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<MapRef<'_, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (shard, hash) = self.shard(key);
+
+        RwLockReadGuard::try_map(shard.read(), |table| {
+            table.find(hash, |(k, _)| key.equivalent(k))
+        })
+        .ok()
+        .map(MapRef::new)
+    }
+
+    /// Returns a mutable reference to the value associated with the key. If the key is not in the
+    /// map, `None` is returned.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::{sync::mapref::MapRefMut, SyncShardMap};
+    ///
+    /// let map = SyncShardMap::new();
+    /// map.insert("foo", "bar");
+    ///
+    /// // `get_mut` returns a `MapRefMut` which holds a write lock on the shard.
+    /// let mut entry: MapRefMut<'_, _, _> = map.get_mut(&"foo").unwrap();
+    /// *entry.value_mut() = "baz";
+    /// drop(entry);
+    ///
+    /// assert_eq!(map.get(&"foo").unwrap().value(), &"baz");
+    /// ```
+    pub fn get_mut<Q>(&self, key: &Q) -> Option<MapRefMut<'_, K, V>>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (shard, hash) = self.shard(key);
+
+        RwLockWriteGuard::try_map(shard.write(), |table| {
+            table.find_mut(hash, |(k, _)| key.equivalent(k))
+        })
+        .ok()
+        .map(MapRefMut::new)
+    }
+
+    /// Returns a mutable reference to the value associated with the key, inserting the value
+    /// returned by `f` first if the key is not in the map.
+    ///
+    /// The shard is locked for writing while `f` runs, so it should be quick.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map = SyncShardMap::new();
+    /// *map.get_or_insert_with("hits", || 0) += 1;
+    /// *map.get_or_insert_with("hits", || 0) += 1;
+    ///
+    /// assert_eq!(map.get(&"hits").unwrap().value(), &2);
+    /// ```
+    pub fn get_or_insert_with<F>(&self, key: K, f: F) -> MapRefMut<'_, K, V>
+    where
+        F: FnOnce() -> V,
+    {
+        let (shard, hash) = self.shard(&key);
+
+        let pair = RwLockWriteGuard::map(shard.write(), |table| {
+            match table.entry(
+                hash,
+                |(k, _)| k == &key,
+                |(k, _)| self.inner.hasher.hash_one(k),
+            ) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(slot) => slot.insert((key, f())).into_mut(),
+            }
+        });
+        MapRefMut::new(pair)
+    }
+
+    /// Attempts to get a reference to the value associated with the key without waiting for the
+    /// shard lock. If the key is not in the map, `Ok(None)` is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map = SyncShardMap::new();
+    /// map.insert("foo", "bar");
+    ///
+    /// let guard = map.get_mut(&"foo").unwrap();
+    /// assert!(map.try_get(&"foo").is_err());
+    /// drop(guard);
+    ///
+    /// assert_eq!(map.try_get(&"foo").unwrap().unwrap().value(), &"bar");
+    /// ```
+    pub fn try_get<Q>(&self, key: &Q) -> Result<Option<MapRef<'_, K, V>>, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (shard, hash) = self.shard(key);
+        let reader = shard.try_read().ok_or(WouldBlock(()))?;
+
+        Ok(
+            RwLockReadGuard::try_map(reader, |table| table.find(hash, |(k, _)| key.equivalent(k)))
+                .ok()
+                .map(MapRef::new),
+        )
+    }
+
+    /// Attempts to get a mutable reference to the value associated with the key without waiting
+    /// for the shard lock. If the key is not in the map, `Ok(None)` is returned.
+    pub fn try_get_mut<Q>(&self, key: &Q) -> Result<Option<MapRefMut<'_, K, V>>, WouldBlock>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (shard, hash) = self.shard(key);
+        let writer = shard.try_write().ok_or(WouldBlock(()))?;
+
+        Ok(RwLockWriteGuard::try_map(writer, |table| {
+            table.find_mut(hash, |(k, _)| key.equivalent(k))
+        })
+        .ok()
+        .map(MapRefMut::new))
+    }
+
+    /// Returns `true` if the map contains the key.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map = SyncShardMap::new();
+    /// map.insert("foo", "bar");
+    ///
+    /// assert_eq!(map.contains_key(&"foo"), true);
+    /// assert_eq!(map.contains_key(&"bar"), false);
+    /// ```
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (shard, hash) = self.shard(key);
+
+        shard
+            .read()
+            .find(hash, |(k, _)| key.equivalent(k))
+            .is_some()
+    }
+
+    /// Removes a key from the map and returns the value associated with the key. If the key is
+    /// not in the map, `None` is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map = SyncShardMap::new();
+    /// map.insert("foo", "bar");
+    ///
+    /// assert_eq!(map.remove(&"foo"), Some("bar"));
+    /// assert_eq!(map.remove(&"foo"), None);
+    /// ```
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let (shard, hash) = self.shard(key);
+        let mut table = shard.write();
+
+        let entry = table.find_entry(hash, |(k, _)| key.equivalent(k)).ok()?;
+        let ((_, value), _) = entry.remove();
+        Some(value)
+    }
+
+    /// Returns the number of elements in the map.
+    ///
+    /// Each shard is locked in turn, so the result may be outdated if the map is modified
+    /// concurrently.
+    pub fn len(&self) -> usize {
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| shard.read().len())
+            .sum()
+    }
+
+    /// Returns `true` if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner
+            .shards
+            .iter()
+            .all(|shard| shard.read().is_empty())
+    }
+
+    /// Clears the map, removing all key-value pairs.
+    pub fn clear(&self) {
+        for shard in self.inner.shards.iter() {
+            shard.write().clear();
+        }
+    }
+
+    /// Retains only the key-value pairs for which `f` returns `true`.
+    ///
+    /// # Example
+    /// ```
+    /// use whirlwind::SyncShardMap;
+    ///
+    /// let map: SyncShardMap<u32, u32> = (0..10).map(|i| (i, i)).collect();
+    /// map.retain(|_, v| *v % 2 == 0);
+    ///
+    /// assert_eq!(map.len(), 5);
+    /// ```
+    pub fn retain<F>(&self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for shard in self.inner.shards.iter() {
+            shard.write().retain(|(k, v)| f(k, v));
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! This module contains the `MapRef` and `MapRefMut` types, which are used to hold references to
+//! key-value pairs in a [`SyncShardMap`]. Like their counterparts in [`crate::mapref`], they keep
+//! the shard associated with the key locked for the duration of the reference.
+//!
+//! # Example
+//! ```
+//! use whirlwind::SyncShardMap;
+//!
+//! let map = SyncShardMap::new();
+//! map.insert("foo", "bar");
+//!
+//! let r = map.get(&"foo").unwrap();
+//!
+//! assert_eq!(r.key(), &"foo");
+//! assert_eq!(r.value(), &"bar");
+//!
+//! drop(r); // release the lock so we can mutate the value.
+//!
+//! let mut mr = map.get_mut(&"foo").unwrap();
+//! *mr.value_mut() = "baz";
+//!
+//! assert_eq!(mr.value(), &"baz");
+//! ```
+use parking_lot::{MappedRwLockReadGuard, MappedRwLockWriteGuard};
+
+#[cfg(doc)]
+use crate::SyncShardMap;
+
+/// A reference to a key-value pair in a [`SyncShardMap`].
+///
+/// Holds a shared (read-only) lock on the shard associated with the key. Dropping this
+/// reference will release the lock.
+pub struct MapRef<'a, K, V> {
+    pair: MappedRwLockReadGuard<'a, (K, V)>,
+}
+
+impl<K, V> std::ops::Deref for MapRef<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<'a, K, V> MapRef<'a, K, V> {
+    pub(crate) fn new(pair: MappedRwLockReadGuard<'a, (K, V)>) -> Self {
+        Self { pair }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        &self.pair.0
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        &self.pair.1
+    }
+
+    /// Returns a reference to the key-value pair
+    pub fn pair(&self) -> (&K, &V) {
+        (&self.pair.0, &self.pair.1)
+    }
+}
+
+/// A mutable reference to a key-value pair in a [`SyncShardMap`].
+///
+/// Holds an exclusive lock on the shard associated with the key. Dropping this
+/// reference will release the lock.
+pub struct MapRefMut<'a, K, V> {
+    pair: MappedRwLockWriteGuard<'a, (K, V)>,
+}
+
+impl<K, V> std::ops::Deref for MapRefMut<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value()
+    }
+}
+
+impl<K, V> std::ops::DerefMut for MapRefMut<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.value_mut()
+    }
+}
+
+impl<'a, K, V> MapRefMut<'a, K, V> {
+    pub(crate) fn new(pair: MappedRwLockWriteGuard<'a, (K, V)>) -> Self {
+        Self { pair }
+    }
+
+    /// Returns a reference to the key.
+    pub fn key(&self) -> &K {
+        &self.pair.0
+    }
+
+    /// Returns a reference to the value.
+    pub fn value(&self) -> &V {
+        &self.pair.1
+    }
+
+    /// Returns a mutable reference to the value.
+    pub fn value_mut(&mut self) -> &mut V {
+        &mut self.pair.1
+    }
+
+    /// Returns a reference to the key-value pair.
+    pub fn pair(&self) -> (&K, &V) {
+        (&self.pair.0, &self.pair.1)
+    }
+
+    /// Returns a reference to the key-value pair, with a mutable reference to the value.
+    pub fn pair_mut(&mut self) -> (&K, &mut V) {
+        let (key, value) = &mut *self.pair;
+        (key, value)
+    }
+}
@@ -0,0 +1,236 @@
+use std::iter::Chain;
+
+use hashbrown::{
+    hash_table::{self, AbsentEntry, Entry, OccupiedEntry},
+    HashTable,
+};
+
+/// The number of entries that each insertion moves out of the old table while an incremental
+/// resize is in progress.
+const MIGRATE_STEP: usize = 16;
+
+/// The number of empty buckets of the old table that an insertion may skip for each entry it is
+/// allowed to move, so that a sparse stretch of the table does not make one insertion slow.
+const EMPTY_VISITS: usize = 10;
+
+/// The capacity of the first allocation of an incrementally resized table.
+const MIN_CAPACITY: usize = 4;
+
+pub(crate) type Iter<'a, T> = Chain<hash_table::Iter<'a, T>, hash_table::Iter<'a, T>>;
+pub(crate) type IterMut<'a, T> = Chain<hash_table::IterMut<'a, T>, hash_table::IterMut<'a, T>>;
+pub(crate) type IntoIter<T> = Chain<hash_table::IntoIter<T>, hash_table::IntoIter<T>>;
+
+/// The position of an entry in a [`Table`]. It stays valid until an entry is inserted or removed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Slot {
+    /// Whether the entry is in the old table.
+    old: bool,
+    index: usize,
+}
+
+/// The table of a shard: a [`HashTable`] that can optionally grow incrementally.
+///
+/// By default, the table grows like any [`HashTable`], and the insertion that fills it moves every
+/// entry into a larger allocation at once. In incremental mode, that insertion only allocates the
+/// larger table, and each following insertion moves a few entries of the old one over, as Redis
+/// does for its dictionaries. Lookups check both tables until the old one is empty.
+///
+/// The old table is walked bucket by bucket from a cursor that only moves forward, so each
+/// insertion does a bounded amount of work no matter how large the table is. An entry can only
+/// be found in the old table at or after the cursor: the old table never takes new entries, and a
+/// replaced entry goes back into the bucket it was removed from.
+pub(crate) struct Table<T> {
+    /// The table that new entries go to.
+    table: HashTable<T>,
+    /// The table being moved into `table` by an incremental resize, if one is in progress. An
+    /// entry is never in both tables.
+    old: HashTable<T>,
+    /// The index of the next bucket of `old` to move.
+    cursor: usize,
+    incremental: bool,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Self::with_capacity(0, false)
+    }
+}
+
+impl<T> Table<T> {
+    pub fn with_capacity(capacity: usize, incremental: bool) -> Self {
+        Self {
+            table: HashTable::with_capacity(capacity),
+            old: HashTable::new(),
+            cursor: 0,
+            incremental,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.table.len() + self.old.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn find(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> Option<&T> {
+        self.table
+            .find(hash, &mut eq)
+            .or_else(|| self.old.find(hash, eq))
+    }
+
+    pub fn find_mut(&mut self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> Option<&mut T> {
+        match self.table.find_mut(hash, &mut eq) {
+            Some(value) => Some(value),
+            None => self.old.find_mut(hash, eq),
+        }
+    }
+
+    pub fn find_slot(&self, hash: u64, mut eq: impl FnMut(&T) -> bool) -> Option<Slot> {
+        match self.table.find_bucket_index(hash, &mut eq) {
+            Some(index) => Some(Slot { old: false, index }),
+            None => Some(Slot {
+                old: true,
+                index: self.old.find_bucket_index(hash, eq)?,
+            }),
+        }
+    }
+
+    pub fn get(&self, slot: Slot) -> Option<&T> {
+        match slot.old {
+            false => self.table.get_bucket(slot.index),
+            true => self.old.get_bucket(slot.index),
+        }
+    }
+
+    pub fn get_mut(&mut self, slot: Slot) -> Option<&mut T> {
+        match slot.old {
+            false => self.table.get_bucket_mut(slot.index),
+            true => self.old.get_bucket_mut(slot.index),
+        }
+    }
+
+    pub fn find_entry(
+        &mut self,
+        hash: u64,
+        mut eq: impl FnMut(&T) -> bool,
+    ) -> Result<OccupiedEntry<'_, T>, AbsentEntry<'_, T>> {
+        match self.table.find_entry(hash, &mut eq) {
+            Ok(entry) => Ok(entry),
+            Err(absent) => self.old.find_entry(hash, eq).map_err(|_| absent),
+        }
+    }
+
+    pub fn entry(
+        &mut self,
+        hash: u64,
+        mut eq: impl FnMut(&T) -> bool,
+        hasher: impl Fn(&T) -> u64,
+    ) -> Entry<'_, T> {
+        self.make_room(&hasher);
+        if let Ok(entry) = self.old.find_entry(hash, &mut eq) {
+            return Entry::Occupied(entry);
+        }
+        self.table.entry(hash, eq, hasher)
+    }
+
+    pub fn insert_unique(
+        &mut self,
+        hash: u64,
+        value: T,
+        hasher: impl Fn(&T) -> u64,
+    ) -> OccupiedEntry<'_, T> {
+        self.make_room(&hasher);
+        self.table.insert_unique(hash, value, hasher)
+    }
+
+    /// Reserves room for at least `additional` more entries. This does nothing in incremental
+    /// mode, where the table only ever grows a step at a time.
+    pub fn reserve(&mut self, additional: usize, hasher: impl Fn(&T) -> u64) {
+        if !self.incremental {
+            self.table.reserve(additional, hasher);
+        }
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&mut T) -> bool) {
+        self.table.retain(&mut f);
+        self.old.retain(f);
+    }
+
+    pub fn clear(&mut self) {
+        self.table.clear();
+        self.old = HashTable::new();
+        self.cursor = 0;
+    }
+
+    /// Takes every entry out of the table, leaving it empty and in the same mode.
+    pub fn take(&mut self) -> Self {
+        std::mem::replace(self, Self::with_capacity(0, self.incremental))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.table.iter().chain(self.old.iter())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        self.table.iter_mut().chain(self.old.iter_mut())
+    }
+
+    /// Prepares the table for an insertion. In incremental mode, this moves a few entries out of
+    /// the old table, and starts a new resize if the table is full, so that the insertion never
+    /// has to grow it.
+    fn make_room(&mut self, hasher: &impl Fn(&T) -> u64) {
+        if !self.incremental {
+            return;
+        }
+
+        self.migrate(hasher);
+        // The new table has room for twice as many entries as the old one held, and every
+        // insertion moves the cursor at least `MIGRATE_STEP` buckets, so the old table is always
+        // empty well before the new one fills up. Even if removals leave tombstones in the new
+        // table, the entries moved in and inserted meanwhile take at most half of its room again.
+        if self.table.len() == self.table.capacity() && self.old.is_empty() {
+            let capacity = (self.table.capacity() * 2).max(MIN_CAPACITY);
+            self.old = std::mem::replace(&mut self.table, HashTable::with_capacity(capacity));
+        }
+    }
+
+    /// Moves up to `MIGRATE_STEP` entries from the old table into the new one, visiting at most
+    /// `MIGRATE_STEP * EMPTY_VISITS` empty buckets along the way.
+    fn migrate(&mut self, hasher: &impl Fn(&T) -> u64) {
+        if self.old.num_buckets() == 0 {
+            return;
+        }
+
+        let (mut moved, mut empty) = (0, 0);
+        while moved < MIGRATE_STEP && self.cursor < self.old.num_buckets() {
+            match self.old.get_bucket_entry(self.cursor) {
+                Ok(entry) => {
+                    let (value, _) = entry.remove();
+                    self.table.insert_unique(hasher(&value), value, hasher);
+                    moved += 1;
+                }
+                Err(_) => empty += 1,
+            }
+            self.cursor += 1;
+            if empty == MIGRATE_STEP * EMPTY_VISITS {
+                break;
+            }
+        }
+
+        if self.old.is_empty() {
+            self.old = HashTable::new();
+            self.cursor = 0;
+        }
+    }
+}
+
+impl<T> IntoIterator for Table<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.table.into_iter().chain(self.old)
+    }
+}
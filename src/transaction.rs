@@ -0,0 +1,238 @@
+//! This module contains the [`Transaction`] handle used by [`crate::ShardMap::transaction`] to
+//! read and write several keys atomically, even when they live in different shards.
+//!
+//! Transactions are optimistic: reads take a shard lock only for as long as the value is copied
+//! out, and writes are buffered until the transaction commits. At commit time every shard the
+//! transaction touched is locked in ascending order and checked for changes made since it was
+//! read. If another writer got there first, the buffered writes are discarded and the
+//! transaction closure is run again, after yielding to the executor a few times so that the
+//! conflicting writer can make progress. A transaction that still conflicts after
+//! [`MAX_ATTEMPTS`] attempts fails with [`TransactionError::Conflict`].
+//!
+//! [`TransactionError::Conflict`]: crate::error::TransactionError::Conflict
+//!
+//! # Example
+//! ```
+//! use whirlwind::ShardMap;
+//! use tokio::runtime::Runtime;
+//!
+//! let rt = Runtime::new().unwrap();
+//! let map = ShardMap::new();
+//! rt.block_on(async {
+//!     map.insert("alice", 100).await;
+//!     map.insert("bob", 50).await;
+//!
+//!     map.transaction(|tx| async move {
+//!         let alice = tx.get(&"alice").await.unwrap();
+//!         let bob = tx.get(&"bob").await.unwrap();
+//!         tx.insert("alice", alice - 30);
+//!         tx.insert("bob", bob + 30);
+//!         Ok::<_, ()>(())
+//!     })
+//!     .await
+//!     .unwrap();
+//!
+//!     assert_eq!(map.get(&"alice").await.unwrap().value(), &70);
+//!     assert_eq!(map.get(&"bob").await.unwrap().value(), &80);
+//! });
+//! ```
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    hash::{BuildHasher, Hash, RandomState},
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll},
+};
+
+use hashbrown::{hash_table::Entry, Equivalent, HashTable};
+
+use crate::ShardMap;
+
+/// The number of times [`ShardMap::transaction`] runs its closure before giving up on a
+/// transaction that keeps conflicting with other writers.
+pub const MAX_ATTEMPTS: u32 = 64;
+
+/// Yields to the executor a number of times that doubles with each failed attempt, up to 64.
+pub(crate) async fn backoff(attempt: u32) {
+    for _ in 0..1u32 << attempt.min(6) {
+        YieldNow(false).await;
+    }
+}
+
+/// A future that is pending exactly once, rescheduling itself so that other tasks get to run.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// The reads and buffered writes of a single attempt at running a transaction.
+pub(crate) struct TxState<K, V> {
+    /// The version of each shard at the time it was first read, keyed by shard id.
+    pub(crate) reads: BTreeMap<usize, u64>,
+    /// Buffered writes along with the hash of their key. A value of `None` is a removal.
+    pub(crate) writes: HashTable<(u64, K, Option<V>)>,
+    /// Set when the same shard was observed at two different versions, in which case the attempt
+    /// can never commit.
+    pub(crate) conflict: bool,
+    finished: bool,
+}
+
+/// A handle to a running transaction on a [`ShardMap`].
+///
+/// Reads see the transaction's own buffered writes, and otherwise the latest committed value in
+/// the map. Writes are not visible to anyone else until the transaction commits.
+///
+/// A handle is only valid while the closure passed to [`ShardMap::transaction`] is running. Using
+/// a handle after that (for example, one that was smuggled out of the closure) panics.
+pub struct Transaction<K, V, S = RandomState> {
+    map: ShardMap<K, V, S>,
+    state: Arc<Mutex<TxState<K, V>>>,
+}
+
+impl<K, V, S> Clone for Transaction<K, V, S> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+impl<K, V, S> Transaction<K, V, S>
+where
+    K: Eq + Hash + 'static,
+    V: 'static,
+    S: BuildHasher,
+{
+    pub(crate) fn new(map: ShardMap<K, V, S>) -> Self {
+        Self {
+            map,
+            state: Arc::new(Mutex::new(TxState {
+                reads: BTreeMap::new(),
+                writes: HashTable::new(),
+                conflict: false,
+                finished: false,
+            })),
+        }
+    }
+
+    /// Marks the transaction as finished and takes its reads and writes out of the handle.
+    pub(crate) fn finish(&self) -> TxState<K, V> {
+        let mut state = self.state();
+        state.finished = true;
+
+        TxState {
+            reads: std::mem::take(&mut state.reads),
+            writes: std::mem::take(&mut state.writes),
+            conflict: state.conflict,
+            finished: true,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, TxState<K, V>> {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        assert!(!state.finished, "transaction used after it completed");
+        state
+    }
+
+    /// Returns a copy of the value associated with the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+        V: Clone,
+    {
+        let hash = self.map.hash(key);
+
+        if let Some((_, _, value)) = self
+            .state()
+            .writes
+            .find(hash, |(_, k, _)| key.equivalent(k))
+        {
+            return value.clone();
+        }
+
+        let (shard, reader) = self.map.read_shard(hash).await;
+        let version = shard.version();
+        let value = reader
+            .find(hash, |(k, _)| key.equivalent(k))
+            .map(|(_, v)| v.clone());
+        drop(reader);
+
+        self.record_read(shard.id(), version);
+        value
+    }
+
+    /// Returns `true` if the key is present, taking the transaction's own writes into account.
+    ///
+    /// The key may be any borrowed form of the map's key type, but [`Hash`] and [`Eq`] on the
+    /// borrowed form *must* match those for the key type.
+    pub async fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        Q: ?Sized + Hash + Equivalent<K>,
+    {
+        let hash = self.map.hash(key);
+
+        if let Some((_, _, value)) = self
+            .state()
+            .writes
+            .find(hash, |(_, k, _)| key.equivalent(k))
+        {
+            return value.is_some();
+        }
+
+        let (shard, reader) = self.map.read_shard(hash).await;
+        let version = shard.version();
+        let found = reader.find(hash, |(k, _)| key.equivalent(k)).is_some();
+        drop(reader);
+
+        self.record_read(shard.id(), version);
+        found
+    }
+
+    /// Buffers an insert of a key-value pair, to be applied when the transaction commits.
+    pub fn insert(&self, key: K, value: V) {
+        self.write(key, Some(value));
+    }
+
+    /// Buffers the removal of a key, to be applied when the transaction commits.
+    pub fn remove(&self, key: K) {
+        self.write(key, None);
+    }
+
+    fn write(&self, key: K, value: Option<V>) {
+        let hash = self.map.hash(&key);
+        let mut state = self.state();
+
+        match state
+            .writes
+            .entry(hash, |(_, k, _)| k == &key, |(hash, _, _)| *hash)
+        {
+            Entry::Occupied(mut entry) => entry.get_mut().2 = value,
+            Entry::Vacant(slot) => {
+                slot.insert((hash, key, value));
+            }
+        }
+    }
+
+    fn record_read(&self, shard: usize, version: u64) {
+        let mut state = self.state();
+        let recorded = *state.reads.entry(shard).or_insert(version);
+        if recorded != version {
+            state.conflict = true;
+        }
+    }
+}
@@ -0,0 +1,457 @@
+//! This module contains the [`KeyWatcher`] returned by [`crate::ShardMap::watch`], which receives
+//! an event every time a single key in the map changes.
+//!
+//! A watcher only keeps the latest change to its key. One that falls behind skips straight to the
+//! newest event, which suits keys that hold state such as configuration, where only the current
+//! value matters.
+//!
+//! # Example
+//! ```
+//! use whirlwind::{watch::WatchEvent, ShardMap};
+//! use tokio::runtime::Runtime;
+//!
+//! let rt = Runtime::new().unwrap();
+//! # #[cfg(feature = "tokio")]
+//! let map = ShardMap::new();
+//! # #[cfg(feature = "tokio")]
+//! rt.block_on(async {
+//!     let mut watcher = map.watch(&"log_level");
+//!
+//!     map.insert("log_level", "debug").await;
+//!     assert_eq!(watcher.recv().await, Some(WatchEvent::Changed("debug")));
+//!
+//!     // Changes that were not received yet are replaced by newer ones.
+//!     *map.get_mut(&"log_level").await.unwrap() = "info";
+//!     map.remove(&"log_level").await;
+//!     assert_eq!(watcher.recv().await, Some(WatchEvent::Removed));
+//!     assert_eq!(watcher.try_recv(), None);
+//! });
+//! ```
+#[cfg(not(feature = "tokio"))]
+use std::marker::PhantomData;
+#[cfg(feature = "tokio")]
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "tokio")]
+use crossbeam_utils::CachePadded;
+#[cfg(feature = "tokio")]
+use futures_util::{stream, FutureExt, Stream, StreamExt};
+#[cfg(feature = "tokio")]
+use hashbrown::HashTable;
+#[cfg(feature = "tokio")]
+use tokio::sync::watch;
+
+#[cfg(feature = "tokio")]
+use crate::changes::{ChangeStream, Feed};
+use crate::{changes::Change, shard::Shard};
+
+/// A change to a watched key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent<V> {
+    /// The key was inserted or its value was modified. Holds a copy of the new value.
+    Changed(V),
+    /// The key was removed from the map.
+    Removed,
+}
+
+/// Receives the changes to a key in a [`crate::ShardMap`], in the order they were made.
+///
+/// Only the latest change that was not received yet is kept, so a watcher that falls behind skips
+/// the changes in between. Once every clone of the map has been dropped, the last change is still
+/// received and then [`KeyWatcher::recv`] returns `None`.
+#[cfg(feature = "tokio")]
+pub struct KeyWatcher<V> {
+    events: Pin<Box<dyn Stream<Item = WatchEvent<V>> + Send>>,
+    // Dropped after `events`, so that the receiver is gone by the time the map checks whether the
+    // key is still watched.
+    _unwatch: Unwatch,
+}
+
+/// Removes a key from the watched keys of its map when dropped, if it has no watchers left.
+#[cfg(feature = "tokio")]
+struct Unwatch(Option<Box<dyn FnOnce() + Send>>);
+
+#[cfg(feature = "tokio")]
+impl Drop for Unwatch {
+    fn drop(&mut self) {
+        if let Some(unwatch) = self.0.take() {
+            unwatch();
+        }
+    }
+}
+
+/// The receiving half of the channel that a watched key publishes its changes to.
+#[cfg(feature = "tokio")]
+pub(crate) type Receiver<V> = watch::Receiver<Option<WatchEvent<V>>>;
+
+#[cfg(feature = "tokio")]
+impl<V> KeyWatcher<V>
+where
+    V: Clone + Send + Sync + 'static,
+{
+    pub(crate) fn new(rx: Receiver<V>, unwatch: Box<dyn FnOnce() + Send>) -> Self {
+        let events = stream::unfold(rx, |mut rx| async move {
+            rx.changed().await.ok()?;
+            let event = rx.borrow_and_update().clone()?;
+            Some((event, rx))
+        });
+
+        Self {
+            events: Box::pin(events.fuse()),
+            _unwatch: Unwatch(Some(unwatch)),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<V> KeyWatcher<V> {
+    /// Waits for the next change to the key. Returns `None` once the map has been dropped.
+    pub async fn recv(&mut self) -> Option<WatchEvent<V>> {
+        self.events.next().await
+    }
+
+    /// Returns the next change to the key if there is one, without waiting.
+    pub fn try_recv(&mut self) -> Option<WatchEvent<V>> {
+        self.events.next().now_or_never().flatten()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<V> Stream for KeyWatcher<V> {
+    type Item = WatchEvent<V>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.as_mut().poll_next(cx)
+    }
+}
+
+/// A watched key along with the channel its changes are published to.
+#[cfg(feature = "tokio")]
+struct Watched<K, V> {
+    hash: u64,
+    key: K,
+    tx: watch::Sender<Option<WatchEvent<V>>>,
+    // Changes are published without any bounds on `V`, so cloning it is captured up front.
+    clone_value: fn(&V) -> V,
+}
+
+#[cfg(feature = "tokio")]
+type Table<K, V> = HashTable<Watched<K, V>>;
+
+#[cfg(feature = "tokio")]
+type Tables<K, V> = Box<[CachePadded<Mutex<Table<K, V>>>]>;
+
+/// Records every change to a map while the changed shard is still locked, so that nothing is
+/// missed or reordered. Used by [`crate::persistence`].
+#[cfg(feature = "persistence")]
+pub(crate) trait Journal<K, V>: Send + Sync {
+    /// Records the new value of `key`, or its removal if `value` is `None`.
+    fn record(&self, key: &K, value: Option<&V>);
+}
+
+/// Everyone observing the changes to a map: the watchers of every watched key, the subscribers
+/// of the change feed, and the journal.
+pub(crate) struct Watchers<K, V> {
+    /// The number of keys with at least one watcher, so that changes to a map nobody watches only
+    /// cost an atomic load.
+    #[cfg(feature = "tokio")]
+    keys: AtomicUsize,
+    /// The watched keys, split by hash the same way the map was first split into shards, so that
+    /// changes to different shards rarely contend for the same table.
+    #[cfg(feature = "tokio")]
+    tables: Tables<K, V>,
+    #[cfg(feature = "tokio")]
+    shift: usize,
+    #[cfg(feature = "tokio")]
+    feed: OnceLock<Feed<K, V>>,
+    #[cfg(feature = "persistence")]
+    journal: std::sync::RwLock<Option<std::sync::Arc<dyn Journal<K, V>>>>,
+    #[cfg(feature = "persistence")]
+    journaled: std::sync::atomic::AtomicBool,
+    #[cfg(not(feature = "tokio"))]
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> Watchers<K, V> {
+    /// Creates the watchers of a map that starts out with `shards` shards.
+    pub(crate) fn new(shards: usize) -> Self {
+        #[cfg(not(feature = "tokio"))]
+        let _ = shards;
+
+        Self {
+            #[cfg(feature = "tokio")]
+            keys: AtomicUsize::new(0),
+            #[cfg(feature = "tokio")]
+            tables: (0..shards)
+                .map(|_| CachePadded::new(Mutex::new(HashTable::new())))
+                .collect(),
+            #[cfg(feature = "tokio")]
+            shift: crate::shard_map::shard_shift(shards),
+            #[cfg(feature = "tokio")]
+            feed: OnceLock::new(),
+            #[cfg(feature = "persistence")]
+            journal: std::sync::RwLock::new(None),
+            #[cfg(feature = "persistence")]
+            journaled: std::sync::atomic::AtomicBool::new(false),
+            #[cfg(not(feature = "tokio"))]
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(feature = "persistence")]
+impl<K, V> Watchers<K, V> {
+    fn is_journaled(&self) -> bool {
+        self.journaled.load(Ordering::Acquire)
+    }
+
+    /// Starts recording changes to `journal`. Returns `false` if the map already has a journal.
+    ///
+    /// Changes made while this is called may or may not be recorded, so callers that need every
+    /// change to be recorded must lock every shard first.
+    pub(crate) fn attach_journal(&self, journal: std::sync::Arc<dyn Journal<K, V>>) -> bool {
+        let mut slot = self.journal.write().unwrap_or_else(PoisonError::into_inner);
+        if slot.is_some() {
+            return false;
+        }
+        *slot = Some(journal);
+        self.journaled.store(true, Ordering::Release);
+        true
+    }
+
+    /// Stops recording changes.
+    pub(crate) fn detach_journal(&self) {
+        let mut slot = self.journal.write().unwrap_or_else(PoisonError::into_inner);
+        self.journaled.store(false, Ordering::Release);
+        *slot = None;
+    }
+
+    fn record(&self, key: &K, value: Option<&V>) {
+        if self.is_journaled() {
+            let journal = self.journal.read().unwrap_or_else(PoisonError::into_inner);
+            if let Some(journal) = journal.as_ref() {
+                journal.record(key, value);
+            }
+        }
+    }
+}
+
+impl<K: Eq, V> Watchers<K, V> {
+    /// Locks the table of watched keys that a key with the given hash belongs to.
+    #[cfg(feature = "tokio")]
+    fn table(&self, hash: u64) -> MutexGuard<'_, Table<K, V>> {
+        let idx = crate::shard_map::shard_for_hash(hash as usize, self.shift);
+        self.tables[idx]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn watches_keys(&self) -> bool {
+        #[cfg(feature = "tokio")]
+        return self.keys.load(Ordering::Relaxed) != 0;
+        #[cfg(not(feature = "tokio"))]
+        return false;
+    }
+
+    /// Returns `true` if no one is observing the map, in which case changes need not be reported.
+    pub(crate) fn is_empty(&self) -> bool {
+        #[cfg(feature = "persistence")]
+        if self.is_journaled() {
+            return false;
+        }
+
+        #[cfg(feature = "tokio")]
+        if self.feed.get().is_some_and(Feed::is_active) {
+            return false;
+        }
+
+        !self.watches_keys()
+    }
+
+    /// Registers a new watcher for `key`, returning the receiver it gets the changes through.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn watch(&self, hash: u64, key: K) -> Receiver<V>
+    where
+        V: Clone,
+    {
+        let mut table = self.table(hash);
+        if let Some(watched) = table.find(hash, |watched| watched.key == key) {
+            return watched.tx.subscribe();
+        }
+
+        let (tx, rx) = watch::channel(None);
+        let watched = Watched {
+            hash,
+            key,
+            tx,
+            clone_value: V::clone,
+        };
+        table.insert_unique(hash, watched, |watched| watched.hash);
+        self.keys.fetch_add(1, Ordering::Relaxed);
+        rx
+    }
+
+    /// Stops watching `key` if none of its watchers are left.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn unwatch(&self, hash: u64, key: &K) {
+        let mut table = self.table(hash);
+        if let Ok(entry) = table.find_entry(hash, |watched| &watched.key == key) {
+            if entry.get().tx.receiver_count() == 0 {
+                entry.remove();
+                self.keys.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Subscribes to the change feed of the map.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn subscribe(&self) -> ChangeStream<K, V>
+    where
+        K: Clone + Send + 'static,
+        V: Clone + Send + 'static,
+    {
+        self.feed.get_or_init(|| Feed::new()).subscribe()
+    }
+
+    /// Reports a change to `key`, which belongs to `shard`. This must be called while the shard is
+    /// locked for writing, so that changes are seen in the order they were made.
+    pub(crate) fn notify(&self, shard: &Shard<K, V>, hash: u64, key: &K, change: Change<'_, V>) {
+        self.notify_key(hash, key, change.value());
+
+        #[cfg(feature = "tokio")]
+        if let Some(feed) = self.feed.get() {
+            feed.change(shard, key, change);
+        }
+        #[cfg(not(feature = "tokio"))]
+        let _ = shard;
+    }
+
+    /// Tells the watchers of `key` and the journal about its new value, or that it was removed if
+    /// `value` is `None`, without reporting the change to the feed.
+    pub(crate) fn notify_key(&self, hash: u64, key: &K, value: Option<&V>) {
+        #[cfg(feature = "persistence")]
+        self.record(key, value);
+
+        if !self.watches_keys() {
+            return;
+        }
+
+        #[cfg(feature = "tokio")]
+        {
+            let mut table = self.table(hash);
+            if let Ok(entry) = table.find_entry(hash, |watched| &watched.key == key) {
+                let watched = entry.get();
+                let event = match value {
+                    Some(value) => WatchEvent::Changed((watched.clone_value)(value)),
+                    None => WatchEvent::Removed,
+                };
+
+                // Sending only fails once every watcher of the key is gone.
+                if watched.tx.send(Some(event)).is_err() {
+                    entry.remove();
+                    self.keys.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        }
+        #[cfg(not(feature = "tokio"))]
+        let _ = (hash, key, value);
+    }
+
+    /// Reports that every key of `shard` was removed. The watchers of those keys and the journal
+    /// have to be told separately, with [`Watchers::notify_key`].
+    pub(crate) fn notify_clear(&self, shard: &Shard<K, V>) {
+        #[cfg(feature = "tokio")]
+        if let Some(feed) = self.feed.get() {
+            feed.clear(shard);
+        }
+        #[cfg(not(feature = "tokio"))]
+        let _ = shard;
+    }
+}
+
+type Notify<K, V> = fn(&Watchers<K, V>, &Shard<K, V>, u64, &K, Change<'_, V>);
+
+/// Tracks whether a mutable guard handed out a mutable reference to its value, so that the
+/// change can be recorded in the shard and reported when the guard is dropped.
+pub(crate) struct ChangeTracker<'a, K, V> {
+    shard: &'a Shard<K, V>,
+    /// The watchers to notify of changes to the key, along with the hash of the key.
+    watchers: Option<(&'a Watchers<K, V>, u64)>,
+    // Guards are dropped without any bounds on `K`, so `Watchers::notify` is captured up front.
+    notify: Notify<K, V>,
+    dirty: bool,
+    inserted: bool,
+}
+
+impl<'a, K: Eq, V> ChangeTracker<'a, K, V> {
+    pub(crate) fn new(watchers: &'a Watchers<K, V>, shard: &'a Shard<K, V>, hash: u64) -> Self {
+        Self {
+            shard,
+            watchers: Some((watchers, hash)),
+            notify: Watchers::notify,
+            dirty: false,
+            inserted: false,
+        }
+    }
+
+    /// A tracker that records changes in the shard, but never notifies anyone.
+    pub(crate) fn untracked(shard: &'a Shard<K, V>) -> Self {
+        Self {
+            shard,
+            watchers: None,
+            notify: Watchers::notify,
+            dirty: false,
+            inserted: false,
+        }
+    }
+}
+
+impl<K, V> ChangeTracker<'_, K, V> {
+    pub(crate) fn mark(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Marks the key as newly inserted, rather than updated.
+    pub(crate) fn mark_inserted(&mut self) {
+        self.dirty = true;
+        self.inserted = true;
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Records and reports the new value of the key if it may have changed.
+    pub(crate) fn flush(&mut self, key: &K, value: &V) {
+        if std::mem::take(&mut self.dirty) {
+            self.shard.mark_changed();
+            let change = match std::mem::take(&mut self.inserted) {
+                true => Change::Insert(value),
+                false => Change::Update(value),
+            };
+            if let Some((watchers, hash)) = self.watchers {
+                (self.notify)(watchers, self.shard, hash, key, change);
+            }
+        }
+    }
+
+    /// Reports that the key was removed, discarding any pending change.
+    pub(crate) fn removed(&mut self, key: &K) {
+        self.dirty = false;
+        self.shard.mark_changed();
+        // A key that is removed before its insertion was reported never changed at all.
+        if std::mem::take(&mut self.inserted) {
+            return;
+        }
+        if let Some((watchers, hash)) = self.watchers {
+            (self.notify)(watchers, self.shard, hash, key, Change::Remove);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+#![cfg(feature = "async-lock")]
+
+use futures_lite::future;
+use whirlwind::{ShardMap, ShardSet};
+
+#[test]
+fn test_without_tokio_runtime() {
+    future::block_on(async {
+        let map = ShardMap::new();
+        map.insert("foo", 1).await;
+
+        let mut value = map.get_mut(&"foo").await.unwrap();
+        let writer = async move {
+            future::yield_now().await;
+            *value += 1;
+        };
+        // The reader waits for the writer, and is woken without a tokio runtime driving it.
+        let reader = async { *map.get(&"foo").await.unwrap().value() };
+
+        let ((), value) = future::zip(writer, reader).await;
+        assert_eq!(value, 2);
+    });
+}
+
+#[test]
+fn test_blocking_in_async_context() {
+    let set = ShardSet::new();
+    future::block_on(async {
+        set.insert(1).await;
+        set.blocking_insert(2);
+        assert!(set.contains(&2).await);
+    });
+    assert_eq!(set.blocking_len(), 2);
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::bool_assert_comparison)]
+
 use whirlwind::*;
 
 #[tokio::test]
@@ -65,3 +67,23 @@ async fn test_shardmap_is_empty() {
     map.remove(&"foo").await;
     assert_eq!(map.is_empty().await, true);
 }
+
+#[tokio::test]
+async fn test_shardmap_borrowed_key() {
+    let map = ShardMap::new();
+    map.insert(String::from("foo"), 1).await;
+    assert!(map.contains_key("foo").await);
+    assert_eq!(map.get("foo").await.unwrap().value(), &1);
+    *map.get_mut("foo").await.unwrap() += 1;
+    assert_eq!(map.remove("foo").await, Some(2));
+    assert!(!map.contains_key("foo").await);
+}
+
+#[tokio::test]
+async fn test_shardset_borrowed_key() {
+    let set = ShardSet::new();
+    set.insert(vec![1, 2, 3]).await;
+    assert!(set.contains([1, 2, 3].as_slice()).await);
+    assert!(set.remove([1, 2, 3].as_slice()).await);
+    assert!(!set.contains([1, 2, 3].as_slice()).await);
+}
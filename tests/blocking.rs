@@ -0,0 +1,72 @@
+use whirlwind::{ShardMap, ShardSet};
+
+#[test]
+fn test_shardmap_blocking() {
+    let map = ShardMap::new();
+    assert!(map.blocking_is_empty());
+    assert_eq!(map.blocking_insert("foo", 1), None);
+    assert_eq!(map.blocking_insert("foo", 2), Some(1));
+    assert!(map.blocking_contains_key(&"foo"));
+    assert_eq!(map.blocking_get(&"foo").unwrap().value(), &2);
+
+    *map.blocking_get_mut(&"foo").unwrap() += 1;
+    assert_eq!(map.blocking_len(), 1);
+    assert_eq!(map.blocking_remove(&"foo"), Some(3));
+    assert!(map.blocking_get(&"foo").is_none());
+
+    for i in 0..10 {
+        map.blocking_insert("bar", i);
+        map.blocking_insert(["a", "b", "c", "d"][i % 4], i);
+    }
+    map.blocking_retain(|k, _| *k != "bar");
+    assert_eq!(map.blocking_len(), 4);
+    map.blocking_clear();
+    assert!(map.blocking_is_empty());
+}
+
+#[test]
+fn test_shardmap_blocking_threads() {
+    let map = ShardMap::new();
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let map = map.clone();
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    map.blocking_insert(t * 100 + i, i);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(map.blocking_len(), 800);
+}
+
+#[test]
+fn test_shardmap_blocking_shared_with_async() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let map = ShardMap::new();
+
+    rt.block_on(map.insert("foo", 1));
+    let handle = std::thread::spawn({
+        let map = map.clone();
+        move || *map.blocking_get_mut(&"foo").unwrap() += 1
+    });
+    handle.join().unwrap();
+
+    assert_eq!(rt.block_on(map.get(&"foo")).unwrap().value(), &2);
+}
+
+#[test]
+fn test_shardset_blocking() {
+    let set = ShardSet::new();
+    set.blocking_insert("foo");
+    assert!(set.blocking_contains(&"foo"));
+    assert_eq!(set.blocking_len(), 1);
+    assert!(set.blocking_remove(&"foo"));
+    assert!(!set.blocking_remove(&"foo"));
+    set.blocking_insert("bar");
+    set.blocking_clear();
+    assert!(set.blocking_is_empty());
+}
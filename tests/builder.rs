@@ -0,0 +1,63 @@
+use std::hash::RandomState;
+
+use whirlwind::{error::BuildError, ShardMap, ShardMapBuilder, ShardSet};
+
+#[tokio::test]
+async fn test_builder() {
+    let map: ShardMap<&str, &str> = ShardMapBuilder::new()
+        .shards(8)
+        .capacity(64)
+        .hasher(RandomState::new())
+        .build()
+        .unwrap();
+    assert_eq!(map.shard_count(), 8);
+
+    map.insert("foo", "bar").await;
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &"bar");
+}
+
+#[tokio::test]
+async fn test_builder_set() {
+    let set: ShardSet<u32> = ShardMapBuilder::new().shards(4).build_set().unwrap();
+    set.insert(1).await;
+    assert!(set.contains(&1).await);
+}
+
+#[test]
+fn test_builder_rejects_invalid_shards() {
+    let build = |shards| {
+        ShardMapBuilder::new()
+            .shards(shards)
+            .build::<u32, u32>()
+            .map(|map| map.shard_count())
+    };
+    assert_eq!(build(0).unwrap_err(), BuildError::TooFewShards(0));
+    assert_eq!(build(1).unwrap_err(), BuildError::TooFewShards(1));
+    assert_eq!(build(3).unwrap_err(), BuildError::ShardsNotPowerOfTwo(3));
+    assert_eq!(build(2).unwrap(), 2);
+}
+
+#[test]
+fn test_builder_round_up_shards() {
+    let build = |shards| {
+        ShardMapBuilder::new()
+            .shards(shards)
+            .round_up_shards(true)
+            .build::<u32, u32>()
+            .map(|map| map.shard_count())
+    };
+    assert_eq!(build(3).unwrap(), 4);
+    assert_eq!(build(17).unwrap(), 32);
+    assert_eq!(build(64).unwrap(), 64);
+    assert_eq!(build(1).unwrap_err(), BuildError::TooFewShards(1));
+    assert!(matches!(
+        build(usize::MAX).unwrap_err(),
+        BuildError::TooManyShards(_)
+    ));
+}
+
+#[test]
+#[should_panic(expected = "shard count must be a power of two")]
+fn test_with_shards_panics_on_invalid_count() {
+    ShardMap::<u32, u32>::with_shards(3);
+}
@@ -0,0 +1,47 @@
+use whirlwind::{ShardMap, ShardSet};
+
+#[tokio::test]
+async fn test_shardmap_extend() {
+    let map = ShardMap::with_shards(8);
+    map.insert(0, 0).await;
+    map.extend((0..1000).map(|i| (i, i + 1))).await;
+
+    assert_eq!(map.len().await, 1000);
+    for i in 0..1000 {
+        assert_eq!(map.get(&i).await.unwrap().value(), &(i + 1));
+    }
+}
+
+#[tokio::test]
+async fn test_shardmap_insert_many() {
+    let map = ShardMap::with_shards(4);
+    map.insert("a", 0).await;
+
+    let old = map
+        .insert_many([("a", 1), ("b", 2), ("c", 3), ("b", 4)])
+        .await;
+    assert_eq!(old, [Some(0), None, None, Some(2)]);
+    assert_eq!(map.len().await, 3);
+    assert_eq!(map.get(&"b").await.unwrap().value(), &4);
+
+    assert!(map.insert_many(std::iter::empty()).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_shardmap_from_iter() {
+    let map: ShardMap<u32, u32> = (0..1000).map(|i| (i, i * 2)).collect();
+    assert_eq!(map.len().await, 1000);
+    assert_eq!(map.get(&999).await.unwrap().value(), &1998);
+}
+
+#[tokio::test]
+async fn test_shardset_bulk() {
+    let set: ShardSet<u32> = (0..100).collect();
+    assert_eq!(set.len().await, 100);
+
+    set.extend(100..200).await;
+    assert_eq!(set.len().await, 200);
+
+    assert_eq!(set.insert_many([199, 200, 200]).await, [false, true, false]);
+    assert_eq!(set.len().await, 201);
+}
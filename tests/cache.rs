@@ -0,0 +1,230 @@
+#![cfg(feature = "tokio")]
+
+use std::{
+    hash::{BuildHasherDefault, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use whirlwind::{
+    cache::{EvictionPolicy, RemovalCause},
+    CacheShardMap,
+};
+
+type Log = Arc<Mutex<Vec<(u32, u32, RemovalCause)>>>;
+
+fn listener(log: &Log) -> impl Fn(u32, u32, RemovalCause) + Send + Sync + 'static {
+    let log = Arc::clone(log);
+    move |key, value, cause| log.lock().unwrap().push((key, value, cause))
+}
+
+/// Hashes integers to themselves, so that small keys all land in the first shard.
+#[derive(Default)]
+struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("only u32 keys are hashed")
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = n.into();
+    }
+}
+
+#[tokio::test]
+async fn test_lru_evicts_least_recently_used() {
+    let log = Log::default();
+    // Two shards of three entries each, with every key in the first shard.
+    let cache = CacheShardMap::builder(6)
+        .shards(2)
+        .hasher(BuildHasherDefault::<IdentityHasher>::default())
+        .eviction_listener(listener(&log))
+        .build()
+        .unwrap();
+
+    cache.insert(1, 1).await;
+    cache.insert(2, 2).await;
+    cache.insert(3, 3).await;
+    assert!(cache.get(&1).await.is_some());
+
+    cache.insert(4, 4).await;
+    assert!(cache.contains_key(&1).await);
+    assert!(!cache.contains_key(&2).await);
+    assert_eq!(cache.len().await, 3);
+    assert_eq!(*log.lock().unwrap(), [(2, 2, RemovalCause::Size)]);
+}
+
+#[tokio::test]
+async fn test_capacity_is_respected() {
+    let log = Log::default();
+    let cache = CacheShardMap::builder(64)
+        .shards(4)
+        .eviction_listener(listener(&log))
+        .build()
+        .unwrap();
+
+    for i in 0..1_000 {
+        cache.insert(i, i).await;
+    }
+
+    let len = cache.len().await;
+    assert!(len <= 64);
+    assert_eq!(cache.weighted_size().await, len as u64);
+    assert_eq!(log.lock().unwrap().len(), 1_000 - len);
+}
+
+#[tokio::test]
+async fn test_capacity_is_not_rounded_up() {
+    // 10 does not split evenly across 4 shards, but the shares still add up to 10.
+    let cache = CacheShardMap::builder(10).shards(4).build().unwrap();
+    for i in 0..1_000 {
+        cache.insert(i, i).await;
+    }
+    assert!(cache.len().await <= 10);
+
+    // A small cache gets fewer shards by default, so that every key can be cached.
+    let cache = CacheShardMap::new(3);
+    for i in 0..100 {
+        cache.insert(i, i).await;
+        assert!(cache.contains_key(&i).await);
+    }
+    assert!(cache.len().await <= 3);
+}
+
+#[tokio::test]
+async fn test_weigher() {
+    let cache = CacheShardMap::builder(10)
+        .shards(2)
+        .weigher(|_: &u32, value: &Vec<u8>| value.len() as u64)
+        .build()
+        .unwrap();
+
+    cache.insert(1, vec![0; 3]).await;
+    assert_eq!(cache.weighted_size().await, 3);
+
+    assert_eq!(cache.insert(1, vec![0; 4]).await, Some(vec![0; 3]));
+    assert_eq!(cache.weighted_size().await, 4);
+
+    // Heavier than a whole shard, so it replaces nothing and is never stored.
+    assert_eq!(cache.insert(1, vec![0; 6]).await, Some(vec![0; 4]));
+    assert!(!cache.contains_key(&1).await);
+    assert_eq!(cache.weighted_size().await, 0);
+}
+
+#[tokio::test]
+async fn test_tinylfu_keeps_popular_entries() {
+    let log = Log::default();
+    let cache = CacheShardMap::builder(200)
+        .shards(2)
+        .policy(EvictionPolicy::TinyLfu)
+        .eviction_listener(listener(&log))
+        .build()
+        .unwrap();
+
+    for key in 0..50 {
+        cache.insert(key, key).await;
+    }
+    for _ in 0..5 {
+        for key in 0..50 {
+            assert!(cache.get(&key).await.is_some());
+        }
+    }
+
+    // A scan of one-off keys does not flush out the popular ones, as long as they stay popular.
+    for key in 1_000..10_000 {
+        cache.insert(key, key).await;
+        if key % 4 == 0 {
+            cache.get(&((key / 4) % 50)).await;
+        }
+    }
+    let mut kept = 0;
+    for key in 0..50 {
+        kept += usize::from(cache.contains_key(&key).await);
+    }
+    assert!(kept >= 45, "only {kept} popular entries were kept");
+    assert!(cache.len().await <= 200);
+
+    let log = log.lock().unwrap();
+    assert!(log
+        .iter()
+        .any(|&(_, _, cause)| cause == RemovalCause::Rejected));
+}
+
+#[tokio::test]
+async fn test_tinylfu_small_shards_have_no_window() {
+    let log = Log::default();
+    // Two shards of two entries each, with every key in the first shard.
+    let cache = CacheShardMap::builder(4)
+        .shards(2)
+        .policy(EvictionPolicy::TinyLfu)
+        .hasher(BuildHasherDefault::<IdentityHasher>::default())
+        .eviction_listener(listener(&log))
+        .build()
+        .unwrap();
+
+    cache.insert(1, 1).await;
+    cache.insert(2, 2).await;
+    assert!(log.lock().unwrap().is_empty());
+
+    // The new key goes straight up against the least recently used one, and is not popular
+    // enough to replace it.
+    cache.insert(3, 3).await;
+    assert_eq!(*log.lock().unwrap(), [(3, 3, RemovalCause::Rejected)]);
+    assert!(cache.contains_key(&1).await);
+    assert!(cache.contains_key(&2).await);
+}
+
+#[tokio::test]
+async fn test_tinylfu_contains_key_counts_as_access() {
+    let log = Log::default();
+    let cache = CacheShardMap::builder(4)
+        .shards(2)
+        .policy(EvictionPolicy::TinyLfu)
+        .hasher(BuildHasherDefault::<IdentityHasher>::default())
+        .eviction_listener(listener(&log))
+        .build()
+        .unwrap();
+
+    cache.insert(1, 1).await;
+    cache.insert(2, 2).await;
+    for _ in 0..3 {
+        assert!(!cache.contains_key(&3).await);
+    }
+
+    // Looking the key up made it more popular than the entry it replaces.
+    cache.insert(3, 3).await;
+    assert_eq!(*log.lock().unwrap(), [(1, 1, RemovalCause::Size)]);
+    assert!(cache.contains_key(&3).await);
+}
+
+#[tokio::test]
+async fn test_remove_and_clear() {
+    let log = Log::default();
+    let cache = CacheShardMap::builder(100)
+        .eviction_listener(listener(&log))
+        .build()
+        .unwrap();
+
+    for i in 0..10 {
+        cache.insert(i, i).await;
+    }
+    assert_eq!(cache.remove(&3).await, Some(3));
+    assert_eq!(cache.remove(&3).await, None);
+    assert!(log.lock().unwrap().is_empty());
+
+    cache.clear().await;
+    assert!(cache.is_empty().await);
+    assert_eq!(cache.weighted_size().await, 0);
+
+    let mut log = log.lock().unwrap().clone();
+    log.sort_unstable_by_key(|&(key, _, _)| key);
+    let expected: Vec<_> = (0..10)
+        .filter(|&i| i != 3)
+        .map(|i| (i, i, RemovalCause::Explicit))
+        .collect();
+    assert_eq!(log, expected);
+}
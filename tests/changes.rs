@@ -0,0 +1,124 @@
+#![cfg(feature = "tokio")]
+
+use std::collections::HashMap;
+
+use futures_util::StreamExt;
+use whirlwind::{
+    changes::{ChangeKind, CHANGE_BUFFER},
+    ShardMap,
+};
+
+#[tokio::test]
+async fn test_changes_cover_mutations() {
+    let map = ShardMap::with_shards(2);
+    let changes = map.subscribe();
+
+    map.insert("foo", 1).await;
+    map.insert("foo", 2).await;
+    *map.get_mut(&"foo").await.unwrap() += 1;
+    map.entry("bar").await.or_insert(10);
+    map.remove(&"foo").await;
+    map.clear().await;
+    drop(map);
+
+    let events: Vec<_> = changes.collect().await;
+    let kinds: Vec<_> = events.iter().map(|event| event.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        [
+            ChangeKind::Insert("foo", 1),
+            ChangeKind::Update("foo", 2),
+            ChangeKind::Update("foo", 3),
+            ChangeKind::Insert("bar", 10),
+            ChangeKind::Remove("foo"),
+            ChangeKind::Clear,
+            ChangeKind::Clear,
+        ]
+    );
+
+    for shard in 0..2 {
+        let seqs: Vec<_> = events
+            .iter()
+            .filter(|event| event.shard == shard)
+            .map(|event| event.seq)
+            .collect();
+        assert!(seqs.iter().copied().eq(0..seqs.len() as u64));
+    }
+}
+
+#[tokio::test]
+async fn test_changes_skip_unmodified_guards() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+    let mut changes = map.subscribe();
+
+    let _ = map.get_mut(&"foo").await.unwrap().value();
+    map.entry("foo").await.or_insert(2);
+    // Inserting and removing through the same entry never changes the map.
+    map.entry("bar").await.insert(3).remove();
+    map.remove(&"baz").await;
+    map.insert("qux", 4).await;
+
+    let event = changes.recv().await.unwrap();
+    assert_eq!(event.kind, ChangeKind::Insert("qux", 4));
+}
+
+#[tokio::test]
+async fn test_retain_reports_only_removals() {
+    let map = ShardMap::new();
+    for i in 0..(CHANGE_BUFFER * 2) {
+        map.insert(i, i).await;
+    }
+    let mut changes = map.subscribe();
+
+    map.retain(|_, _| true).await;
+    map.retain(|k, _| *k != 7).await;
+    map.retain_async(|k, _| Box::pin(async move { *k != 8 }))
+        .await;
+
+    assert_eq!(changes.recv().await.unwrap().kind, ChangeKind::Remove(7));
+    assert_eq!(changes.recv().await.unwrap().kind, ChangeKind::Remove(8));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_changes_sequence_per_shard() {
+    let map = ShardMap::with_shards(4);
+    let mut changes = map.subscribe();
+
+    let mut tasks = Vec::new();
+    for task in 0..4 {
+        let map = map.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..50 {
+                map.insert(task * 100 + i, i).await;
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut next: HashMap<usize, u64> = HashMap::new();
+    for _ in 0..200 {
+        let event = changes.recv().await.unwrap();
+        let seq = next.entry(event.shard).or_default();
+        assert_eq!(event.seq, *seq);
+        *seq += 1;
+    }
+    assert_eq!(next.values().sum::<u64>(), 200);
+}
+
+#[tokio::test]
+async fn test_lagging_subscriber_sees_gap() {
+    let map = ShardMap::new();
+    let mut changes = map.subscribe();
+
+    // Every event is in the same shard.
+    for i in 0..CHANGE_BUFFER + 10 {
+        map.insert("foo", i).await;
+    }
+
+    let event = changes.recv().await.unwrap();
+    assert_eq!(event.seq, 10);
+    assert_eq!(event.kind, ChangeKind::Update("foo", 10));
+}
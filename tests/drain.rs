@@ -0,0 +1,78 @@
+use futures_util::StreamExt;
+use whirlwind::{ShardMap, ShardSet};
+
+#[tokio::test]
+async fn test_shardmap_drain() {
+    let map = ShardMap::with_shards(8);
+    for i in 0..1000 {
+        map.insert(i, i.to_string()).await;
+    }
+
+    let mut drained: Vec<_> = map.drain().collect().await;
+    drained.sort();
+    assert_eq!(
+        drained,
+        (0..1000).map(|i| (i, i.to_string())).collect::<Vec<_>>()
+    );
+    assert!(map.is_empty().await);
+    assert_eq!(map.drain().count().await, 0);
+}
+
+#[tokio::test]
+async fn test_shardmap_into_iter() {
+    let map = ShardMap::new();
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+
+    let clone = map.clone();
+    let map = map.try_into_iter().err().unwrap();
+    drop(clone);
+
+    let iter = map.into_iter();
+    assert_eq!(iter.len(), 100);
+    let mut pairs: Vec<_> = iter.collect();
+    pairs.sort();
+    assert_eq!(pairs, (0..100).map(|i| (i, i)).collect::<Vec<_>>());
+}
+
+#[test]
+#[should_panic(expected = "still shared")]
+fn test_shardmap_into_iter_shared() {
+    let map = ShardMap::<u32, u32>::new();
+    let _clone = map.clone();
+    let _ = map.into_iter();
+}
+
+#[tokio::test]
+async fn test_shardmap_into_iter_owned_ref() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+
+    // An owned reference shares the map just like a clone does.
+    let entry = map.get_owned(&"foo").await.unwrap();
+    let map = map.try_into_iter().err().unwrap();
+    assert_eq!(entry.value(), &1);
+    drop(entry);
+
+    assert_eq!(
+        map.try_into_iter().ok().unwrap().collect::<Vec<_>>(),
+        [("foo", 1)]
+    );
+}
+
+#[tokio::test]
+async fn test_shardset_drain_into_iter() {
+    let set = ShardSet::new();
+    for i in 0..100 {
+        set.insert(i).await;
+    }
+
+    let mut drained: Vec<_> = set.drain().collect().await;
+    drained.sort();
+    assert_eq!(drained, (0..100).collect::<Vec<_>>());
+    assert!(set.is_empty().await);
+
+    set.insert(1).await;
+    assert_eq!(set.into_iter().collect::<Vec<_>>(), [1]);
+}
@@ -0,0 +1,69 @@
+use whirlwind::{entry::Entry, ShardMap};
+
+#[tokio::test]
+async fn test_entry_or_insert() {
+    let map = ShardMap::new();
+    assert_eq!(*map.entry("foo").await.or_insert(1), 1);
+    assert_eq!(*map.entry("foo").await.or_insert(2), 1);
+    assert_eq!(*map.entry("bar").await.or_insert_with(|| 3), 3);
+    assert_eq!(*map.entry("baz").await.or_default(), 0);
+    assert_eq!(
+        *map.entry("qux")
+            .await
+            .or_insert_with_async(|| async { 4 })
+            .await,
+        4
+    );
+    assert_eq!(map.len().await, 4);
+}
+
+#[tokio::test]
+async fn test_entry_and_modify() {
+    let map = ShardMap::new();
+    map.entry("foo").await.and_modify(|v| *v += 1).or_insert(0);
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &0);
+    map.entry("foo").await.and_modify(|v| *v += 1).or_insert(0);
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &1);
+}
+
+#[tokio::test]
+async fn test_entry_insert_remove() {
+    let map = ShardMap::new();
+    assert_eq!(map.entry("foo").await.remove(), None);
+
+    let entry = map.entry("foo").await.insert("bar");
+    assert_eq!(entry.key(), &"foo");
+    assert_eq!(entry.get(), &"bar");
+    drop(entry);
+
+    match map.entry("foo").await {
+        Entry::Occupied(mut entry) => {
+            assert_eq!(entry.insert("baz"), "bar");
+            assert_eq!(entry.remove_entry(), ("foo", "baz"));
+        }
+        Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert!(!map.contains_key(&"foo").await);
+
+    match map.entry("foo").await {
+        Entry::Vacant(entry) => assert_eq!(entry.into_key(), "foo"),
+        Entry::Occupied(_) => panic!("expected a vacant entry"),
+    };
+}
+
+#[tokio::test]
+async fn test_entry_concurrent_counter() {
+    let map = ShardMap::new();
+    let tasks: Vec<_> = (0..64)
+        .map(|_| {
+            let map = map.clone();
+            tokio::spawn(async move {
+                *map.entry("counter").await.or_insert(0) += 1;
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(map.get(&"counter").await.unwrap().value(), &64);
+}
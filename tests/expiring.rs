@@ -0,0 +1,176 @@
+#![cfg(feature = "tokio")]
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use whirlwind::{ExpiringShardMap, ShardMapBuilder};
+
+#[tokio::test(start_paused = true)]
+async fn test_entries_expire() {
+    let map = ExpiringShardMap::new();
+    map.insert_with_ttl("a", 1, Duration::from_secs(10)).await;
+    map.insert("b", 2).await;
+
+    tokio::time::advance(Duration::from_secs(9)).await;
+    assert_eq!(map.get(&"a").await.unwrap().value(), &1);
+    assert_eq!(map.ttl(&"a").await, Some(Some(Duration::from_secs(1))));
+    assert_eq!(map.ttl(&"b").await, Some(None));
+    assert_eq!(map.len().await, 2);
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert!(map.get(&"a").await.is_none());
+    assert!(map.get_mut(&"a").await.is_none());
+    assert!(!map.contains_key(&"a").await);
+    assert_eq!(map.ttl(&"a").await, None);
+    assert!(map.contains_key(&"b").await);
+    assert_eq!(map.len().await, 1);
+
+    // Expired values are not handed back.
+    assert_eq!(map.insert("a", 3).await, None);
+    assert_eq!(map.get(&"a").await.unwrap().value(), &3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_reinsert_replaces_ttl() {
+    let map = ExpiringShardMap::new();
+    map.insert_with_ttl("a", 1, Duration::from_secs(1)).await;
+    assert_eq!(map.insert("a", 2).await, Some(1));
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(map.get(&"a").await.unwrap().value(), &2);
+
+    let mut entry = map.get_mut(&"a").await.unwrap();
+    *entry += 1;
+    entry.set_expires_at(Some(Instant::now() + Duration::from_secs(1)));
+    drop(entry);
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(map.remove(&"a").await, None);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_purge_expired() {
+    let map = ExpiringShardMap::with_shards(4);
+    for i in 0..100 {
+        if i % 2 == 0 {
+            map.insert_with_ttl(i, i, Duration::from_secs(1)).await;
+        } else {
+            map.insert(i, i).await;
+        }
+    }
+
+    assert_eq!(map.purge_expired().await, 0);
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(map.purge_expired().await, 50);
+    assert_eq!(map.len().await, 50);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_purge_skips_outdated_deadlines() {
+    let map = ExpiringShardMap::with_shards(4);
+    map.insert_with_ttl("replaced", 1, Duration::from_secs(1))
+        .await;
+    map.insert_with_ttl("replaced", 2, Duration::from_secs(10))
+        .await;
+    map.insert_with_ttl("persisted", 3, Duration::from_secs(1))
+        .await;
+    map.get_mut(&"persisted")
+        .await
+        .unwrap()
+        .set_expires_at(None);
+    map.insert("scheduled", 4).await;
+    map.get_mut(&"scheduled")
+        .await
+        .unwrap()
+        .set_expires_at(Some(Instant::now() + Duration::from_secs(5)));
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(map.purge_expired().await, 0);
+    assert_eq!(map.len().await, 3);
+
+    tokio::time::advance(Duration::from_secs(4)).await;
+    assert_eq!(map.purge_expired().await, 1);
+    assert!(!map.contains_key(&"scheduled").await);
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(map.purge_expired().await, 1);
+    assert_eq!(map.get(&"persisted").await.unwrap().value(), &3);
+    assert_eq!(map.len().await, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_refreshed_key_expires_once() {
+    let map = ExpiringShardMap::with_shards(2);
+    for i in 0..10_000 {
+        map.insert_with_ttl(u32::MAX, i, Duration::from_secs(60))
+            .await;
+        map.insert_with_ttl(i, i, Duration::from_secs(1)).await;
+        tokio::time::advance(Duration::from_millis(1)).await;
+    }
+
+    assert_eq!(map.purge_expired().await, 9_001);
+    tokio::time::advance(Duration::from_secs(60)).await;
+    assert_eq!(map.purge_expired().await, 1_000);
+    assert!(map.is_empty().await);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_clear_forgets_deadlines() {
+    let map = ExpiringShardMap::with_shards(2);
+    for i in 0..100 {
+        map.insert_with_ttl(i, i, Duration::from_secs(1)).await;
+    }
+    map.clear().await;
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(map.purge_expired().await, 0);
+    assert_eq!(map.len().await, 100);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_sweeper_reclaims_entries() {
+    let map = ExpiringShardMap::new();
+    let sweeper = map.spawn_sweeper(Duration::from_secs(1));
+
+    for i in 0..10 {
+        map.insert_with_ttl(i, i, Duration::from_millis(500)).await;
+    }
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(map.purge_expired().await, 0);
+    assert!(map.is_empty().await);
+
+    // The sweeper stops once the map is gone.
+    drop(map);
+    tokio::time::timeout(Duration::from_secs(5), sweeper)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_custom_clock() {
+    let now = Arc::new(Mutex::new(Instant::now()));
+    let clock = {
+        let now = Arc::clone(&now);
+        move || *now.lock().unwrap()
+    };
+
+    let map = ShardMapBuilder::new()
+        .shards(2)
+        .build_expiring_with_clock(clock)
+        .unwrap();
+    map.insert_with_ttl("a", 1, Duration::from_secs(60)).await;
+
+    *now.lock().unwrap() += Duration::from_secs(59);
+    assert!(map.contains_key(&"a").await);
+
+    *now.lock().unwrap() += Duration::from_secs(1);
+    assert!(!map.contains_key(&"a").await);
+}
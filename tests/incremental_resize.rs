@@ -0,0 +1,162 @@
+use std::{
+    hash::{BuildHasher, DefaultHasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use futures_util::StreamExt;
+use whirlwind::{ShardMap, ShardMapBuilder, SyncShardMap};
+
+fn incremental_map<V: 'static>() -> ShardMap<u32, V> {
+    ShardMapBuilder::new()
+        .shards(2)
+        .incremental_resize(true)
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_grow_incrementally() {
+    let map = incremental_map();
+
+    // Every key stays reachable while shards are partway through growing.
+    for i in 0..5000 {
+        assert_eq!(map.insert(i, i).await, None);
+        if i % 97 == 0 {
+            for j in 0..=i {
+                assert_eq!(map.get(&j).await.unwrap().value(), &j, "{j} of {i}");
+            }
+            assert_eq!(map.len().await, i as usize + 1);
+        }
+    }
+
+    // Updates and removals reach keys whichever table they are in.
+    for i in (0..5000).step_by(3) {
+        assert_eq!(map.insert(i, i * 2).await, Some(i));
+    }
+    for i in (0..5000).step_by(5) {
+        let expected = if i % 3 == 0 { i * 2 } else { i };
+        assert_eq!(map.remove(&i).await, Some(expected));
+    }
+    assert_eq!(map.len().await, 4000);
+    assert_eq!(map.iter().count().await, 4000);
+
+    let mut pairs: Vec<_> = map.into_iter().collect();
+    pairs.sort_unstable();
+    let expected = (0..5000)
+        .filter(|i| i % 5 != 0)
+        .map(|i| (i, if i % 3 == 0 { i * 2 } else { i }));
+    assert!(pairs.into_iter().eq(expected));
+}
+
+#[tokio::test]
+async fn test_entries_and_bulk_operations() {
+    let map = incremental_map();
+    map.extend((0..3000).map(|i| (i, 0))).await;
+
+    for i in 0..6000 {
+        *map.entry(i % 4000).await.or_insert(0) += 1;
+    }
+    assert_eq!(map.len().await, 4000);
+    assert_eq!(map.get(&10).await.unwrap().value(), &2);
+    assert_eq!(map.get(&3500).await.unwrap().value(), &1);
+
+    map.iter_mut()
+        .for_each(|mut r| async move { *r.value_mut() += 1 })
+        .await;
+    map.retain(|k, _| k % 2 == 0).await;
+    assert_eq!(map.len().await, 2000);
+    assert_eq!(map.get(&10).await.unwrap().value(), &3);
+
+    // Draining leaves the shards empty and still growing incrementally.
+    assert_eq!(map.drain().count().await, 2000);
+    assert!(map.is_empty().await);
+    map.extend((0..1000).map(|i| (i, i))).await;
+    assert_eq!(map.snapshot().await.len(), 1000);
+
+    map.reshard(8).await.unwrap();
+    assert_eq!(map.len().await, 1000);
+    for i in 0..2000 {
+        map.insert(i, i).await;
+    }
+    assert_eq!(map.len().await, 2000);
+    assert_eq!(map.get(&1999).await.unwrap().value(), &1999);
+}
+
+#[test]
+fn test_sync_map() {
+    let map: Arc<SyncShardMap<u32, u32>> = Arc::new(
+        ShardMapBuilder::new()
+            .shards(2)
+            .incremental_resize(true)
+            .build_sync()
+            .unwrap(),
+    );
+
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 0..2000 {
+                    map.insert(t * 2000 + i, i);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(map.len(), 8000);
+    for key in 0..8000 {
+        assert_eq!(map.get(&key).unwrap().value(), &(key % 2000));
+    }
+}
+
+/// Counts how many times keys are hashed, which is once per lookup plus once per entry moved to
+/// a new table.
+#[derive(Clone, Default)]
+struct CountingState(Arc<AtomicUsize>);
+
+impl BuildHasher for CountingState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        DefaultHasher::new()
+    }
+}
+
+#[tokio::test]
+async fn test_insert_work_is_bounded() {
+    const KEYS: u32 = 200_000;
+
+    async fn max_hashes_per_insert(incremental: bool) -> usize {
+        let hashes = CountingState::default();
+        let map: ShardMap<u32, u32, _> = ShardMapBuilder::new()
+            .shards(2)
+            .hasher(hashes.clone())
+            .incremental_resize(incremental)
+            .build()
+            .unwrap();
+
+        let mut max = 0;
+        for i in 0..KEYS {
+            let before = hashes.0.load(Ordering::Relaxed);
+            map.insert(i, i).await;
+            max = max.max(hashes.0.load(Ordering::Relaxed) - before);
+        }
+
+        // Moving every entry a bounded number of times keeps the total linear.
+        assert!(hashes.0.load(Ordering::Relaxed) < 4 * KEYS as usize);
+        max
+    }
+
+    // Growing all at once rehashes a whole shard on a single insert.
+    assert!(max_hashes_per_insert(false).await > KEYS as usize / 8);
+    // Growing incrementally moves at most a few entries on each insert.
+    assert!(max_hashes_per_insert(true).await <= 17);
+}
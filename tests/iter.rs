@@ -0,0 +1,78 @@
+use futures_util::StreamExt;
+use whirlwind::{ShardMap, ShardSet};
+
+#[tokio::test]
+async fn test_shardmap_iter() {
+    let map = ShardMap::with_shards(8);
+    for i in 0..1000 {
+        map.insert(i, i * 2).await;
+    }
+
+    let mut pairs: Vec<_> = map.iter().map(|r| (*r.key(), *r.value())).collect().await;
+    pairs.sort();
+    assert_eq!(pairs, (0..1000).map(|i| (i, i * 2)).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_shardmap_iter_empty() {
+    let map = ShardMap::<u32, u32>::new();
+    assert_eq!(map.iter().count().await, 0);
+}
+
+#[tokio::test]
+async fn test_shardmap_iter_mut() {
+    let map = ShardMap::with_shards(8);
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+
+    map.iter_mut()
+        .for_each(|mut r| async move { *r += 1 })
+        .await;
+
+    for i in 0..100 {
+        assert_eq!(map.get(&i).await.unwrap().value(), &(i + 1));
+    }
+}
+
+#[tokio::test]
+async fn test_shardmap_keys_values() {
+    let map = ShardMap::new();
+    for i in 0..100 {
+        map.insert(i, i * 3).await;
+    }
+
+    let mut keys: Vec<_> = map.keys().map(|k| *k).collect().await;
+    keys.sort();
+    assert_eq!(keys, (0..100).collect::<Vec<_>>());
+
+    let mut values: Vec<_> = map.values().map(|v| *v).collect().await;
+    values.sort();
+    assert_eq!(values, (0..100).map(|i| i * 3).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_shardmap_iter_in_task() {
+    let map = ShardMap::new();
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+
+    let task = {
+        let map = map.clone();
+        tokio::spawn(async move { map.iter().count().await })
+    };
+    assert_eq!(task.await.unwrap(), 100);
+}
+
+#[tokio::test]
+async fn test_shardset_iter() {
+    let set = ShardSet::new();
+    for i in 0..100 {
+        set.insert(i).await;
+    }
+
+    let mut values: Vec<_> = set.iter().map(|v| *v).collect().await;
+    values.sort();
+    assert_eq!(values, (0..100).collect::<Vec<_>>());
+}
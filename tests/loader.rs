@@ -0,0 +1,133 @@
+#![cfg(feature = "tokio")]
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use whirlwind::ShardMap;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_callers_share_one_load() {
+    let map = ShardMap::new();
+    let loads = Arc::new(AtomicUsize::new(0));
+
+    let mut tasks = Vec::new();
+    for _ in 0..32 {
+        let (map, loads) = (map.clone(), loads.clone());
+        tasks.push(tokio::spawn(async move {
+            let value = map
+                .get_or_try_insert_with("key", || async {
+                    loads.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, ()>(7)
+                })
+                .await
+                .unwrap();
+            *value.value()
+        }));
+    }
+
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), 7);
+    }
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_errors_reach_every_waiter() {
+    let map = ShardMap::<&str, u32>::new();
+    let loads = Arc::new(AtomicUsize::new(0));
+
+    let mut tasks = Vec::new();
+    for _ in 0..8 {
+        let (map, loads) = (map.clone(), loads.clone());
+        tasks.push(tokio::spawn(async move {
+            map.get_or_try_insert_with("key", || async {
+                loads.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Err::<u32, _>("unavailable".to_string())
+            })
+            .await
+            .map(|value| *value.value())
+        }));
+    }
+
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), Err("unavailable".to_string()));
+    }
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+    // A failed load leaves nothing behind, so the next caller loads again.
+    let value = map
+        .get_or_try_insert_with("key", || async { Ok::<_, String>(1) })
+        .await
+        .unwrap();
+    assert_eq!(value.value(), &1);
+}
+
+#[tokio::test]
+async fn test_shard_is_not_locked_while_loading() {
+    let map = ShardMap::with_shards(2);
+
+    let value = map
+        .get_or_try_insert_with(1, || async {
+            // Every shard is free while the loader runs.
+            assert!(matches!(map.try_get(&1), Ok(None)));
+            map.insert(2, 2).await;
+            Ok::<_, ()>(1)
+        })
+        .await
+        .unwrap();
+    assert_eq!(value.value(), &1);
+    drop(value);
+
+    assert_eq!(map.get(&2).await.unwrap().value(), &2);
+}
+
+#[tokio::test]
+async fn test_existing_value_wins_over_load() {
+    let map = ShardMap::new();
+
+    let value = map
+        .get_or_insert_with("key", || async {
+            map.insert("key", 1).await;
+            2
+        })
+        .await;
+    assert_eq!(value.value(), &1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_waiter_takes_over_cancelled_load() {
+    let map = ShardMap::new();
+
+    let leader = {
+        let map = map.clone();
+        tokio::spawn(async move {
+            map.get_or_insert_with("key", || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                1
+            })
+            .await;
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    let waiter = {
+        let map = map.clone();
+        tokio::spawn(async move { *map.get_or_insert_with("key", || async { 2 }).await.value() })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!waiter.is_finished());
+
+    leader.abort();
+    let value = tokio::time::timeout(Duration::from_secs(5), waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(value, 2);
+}
@@ -0,0 +1,97 @@
+use whirlwind::ShardMap;
+
+#[tokio::test]
+async fn test_get_many_mut() {
+    let map = ShardMap::with_shards(4);
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+
+    let mut refs = map.get_many_mut([&3, &1, &4]).await.unwrap();
+    assert_eq!(refs.len(), 3);
+    assert_eq!(refs.key(0), &3);
+    assert_eq!(refs[2], 4);
+    let [a, b, c] = refs.values_mut();
+    std::mem::swap(a, b);
+    *c += 10;
+    drop(refs);
+
+    assert_eq!(map.get(&3).await.unwrap().value(), &1);
+    assert_eq!(map.get(&1).await.unwrap().value(), &3);
+    assert_eq!(map.get(&4).await.unwrap().value(), &14);
+}
+
+#[tokio::test]
+async fn test_get_many_mut_missing_or_duplicate() {
+    let map = ShardMap::new();
+    map.insert("a", 1).await;
+    map.insert("b", 2).await;
+
+    assert!(map.get_many_mut([&"a", &"c"]).await.is_none());
+    assert!(map.get_many_mut([&"a", &"b", &"a"]).await.is_none());
+    assert!(map.get_many_mut_vec::<&str>(&[]).await.unwrap().is_empty());
+
+    // The locks taken by a failed call are released.
+    assert!(map.get_mut(&"a").await.is_some());
+}
+
+#[tokio::test]
+async fn test_get_many_mut_vec() {
+    let map = ShardMap::with_shards(2);
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+
+    let keys: Vec<_> = (0..100).step_by(7).collect();
+    let keys: Vec<_> = keys.iter().collect();
+    let mut refs = map.get_many_mut_vec(&keys).await.unwrap();
+    for (k, v) in refs.iter_mut() {
+        *v += *k;
+    }
+    assert!(refs.iter().all(|(k, v)| *v == k * 2));
+    drop(refs);
+
+    assert_eq!(map.get(&7).await.unwrap().value(), &14);
+    assert_eq!(map.get(&8).await.unwrap().value(), &8);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_get_many_mut_no_deadlock() {
+    let map = ShardMap::with_shards(16);
+    for i in 0..16 {
+        map.insert(i, 1000).await;
+    }
+
+    let tasks: Vec<_> = (0..64)
+        .map(|t| {
+            let map = map.clone();
+            tokio::spawn(async move {
+                for i in 0..50 {
+                    let (from, to) = ((t + i) % 16, (t * 7 + i + 1) % 16);
+                    if from == to {
+                        continue;
+                    }
+                    // Alternate the key order so that tasks request shards in opposite orders.
+                    let keys = if i % 2 == 0 {
+                        [&from, &to]
+                    } else {
+                        [&to, &from]
+                    };
+                    let mut refs = map.get_many_mut(keys).await.unwrap();
+                    let [a, b] = refs.values_mut();
+                    *a -= 1;
+                    *b += 1;
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    let mut total = 0;
+    for i in 0..16 {
+        total += *map.get(&i).await.unwrap();
+    }
+    assert_eq!(total, 16 * 1000);
+}
@@ -0,0 +1,84 @@
+use whirlwind::{mapref::OwnedMapRef, ShardMap};
+
+struct Holder {
+    entry: OwnedMapRef<String, u32>,
+}
+
+#[tokio::test]
+async fn test_get_owned_outlives_map() {
+    let map = ShardMap::new();
+    map.insert(String::from("foo"), 1).await;
+
+    let holder = Holder {
+        entry: map.get_owned("foo").await.unwrap(),
+    };
+    drop(map);
+
+    assert_eq!(holder.entry.key(), "foo");
+    assert_eq!(*holder.entry, 1);
+}
+
+#[tokio::test]
+async fn test_get_mut_owned_in_task() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+    assert!(map.get_owned(&"bar").await.is_none());
+    assert!(map.get_mut_owned(&"bar").await.is_none());
+
+    let mut entry = map.get_mut_owned(&"foo").await.unwrap();
+    let task = tokio::spawn(async move {
+        *entry.value_mut() += 1;
+        drop(entry);
+    });
+    task.await.unwrap();
+
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &2);
+}
+
+#[tokio::test]
+async fn test_get_owned_holds_lock() {
+    let map = ShardMap::with_shards(2);
+    map.insert("foo", 1).await;
+
+    let entry = map.get_owned(&"foo").await.unwrap();
+    let writer = {
+        let map = map.clone();
+        tokio::spawn(async move { map.insert("foo", 2).await })
+    };
+    tokio::task::yield_now().await;
+    assert_eq!(*entry, 1);
+    drop(entry);
+
+    assert_eq!(writer.await.unwrap(), Some(1));
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &2);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_get_mut_owned_reports_changes() {
+    use whirlwind::{watch::WatchEvent, ShardMapBuilder};
+
+    let map: ShardMap<u32, u32> = ShardMapBuilder::new()
+        .shards(2)
+        .incremental_resize(true)
+        .build()
+        .unwrap();
+    // Growing the shards leaves the early keys in its old table for a while.
+    for i in 0..64 {
+        map.insert(i, i).await;
+    }
+    let mut watcher = map.watch(&0);
+
+    let entry = map.get_mut_owned(&0).await.unwrap();
+    assert_eq!(entry.pair(), (&0, &0));
+    drop(entry);
+    assert_eq!(watcher.try_recv(), None);
+
+    for i in 0..64 {
+        *map.get_mut_owned(&i).await.unwrap() += 1;
+    }
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(1)));
+    for i in 0..64 {
+        assert_eq!(*map.get_owned(&i).await.unwrap(), i + 1);
+    }
+}
@@ -0,0 +1,288 @@
+#![cfg(feature = "persistence")]
+
+use std::{
+    fs::{self, OpenOptions},
+    io,
+    time::Duration,
+};
+
+use whirlwind::{persistence::FsyncPolicy, ShardMap};
+
+#[tokio::test]
+async fn test_recover_changes() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let map = ShardMap::new();
+    map.insert(0, "zero".to_string()).await;
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+
+    for i in 1..100 {
+        map.insert(i, i.to_string()).await;
+    }
+    map.get_mut(&1).await.unwrap().push_str("-changed");
+    map.remove(&2).await;
+    map.entry(3).await.and_modify(|value| value.clear());
+    map.retain(|key, _| key % 10 != 9).await;
+    persister.sync().unwrap();
+
+    let recovered: ShardMap<u32, String> = ShardMap::recover(dir.path()).unwrap();
+    let expected = map.snapshot().await.to_hash_map();
+    assert_eq!(recovered.snapshot().await.to_hash_map(), expected);
+    assert_eq!(recovered.get(&0).await.unwrap().value(), "zero");
+    assert_eq!(recovered.get(&1).await.unwrap().value(), "1-changed");
+    assert!(recovered.get(&2).await.is_none());
+    assert_eq!(recovered.get(&3).await.unwrap().value(), "");
+    assert!(recovered.get(&19).await.is_none());
+
+    map.clear().await;
+    persister.sync().unwrap();
+    let recovered: ShardMap<u32, String> = ShardMap::recover(dir.path()).unwrap();
+    assert!(recovered.is_empty().await);
+}
+
+#[tokio::test]
+async fn test_checkpoint_compacts_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("wal");
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Never).await.unwrap();
+    for i in 0..1000u32 {
+        map.insert(i % 10, i).await;
+    }
+    persister.sync().unwrap();
+    let before = fs::metadata(&log).unwrap().len();
+
+    persister.checkpoint().await.unwrap();
+    let after = fs::metadata(&log).unwrap().len();
+    assert!(after < before / 10, "{after} >= {before} / 10");
+
+    map.insert(10, 10).await;
+    persister.sync().unwrap();
+    let recovered: ShardMap<u32, u32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(recovered.len().await, 11);
+    assert_eq!(recovered.get(&3).await.unwrap().value(), &993);
+    assert_eq!(recovered.get(&10).await.unwrap().value(), &10);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_spawn_checkpoints() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("wal");
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Never).await.unwrap();
+    let task = persister.spawn_checkpoints(Duration::from_secs(60));
+
+    map.insert("foo", 1).await;
+    persister.sync().unwrap();
+    let written = fs::metadata(&log).unwrap().len();
+
+    tokio::time::sleep(Duration::from_secs(61)).await;
+    assert!(fs::metadata(&log).unwrap().len() < written);
+
+    drop(persister);
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert!(task.is_finished());
+
+    let recovered: ShardMap<String, i32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(recovered.get("foo").await.unwrap().value(), &1);
+}
+
+#[tokio::test]
+async fn test_torn_log_tail() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    map.insert("foo".to_string(), 1).await;
+    map.insert("bar".to_string(), 2).await;
+    drop(persister);
+
+    // Simulate a crash halfway through appending the last record.
+    let log = OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("wal"))
+        .unwrap();
+    let len = log.metadata().unwrap().len();
+    log.set_len(len - 3).unwrap();
+
+    let recovered: ShardMap<String, i32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(recovered.len().await, 1);
+    assert_eq!(recovered.get("foo").await.unwrap().value(), &1);
+}
+
+#[tokio::test]
+async fn test_corrupt_files() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let map = ShardMap::new();
+    map.insert(1u8, 1u8).await;
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    map.insert(2, 2).await;
+    drop(persister);
+
+    let log = dir.path().join("wal");
+    let mut bytes = fs::read(&log).unwrap();
+    *bytes.last_mut().unwrap() ^= 0xff;
+    fs::write(&log, bytes).unwrap();
+    let err = ShardMap::<u8, u8>::recover(dir.path()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let snapshot = dir.path().join("snapshot");
+    let mut bytes = fs::read(&snapshot).unwrap();
+    bytes[4] = 0xff;
+    fs::write(&snapshot, bytes).unwrap();
+    let err = ShardMap::<u8, u8>::recover(dir.path()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("version"));
+}
+
+#[tokio::test]
+async fn test_persister_lifetime() {
+    let dir = tempfile::tempdir().unwrap();
+    let recovered: ShardMap<String, i32> = ShardMap::recover(dir.path().join("missing")).unwrap();
+    assert!(recovered.is_empty().await);
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    let err = map
+        .persist(dir.path(), FsyncPolicy::Always)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+    map.insert("foo".to_string(), 1).await;
+    drop(persister);
+    map.insert("bar".to_string(), 2).await;
+
+    let recovered: ShardMap<String, i32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(recovered.len().await, 1);
+    assert!(recovered.contains_key("foo").await);
+
+    // The map can be persisted again once the previous persister is dropped.
+    let _persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    let recovered: ShardMap<String, i32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(recovered.len().await, 2);
+}
+
+#[tokio::test]
+async fn test_stale_log_is_not_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("wal");
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    map.insert("foo".to_string(), 1).await;
+    map.remove("foo").await;
+    drop(persister);
+    let stale = fs::read(&log).unwrap();
+
+    let map = ShardMap::new();
+    map.insert("foo".to_string(), 2).await;
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    persister.checkpoint().await.unwrap();
+    drop(persister);
+
+    // Simulate a crash after the new snapshot was written, but before the old log was emptied.
+    fs::write(&log, stale).unwrap();
+    let recovered: ShardMap<String, i32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(recovered.len().await, 1);
+    assert_eq!(recovered.get("foo").await.unwrap().value(), &2);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_changes_during_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Never).await.unwrap();
+    let writer = tokio::spawn({
+        let map = map.clone();
+        async move {
+            for i in 0..2000u32 {
+                map.insert(i % 100, i).await;
+            }
+        }
+    });
+    for _ in 0..10 {
+        persister.checkpoint().await.unwrap();
+    }
+    writer.await.unwrap();
+    persister.sync().unwrap();
+
+    let recovered: ShardMap<u32, u32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(
+        recovered.snapshot().await.to_hash_map(),
+        map.snapshot().await.to_hash_map()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_failed_persist_keeps_previous_files() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let map = ShardMap::new();
+    let persister = map.persist(dir.path(), FsyncPolicy::Always).await.unwrap();
+    for i in 0..100 {
+        map.insert(i, i).await;
+    }
+    drop(persister);
+
+    // A directory in the way of the temporary snapshot file makes writing the snapshot fail.
+    fs::create_dir(dir.path().join("snapshot.tmp")).unwrap();
+
+    let recovered: ShardMap<u32, u32> = ShardMap::recover(dir.path()).unwrap();
+    let writer = tokio::spawn({
+        let recovered = recovered.clone();
+        async move {
+            for i in 0.. {
+                recovered.insert(1_000 + i % 100, i).await;
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+    for _ in 0..10 {
+        assert!(recovered
+            .persist(dir.path(), FsyncPolicy::Always)
+            .await
+            .is_err());
+    }
+    writer.abort();
+
+    let recovered: ShardMap<u32, u32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(
+        recovered.snapshot().await.to_hash_map(),
+        map.snapshot().await.to_hash_map()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_drop_during_checkpoint_keeps_changes() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let map = ShardMap::new();
+    for i in 0..100_000 {
+        map.insert(i, i).await;
+    }
+    let persister = map.persist(dir.path(), FsyncPolicy::Never).await.unwrap();
+
+    // Start a checkpoint and leave it writing its snapshot on the blocking thread pool.
+    let mut checkpoint = Box::pin(persister.checkpoint());
+    assert!(futures_lite::future::poll_once(&mut checkpoint)
+        .await
+        .is_none());
+    drop(checkpoint);
+
+    for i in 0..100 {
+        map.insert(i, 0).await;
+    }
+    drop(persister);
+
+    let recovered: ShardMap<u32, u32> = ShardMap::recover(dir.path()).unwrap();
+    assert_eq!(
+        recovered.snapshot().await.to_hash_map(),
+        map.snapshot().await.to_hash_map()
+    );
+}
@@ -0,0 +1,124 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use whirlwind::{RcuShardMap, ShardMapBuilder};
+
+#[test]
+fn test_basic() {
+    let map = RcuShardMap::new();
+    assert!(map.is_empty());
+
+    assert!(map.insert("foo".to_string(), 1).is_none());
+    assert_eq!(map.insert("foo".to_string(), 2).unwrap().value(), &1);
+    assert_eq!(map.len(), 1);
+
+    // Lookups accept borrowed forms of the key.
+    assert!(map.contains_key("foo"));
+    assert_eq!(*map.get("foo").unwrap(), 2);
+    assert_eq!(
+        map.update("foo", |v| v * 10).unwrap().pair(),
+        (&"foo".to_string(), &20)
+    );
+
+    let removed = map.remove("foo").unwrap();
+    assert_eq!(removed.key(), "foo");
+    assert_eq!(removed.value(), &20);
+    assert!(map.remove("foo").is_none());
+    assert!(map.get("foo").is_none());
+    assert!(map.update("foo", |v| v + 1).is_none());
+}
+
+#[test]
+fn test_readers_do_not_block_writers() {
+    let map = RcuShardMap::with_shards(2);
+    map.insert(1, "one");
+
+    let entry = map.get(&1).unwrap();
+    map.insert(1, "uno");
+    map.remove(&1);
+    assert_eq!(entry.value(), &"one");
+
+    // Reads made while a writer is updating the shard see the previous table.
+    map.insert(2, "two");
+    map.retain(|k, _| {
+        assert_eq!(map.get(k).unwrap().value(), &"two");
+        false
+    });
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_concurrent_readers_and_writers() {
+    const KEYS: u64 = 64;
+
+    let map = Arc::new(RcuShardMap::with_shards(4));
+    map.extend((0..KEYS).map(|i| (i, (i, 0u64))));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let (map, done) = (map.clone(), done.clone());
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    for i in 0..KEYS {
+                        // Writers always publish whole pairs, so a reader never sees a torn one.
+                        let entry = map.get(&i).unwrap();
+                        assert_eq!(entry.value().0, i);
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let writers: Vec<_> = (0..4)
+        .map(|_| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for n in 0..500 {
+                    map.update(&(n % KEYS), |&(key, count)| (key, count + 1));
+                }
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // No update was lost.
+    assert_eq!(map.iter().map(|entry| entry.value().1).sum::<u64>(), 2000);
+}
+
+#[test]
+fn test_bulk() {
+    let map: RcuShardMap<u32, u32> = ShardMapBuilder::new()
+        .shards(8)
+        .capacity(128)
+        .build_rcu()
+        .unwrap();
+    assert_eq!(map.shard_count(), 8);
+
+    map.extend((0..100).map(|i| (i, i)));
+    map.extend((0..10).map(|i| (i, i + 100)));
+    assert_eq!(map.len(), 100);
+    assert_eq!(map.get(&5).unwrap().value(), &105);
+
+    let mut keys: Vec<_> = map.iter().map(|entry| *entry.key()).collect();
+    keys.sort_unstable();
+    assert!(keys.into_iter().eq(0..100));
+
+    map.retain(|k, _| k % 2 == 0);
+    assert_eq!(map.len(), 50);
+
+    map.clone().clear();
+    assert!(map.is_empty());
+}
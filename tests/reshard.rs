@@ -0,0 +1,217 @@
+use std::{
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use whirlwind::{error::BuildError, ShardMap, ShardMapBuilder};
+
+#[tokio::test]
+async fn test_grow_and_shrink() {
+    let map = ShardMap::with_shards(2);
+    map.extend((0..1000).map(|i| (i, i))).await;
+
+    map.reshard(64).await.unwrap();
+    assert_eq!(map.shard_count(), 64);
+    assert_eq!(map.len().await, 1000);
+    for i in 0..1000 {
+        assert_eq!(map.get(&i).await.unwrap().value(), &i);
+    }
+
+    map.reshard(4).await.unwrap();
+    assert_eq!(map.shard_count(), 4);
+    assert_eq!(map.len().await, 1000);
+    assert_eq!(map.remove(&10).await, Some(10));
+    assert_eq!(map.snapshot().await.len(), 999);
+
+    // Invalid shard counts leave the map alone, and resharding to the same count does nothing.
+    assert_eq!(
+        map.reshard(3).await,
+        Err(BuildError::ShardsNotPowerOfTwo(3))
+    );
+    map.reshard(4).await.unwrap();
+    assert_eq!(map.shard_count(), 4);
+
+    let mut pairs: Vec<_> = map.try_into_iter().ok().unwrap().collect();
+    pairs.sort_unstable();
+    assert!(pairs
+        .into_iter()
+        .eq((0..1000).filter(|&i| i != 10).map(|i| (i, i))));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_writers() {
+    const KEYS: u64 = 256;
+
+    let map = Arc::new(ShardMap::with_shards(2));
+    map.extend((0..KEYS).map(|i| (i, 0u64))).await;
+
+    let writers: Vec<_> = (0..4)
+        .map(|_| {
+            let map = map.clone();
+            tokio::spawn(async move {
+                for n in 0..2000 {
+                    *map.get_mut(&(n % KEYS)).await.unwrap() += 1;
+                }
+            })
+        })
+        .collect();
+
+    let counter = {
+        let map = map.clone();
+        tokio::spawn(async move {
+            for _ in 0..50 {
+                // Entries are never seen twice or missed while they are being moved.
+                assert_eq!(map.len().await, KEYS as usize);
+                assert_eq!(map.keys().count().await, KEYS as usize);
+                tokio::task::yield_now().await;
+            }
+        })
+    };
+
+    for shards in [8, 64, 2, 16] {
+        map.reshard(shards).await.unwrap();
+    }
+
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    counter.await.unwrap();
+
+    // No update was lost.
+    assert_eq!(
+        map.values().fold(0, |sum, v| async move { sum + *v }).await,
+        8000
+    );
+}
+
+#[tokio::test]
+async fn test_cancelled_reshard() {
+    type Hasher = BuildHasherDefault<DefaultHasher>;
+
+    let map: ShardMap<u64, u64, Hasher> = ShardMapBuilder::new()
+        .shards(4)
+        .hasher(Hasher::default())
+        .build()
+        .unwrap();
+    map.extend((0..100).map(|i| (i, i))).await;
+
+    // Holding a reference into the last shard stalls the reshard once it reaches that shard.
+    // Shards are picked by the hash bits right below the 7 that hashbrown uses.
+    let key = (0..100)
+        .find(|&key| (Hasher::default().hash_one(key) << 7) >> 62 == 3)
+        .unwrap();
+    let guard = map.get(&key).await.unwrap();
+    let reshard = tokio::time::timeout(Duration::from_millis(50), map.reshard(16));
+    assert!(reshard.await.is_err());
+
+    // The map is fully usable with part of its entries moved.
+    assert_eq!(map.shard_count(), 16);
+    assert_eq!(map.len().await, 100);
+    assert_eq!(map.snapshot().await.get(&key), Some(&key));
+    for i in 0..100 {
+        assert_eq!(map.try_get(&i).unwrap().unwrap().value(), &i);
+    }
+    map.insert(100, 100).await;
+    drop(guard);
+
+    // Resharding again finishes the interrupted move first.
+    map.reshard(8).await.unwrap();
+    assert_eq!(map.shard_count(), 8);
+    assert_eq!(map.len().await, 101);
+    for i in 0..=100 {
+        assert_eq!(map.get(&i).await.unwrap().value(), &i);
+    }
+}
+
+#[tokio::test]
+async fn test_pending_reshard_does_not_block_bulk_operations() {
+    type Hasher = BuildHasherDefault<DefaultHasher>;
+
+    let map: Arc<ShardMap<u64, u64, Hasher>> = Arc::new(
+        ShardMapBuilder::new()
+            .shards(2)
+            .hasher(Hasher::default())
+            .build()
+            .unwrap(),
+    );
+    let shard = |key: u64| (Hasher::default().hash_one(key) << 7) >> 63;
+    let x = (0..).find(|&key| shard(key) == 0).unwrap();
+    let y = (0..).find(|&key| shard(key) == 1).unwrap();
+    map.extend([(x, 0), (y, 0)]).await;
+
+    // This task holds the shard of `x`, which a bulk lock then waits for, holding up the reshard.
+    let guard = map.get_mut(&x).await.unwrap();
+    let many = tokio::spawn({
+        let map = map.clone();
+        async move { map.get_many_mut([&x, &y]).await.is_some() }
+    });
+    tokio::task::yield_now().await;
+    let reshard = tokio::spawn({
+        let map = map.clone();
+        async move { map.reshard(4).await.unwrap() }
+    });
+    tokio::task::yield_now().await;
+
+    // Another bulk operation from the task holding the shard must not wait behind the reshard.
+    let extend = tokio::time::timeout(Duration::from_secs(1), map.extend([(y, 1)]));
+    assert!(extend.await.is_ok());
+    drop(guard);
+
+    assert!(many.await.unwrap());
+    reshard.await.unwrap();
+    assert_eq!(map.shard_count(), 4);
+    assert_eq!(map.get(&y).await.unwrap().value(), &1);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_changes_across_reshard() {
+    use whirlwind::changes::ChangeKind;
+
+    let map = ShardMap::with_shards(2);
+    let mut changes = map.subscribe();
+
+    map.insert("foo", 1).await;
+    let before = changes.recv().await.unwrap();
+    assert!(before.shard < 2);
+
+    map.reshard(4).await.unwrap();
+
+    // The key's events now come from one of the new shards, which number their own events.
+    map.insert("foo", 2).await;
+    let after = changes.recv().await.unwrap();
+    assert_eq!(after.kind, ChangeKind::Update("foo", 2));
+    assert!((2..6).contains(&after.shard));
+    assert_eq!(after.seq, 0);
+}
+
+#[tokio::test]
+async fn test_transaction_across_reshard() {
+    let map = ShardMap::with_shards(4);
+    map.insert("foo", 2).await;
+
+    // A transaction whose reads were moved by a reshard runs again.
+    let attempts = AtomicUsize::new(0);
+    let result = map
+        .transaction(|tx| {
+            let (map, attempts) = (&map, &attempts);
+            async move {
+                let foo = tx.get(&"foo").await.unwrap();
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    map.reshard(8).await.unwrap();
+                }
+                tx.insert("foo", foo + 1);
+                Ok::<_, ()>(foo)
+            }
+        })
+        .await;
+
+    assert_eq!(result, Ok(2));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &3);
+}
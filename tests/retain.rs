@@ -0,0 +1,88 @@
+use whirlwind::{ShardMap, ShardSet};
+
+#[tokio::test]
+async fn test_shardmap_retain() {
+    let map = ShardMap::with_shards(4);
+    for i in 0..1000 {
+        map.insert(i, i).await;
+    }
+
+    map.retain(|k, v| {
+        *v += 1;
+        k % 3 == 0
+    })
+    .await;
+
+    assert_eq!(map.len().await, 334);
+    for i in 0..1000 {
+        match map.get(&i).await {
+            Some(r) => {
+                assert_eq!(i % 3, 0);
+                assert_eq!(r.value(), &(i + 1));
+            }
+            None => assert_ne!(i % 3, 0),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_shardmap_retain_async() {
+    let map = ShardMap::with_shards(4);
+    for i in 0..1000 {
+        map.insert(i, i).await;
+    }
+
+    map.retain_async(|k, _| {
+        let keep = k % 2 == 0;
+        Box::pin(async move {
+            tokio::task::yield_now().await;
+            keep
+        })
+    })
+    .await;
+
+    assert_eq!(map.len().await, 500);
+    for i in 0..1000 {
+        assert_eq!(map.contains_key(&i).await, i % 2 == 0);
+    }
+}
+
+#[tokio::test]
+async fn test_shardmap_retain_async_borrows_entry() {
+    let map = ShardMap::with_shards(4);
+    for i in 0..100 {
+        map.insert(i, i.to_string()).await;
+    }
+
+    // The future holds on to the key and value across an await, and modifies the value.
+    map.retain_async(|k, v| {
+        Box::pin(async move {
+            tokio::task::yield_now().await;
+            v.push('!');
+            k % 10 == 0
+        })
+    })
+    .await;
+
+    assert_eq!(map.len().await, 10);
+    for i in 0..100 {
+        match map.get(&i).await {
+            Some(r) => assert_eq!(r.value(), &format!("{i}!")),
+            None => assert_ne!(i % 10, 0),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_shardset_retain() {
+    let set = ShardSet::new();
+    for i in 0..100 {
+        set.insert(i).await;
+    }
+
+    set.retain(|v| *v < 10).await;
+
+    assert_eq!(set.len().await, 10);
+    assert!(set.contains(&9).await);
+    assert!(!set.contains(&10).await);
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "serde")]
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasherDefault, DefaultHasher},
+};
+
+use whirlwind::{ShardMap, ShardSet, TrySerialize};
+
+#[test]
+fn test_map_roundtrip() {
+    let map = ShardMap::new();
+    for i in 0..100 {
+        map.blocking_insert(i.to_string(), i);
+    }
+
+    let json = serde_json::to_string(&map.blocking_snapshot()).unwrap();
+    assert_eq!(serde_json::to_string(&TrySerialize(&map)).unwrap(), json);
+    let copy: HashMap<String, i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(copy.len(), 100);
+    assert_eq!(copy["42"], 42);
+
+    let map: ShardMap<String, i32> = serde_json::from_str(&json).unwrap();
+    assert_eq!(map.blocking_len(), 100);
+    for i in 0..100 {
+        assert_eq!(map.blocking_get(&i.to_string()).unwrap().value(), &i);
+    }
+}
+
+#[test]
+fn test_deserialize_with_hasher() {
+    type Hasher = BuildHasherDefault<DefaultHasher>;
+
+    let json = r#"{"foo": 1, "bar": 2, "foo": 3}"#;
+    let map: ShardMap<String, i32, Hasher> = serde_json::from_str(json).unwrap();
+
+    // Later entries win, as with `HashMap`.
+    assert_eq!(map.blocking_len(), 2);
+    assert_eq!(map.blocking_get("foo").unwrap().value(), &3);
+    assert_eq!(map.blocking_get("bar").unwrap().value(), &2);
+}
+
+#[test]
+fn test_set_roundtrip() {
+    let set: ShardSet<u32> = (0..10).collect();
+
+    let json = serde_json::to_string(&TrySerialize(&set)).unwrap();
+    let mut values: Vec<u32> = serde_json::from_str(&json).unwrap();
+    values.sort_unstable();
+    assert!(values.into_iter().eq(0..10));
+
+    let set: ShardSet<u32> = serde_json::from_str("[1, 2, 2, 3]").unwrap();
+    assert_eq!(set.into_iter().count(), 3);
+}
+
+#[tokio::test]
+async fn test_serialize_snapshot() {
+    let map = ShardMap::new();
+    map.insert("foo", vec![1, 2]).await;
+
+    let json = serde_json::to_string(&map.snapshot().await).unwrap();
+    assert_eq!(json, r#"{"foo":[1,2]}"#);
+}
+
+#[tokio::test]
+async fn test_try_serialize_in_async_context() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+    let set: ShardSet<&str> = ["foo"].into_iter().collect();
+
+    assert_eq!(
+        serde_json::to_string(&TrySerialize(&map)).unwrap(),
+        r#"{"foo":1}"#
+    );
+    assert_eq!(
+        serde_json::to_string(&TrySerialize(&set)).unwrap(),
+        r#"["foo"]"#
+    );
+
+    // A locked shard fails the serialization instead of blocking the runtime.
+    let guard = map.get_mut(&"foo").await.unwrap();
+    assert!(serde_json::to_string(&TrySerialize(&map)).is_err());
+    drop(guard);
+    assert!(serde_json::to_string(&TrySerialize(&map)).is_ok());
+}
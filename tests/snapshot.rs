@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use whirlwind::ShardMap;
+
+#[tokio::test]
+async fn test_snapshot_contents() {
+    let map = ShardMap::new();
+    for i in 0..100 {
+        map.insert(i, i * 2).await;
+    }
+
+    let snapshot = map.snapshot().await;
+    assert_eq!(snapshot.len(), 100);
+    assert!(!snapshot.is_empty());
+    assert_eq!(snapshot.get(&10), Some(&20));
+    assert_eq!(snapshot.get_key_value(&10), Some((&10, &20)));
+    assert!(!snapshot.contains_key(&100));
+
+    let mut keys: Vec<_> = snapshot.keys().copied().collect();
+    keys.sort_unstable();
+    assert!(keys.into_iter().eq(0..100));
+    assert_eq!(
+        snapshot.values().sum::<i32>(),
+        (0..100).map(|i| i * 2).sum()
+    );
+
+    let copy = snapshot.to_hash_map();
+    assert_eq!(copy.len(), 100);
+    assert_eq!(copy[&99], 198);
+}
+
+#[tokio::test]
+async fn test_snapshot_blocks_writes() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+
+    let snapshot = map.snapshot().await;
+    assert!(map.try_insert("foo", 2).is_err());
+    assert_eq!(map.get(&"foo").await.unwrap().value(), &1);
+
+    let writer = tokio::spawn({
+        let map = map.clone();
+        async move { map.insert("foo", 2).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(snapshot.get(&"foo"), Some(&1));
+    drop(snapshot);
+
+    assert_eq!(writer.await.unwrap(), Some(1));
+    assert_eq!(map.snapshot().await.get(&"foo"), Some(&2));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_snapshot_is_consistent() {
+    const ACCOUNTS: u32 = 64;
+    const BALANCE: u64 = 100;
+
+    let map = ShardMap::new();
+    for i in 0..ACCOUNTS {
+        map.insert(i, BALANCE).await;
+    }
+
+    let mut tasks = Vec::new();
+    for task in 0..4 {
+        let map = map.clone();
+        tasks.push(tokio::spawn(async move {
+            for i in 0..500 {
+                let from = (task * 7 + i) % ACCOUNTS;
+                let to = (from + 1 + i % (ACCOUNTS - 1)) % ACCOUNTS;
+                let mut pair = map.get_many_mut([&from, &to]).await.unwrap();
+                let [from, to] = pair.values_mut();
+                let amount = (*from).min(5);
+                *from -= amount;
+                *to += amount;
+            }
+        }));
+    }
+
+    for _ in 0..50 {
+        let snapshot = map.snapshot().await;
+        assert_eq!(snapshot.values().sum::<u64>(), ACCOUNTS as u64 * BALANCE);
+        drop(snapshot);
+        tokio::task::yield_now().await;
+    }
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+#[test]
+fn test_blocking_snapshot() {
+    let map = ShardMap::new();
+    map.blocking_insert("foo", 1);
+    map.blocking_insert("bar", 2);
+
+    let snapshot = map.blocking_snapshot();
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(snapshot.get(&"bar"), Some(&2));
+}
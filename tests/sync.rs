@@ -0,0 +1,108 @@
+use std::{
+    hash::{BuildHasherDefault, DefaultHasher},
+    sync::Arc,
+    thread,
+};
+
+use whirlwind::{error::BuildError, ShardMapBuilder, SyncShardMap};
+
+#[test]
+fn test_basic() {
+    let map = SyncShardMap::new();
+    assert!(map.is_empty());
+
+    assert_eq!(map.insert("foo".to_string(), 1), None);
+    assert_eq!(map.insert("foo".to_string(), 2), Some(1));
+    assert_eq!(map.len(), 1);
+
+    // Lookups accept borrowed forms of the key.
+    assert!(map.contains_key("foo"));
+    assert_eq!(map.get("foo").unwrap().pair(), (&"foo".to_string(), &2));
+
+    let mut value = map.get_mut("foo").unwrap();
+    *value += 1;
+    drop(value);
+    assert_eq!(*map.get("foo").unwrap(), 3);
+
+    assert_eq!(map.remove("foo"), Some(3));
+    assert_eq!(map.remove("foo"), None);
+    assert!(map.get("foo").is_none());
+    assert!(map.get_mut("foo").is_none());
+}
+
+#[test]
+fn test_threads() {
+    let map = Arc::new(SyncShardMap::with_shards(4));
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    *map.get_or_insert_with(i % 100, || 0) += 1;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(map.len(), 100);
+    for i in 0..100 {
+        assert_eq!(map.get(&i).unwrap().value(), &80);
+    }
+}
+
+#[test]
+fn test_try_get() {
+    let map = SyncShardMap::with_shards(2);
+    map.insert(1, 1);
+
+    let guard = map.get(&1).unwrap();
+    assert_eq!(map.try_get(&1).unwrap().unwrap().value(), &1);
+    assert!(map.try_get_mut(&1).is_err());
+    drop(guard);
+
+    let mut guard = map.try_get_mut(&1).unwrap().unwrap();
+    *guard.value_mut() = 2;
+    assert!(map.try_get(&1).is_err());
+    drop(guard);
+
+    assert_eq!(map.try_get(&1).unwrap().unwrap().value(), &2);
+    assert!(map.try_get(&2).unwrap().is_none());
+}
+
+#[test]
+fn test_retain_and_clear() {
+    type Hasher = BuildHasherDefault<DefaultHasher>;
+
+    let map: SyncShardMap<u32, u32, Hasher> = (0..100).map(|i| (i, i)).collect();
+    map.retain(|k, v| {
+        *v *= 2;
+        k % 2 == 0
+    });
+    assert_eq!(map.len(), 50);
+    assert_eq!(map.get(&10).unwrap().value(), &20);
+    assert!(!map.contains_key(&11));
+
+    let clone = map.clone();
+    clone.clear();
+    assert!(map.is_empty());
+}
+
+#[test]
+fn test_builder() {
+    let map: SyncShardMap<&str, u32> = ShardMapBuilder::new()
+        .shards(8)
+        .capacity(64)
+        .build_sync()
+        .unwrap();
+    assert_eq!(map.shard_count(), 8);
+
+    let err = ShardMapBuilder::new()
+        .shards(3)
+        .build_sync::<u32, u32>()
+        .err();
+    assert_eq!(err, Some(BuildError::ShardsNotPowerOfTwo(3)));
+}
@@ -0,0 +1,275 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use whirlwind::{error::TransactionError, transaction::MAX_ATTEMPTS, ShardMap};
+
+#[tokio::test]
+async fn test_transaction_commits_all_writes() {
+    let map = ShardMap::with_shards(4);
+    for i in 0..10 {
+        map.insert(i, i).await;
+    }
+
+    let sum = map
+        .transaction(|tx| async move {
+            let mut sum = 0;
+            for i in 0..10 {
+                let value = tx.get(&i).await.unwrap();
+                sum += value;
+                tx.insert(i, value * 2);
+            }
+            tx.remove(0);
+            tx.insert(10, 10);
+            Ok::<_, ()>(sum)
+        })
+        .await;
+
+    assert_eq!(sum, Ok(45));
+    assert!(!map.contains_key(&0).await);
+    assert_eq!(map.get(&9).await.unwrap().value(), &18);
+    assert_eq!(map.get(&10).await.unwrap().value(), &10);
+    assert_eq!(map.len().await, 10);
+}
+
+#[tokio::test]
+async fn test_transaction_reads_own_writes() {
+    let map = ShardMap::new();
+    map.insert("a", 1).await;
+
+    map.transaction(|tx| async move {
+        tx.insert("a", 2);
+        assert_eq!(tx.get(&"a").await, Some(2));
+        tx.remove("a");
+        assert_eq!(tx.get(&"a").await, None);
+        assert!(!tx.contains_key(&"a").await);
+        tx.insert("b", 3);
+        assert!(tx.contains_key(&"b").await);
+        Ok::<_, ()>(())
+    })
+    .await
+    .unwrap();
+
+    assert!(!map.contains_key(&"a").await);
+    assert_eq!(map.get(&"b").await.unwrap().value(), &3);
+}
+
+#[tokio::test]
+async fn test_transaction_abort() {
+    let map = ShardMap::new();
+    map.insert("a", 1).await;
+
+    let result = map
+        .transaction(|tx| async move {
+            tx.insert("a", 2);
+            tx.insert("b", 2);
+            Err::<(), _>("abort")
+        })
+        .await;
+
+    assert_eq!(result, Err(TransactionError::Aborted("abort")));
+    assert_eq!(map.get(&"a").await.unwrap().value(), &1);
+    assert!(!map.contains_key(&"b").await);
+}
+
+#[tokio::test]
+async fn test_transaction_retries_on_conflict() {
+    let map = ShardMap::new();
+    map.insert("a", 1).await;
+
+    let attempts = AtomicUsize::new(0);
+    let result = map
+        .transaction(|tx| {
+            let map = map.clone();
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let a = tx.get(&"a").await.unwrap();
+                if attempt == 0 {
+                    // A write from outside the transaction invalidates what it read.
+                    map.insert("a", 10).await;
+                }
+                tx.insert("a", a + 1);
+                Ok::<_, ()>(a)
+            }
+        })
+        .await;
+
+    assert_eq!(result, Ok(10));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(map.get(&"a").await.unwrap().value(), &11);
+}
+
+#[tokio::test]
+async fn test_transaction_ignores_unmodified_guards() {
+    let map = ShardMap::new();
+    map.insert("a", 1).await;
+
+    let attempts = AtomicUsize::new(0);
+    let result = map
+        .transaction(|tx| {
+            let map = map.clone();
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let a = tx.get(&"a").await.unwrap();
+                // Locking the shard for writing without changing anything is not a conflict.
+                assert_eq!(*map.get_mut(&"a").await.unwrap(), 1);
+                drop(map.entry("b").await);
+                assert!(map.remove(&"c").await.is_none());
+                tx.insert("a", a + 1);
+                Ok::<_, ()>(a)
+            }
+        })
+        .await;
+
+    assert_eq!(result, Ok(1));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    assert_eq!(map.get(&"a").await.unwrap().value(), &2);
+}
+
+#[tokio::test]
+async fn test_transaction_retries_on_guard_writes() {
+    let map = ShardMap::new();
+    map.insert("a", 1).await;
+
+    let attempts = AtomicUsize::new(0);
+    let result = map
+        .transaction(|tx| {
+            let map = map.clone();
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let a = tx.get(&"a").await.unwrap();
+                match attempt {
+                    0 => *map.get_mut(&"a").await.unwrap() += 1,
+                    1 => *map.get_mut_owned(&"a").await.unwrap() += 1,
+                    _ => {}
+                }
+                tx.insert("a", a * 10);
+                Ok::<_, ()>(a)
+            }
+        })
+        .await;
+
+    assert_eq!(result, Ok(3));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    assert_eq!(map.get(&"a").await.unwrap().value(), &30);
+}
+
+#[tokio::test]
+async fn test_transaction_abort_on_torn_view_retries() {
+    let map = ShardMap::new();
+    map.insert("alice", 10).await;
+
+    let attempts = AtomicUsize::new(0);
+    let result = map
+        .transaction(|tx| {
+            let map = map.clone();
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let alice = tx.get(&"alice").await.unwrap();
+                if attempt == 0 {
+                    // A deposit lands after the balance was read, so the abort below is based on
+                    // a view of the map that no longer holds.
+                    map.insert("alice", 100).await;
+                }
+                if alice < 30 {
+                    return Err("insufficient funds");
+                }
+                tx.insert("alice", alice - 30);
+                Ok(alice)
+            }
+        })
+        .await;
+
+    assert_eq!(result, Ok(100));
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert_eq!(map.get(&"alice").await.unwrap().value(), &70);
+}
+
+#[tokio::test]
+async fn test_transaction_gives_up_after_max_attempts() {
+    let map = ShardMap::new();
+    map.insert("a", 0).await;
+
+    let attempts = AtomicUsize::new(0);
+    let result = map
+        .transaction(|tx| {
+            let map = map.clone();
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let a = tx.get(&"a").await.unwrap();
+                // Every attempt is invalidated by a write from outside the transaction.
+                map.insert("a", a + 1).await;
+                tx.insert("a", a);
+                Ok::<_, ()>(())
+            }
+        })
+        .await;
+
+    assert_eq!(result, Err(TransactionError::Conflict));
+    assert_eq!(attempts.load(Ordering::SeqCst), MAX_ATTEMPTS as usize);
+    assert_eq!(map.get(&"a").await.unwrap().value(), &(MAX_ATTEMPTS as i32));
+}
+
+#[tokio::test]
+#[should_panic(expected = "transaction used after it completed")]
+async fn test_transaction_handle_outliving_closure() {
+    let map = ShardMap::<u32, u32>::new();
+    let mut leaked = None;
+
+    map.transaction(|tx| {
+        leaked = Some(tx.clone());
+        async move { Ok::<_, ()>(()) }
+    })
+    .await
+    .unwrap();
+
+    leaked.unwrap().insert(1, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_transfers_preserve_total() {
+    const ACCOUNTS: u32 = 16;
+    const INITIAL: i64 = 1_000;
+
+    let map = ShardMap::with_shards(8);
+    for i in 0..ACCOUNTS {
+        map.insert(i, INITIAL).await;
+    }
+
+    let mut handles = Vec::new();
+    for task in 0..8u32 {
+        let map = map.clone();
+        handles.push(tokio::spawn(async move {
+            for round in 0..200u32 {
+                let from = (task * 7 + round) % ACCOUNTS;
+                let to = (task * 3 + round * 5 + 1) % ACCOUNTS;
+                if from == to {
+                    continue;
+                }
+                map.transaction(|tx| async move {
+                    let a = tx.get(&from).await.unwrap();
+                    tokio::task::yield_now().await;
+                    let b = tx.get(&to).await.unwrap();
+                    tx.insert(from, a - 1);
+                    tx.insert(to, b + 1);
+                    Ok::<_, ()>(())
+                })
+                .await
+                .unwrap();
+            }
+        }));
+    }
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    let total: i64 = map
+        .transaction(|tx| async move {
+            let mut total = 0;
+            for i in 0..ACCOUNTS {
+                total += tx.get(&i).await.unwrap();
+            }
+            Ok::<_, ()>(total)
+        })
+        .await
+        .unwrap();
+    assert_eq!(total, ACCOUNTS as i64 * INITIAL);
+}
@@ -0,0 +1,47 @@
+use whirlwind::{error::WouldBlock, ShardMap, ShardSet};
+
+#[tokio::test]
+async fn test_shardmap_try_ops() {
+    let map = ShardMap::new();
+    assert_eq!(map.try_insert("foo", 1), Ok(None));
+    assert_eq!(map.try_insert("foo", 2), Ok(Some(1)));
+    assert_eq!(map.try_get(&"foo").unwrap().unwrap().value(), &2);
+    assert!(map.try_get(&"bar").unwrap().is_none());
+    assert_eq!(map.try_contains_key(&"foo"), Ok(true));
+
+    *map.try_get_mut(&"foo").unwrap().unwrap() += 1;
+    assert_eq!(map.try_remove(&"foo"), Ok(Some(3)));
+    assert_eq!(map.try_remove(&"foo"), Ok(None));
+}
+
+#[tokio::test]
+async fn test_shardmap_try_ops_would_block() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+
+    let reader = map.get(&"foo").await.unwrap();
+    // Shared access is still possible while the shard is read-locked.
+    assert!(map.try_get(&"foo").is_ok());
+    assert_eq!(map.try_contains_key(&"foo"), Ok(true));
+    assert!(map.try_get_mut(&"foo").is_err());
+    assert_eq!(map.try_remove(&"foo"), Err(WouldBlock(())));
+    assert_eq!(map.try_insert("foo", 2), Err(WouldBlock(("foo", 2))));
+    drop(reader);
+
+    let writer = map.get_mut(&"foo").await.unwrap();
+    assert!(map.try_get(&"foo").is_err());
+    assert_eq!(map.try_contains_key(&"foo"), Err(WouldBlock(())));
+    drop(writer);
+
+    assert_eq!(map.try_insert("foo", 2), Ok(Some(1)));
+}
+
+#[tokio::test]
+async fn test_shardset_try_ops() {
+    let set = ShardSet::new();
+    assert_eq!(set.try_insert("foo"), Ok(()));
+    assert_eq!(set.try_contains(&"foo"), Ok(true));
+    assert_eq!(set.try_remove(&"foo"), Ok(true));
+    assert_eq!(set.try_remove(&"foo"), Ok(false));
+    assert_eq!(set.try_contains(&"foo"), Ok(false));
+}
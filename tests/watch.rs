@@ -0,0 +1,160 @@
+#![cfg(feature = "tokio")]
+
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use whirlwind::{watch::WatchEvent, ShardMap};
+
+#[tokio::test]
+async fn test_watch_insert_and_remove() {
+    let map = ShardMap::new();
+    let mut watcher = map.watch(&"foo");
+
+    map.insert("foo", 1).await;
+    assert_eq!(watcher.recv().await, Some(WatchEvent::Changed(1)));
+
+    map.insert("bar", 2).await;
+    assert_eq!(watcher.try_recv(), None);
+
+    map.insert("foo", 3).await;
+    assert_eq!(watcher.recv().await, Some(WatchEvent::Changed(3)));
+
+    map.remove(&"foo").await;
+    map.remove(&"foo").await;
+    assert_eq!(watcher.recv().await, Some(WatchEvent::Removed));
+    assert_eq!(watcher.try_recv(), None);
+}
+
+#[tokio::test]
+async fn test_watch_mutable_guards() {
+    let map = ShardMap::new();
+    map.insert("foo", 1).await;
+    let mut watcher = map.watch(&"foo");
+
+    // Only guards that handed out mutable access count as a change.
+    let _ = map.get_mut(&"foo").await.unwrap().value();
+    assert_eq!(watcher.try_recv(), None);
+
+    *map.get_mut(&"foo").await.unwrap() += 1;
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(2)));
+
+    *map.get_mut_owned(&"foo").await.unwrap().value_mut() += 1;
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(3)));
+
+    map.iter_mut()
+        .for_each(|mut r| async move { *r += 1 })
+        .await;
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(4)));
+
+    map.insert("bar", 0).await;
+    let mut many = map.get_many_mut([&"foo", &"bar"]).await.unwrap();
+    *many.value_mut(1) += 1;
+    drop(many);
+    assert_eq!(watcher.try_recv(), None);
+
+    let mut many = map.get_many_mut([&"bar", &"foo"]).await.unwrap();
+    *many.value_mut(1) += 1;
+    drop(many);
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(5)));
+}
+
+#[tokio::test]
+async fn test_watch_entry() {
+    let map = ShardMap::new();
+    let mut watcher = map.watch(&"foo");
+
+    map.entry("foo").await.or_insert(1);
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(1)));
+
+    // The second `or_insert` finds the entry occupied and never touches its value.
+    map.entry("foo").await.or_insert(2);
+    assert_eq!(watcher.try_recv(), None);
+
+    map.entry("foo").await.and_modify(|v| *v *= 10);
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(10)));
+
+    *map.entry("foo").await.or_insert(0) += 1;
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Changed(11)));
+
+    map.entry("foo").await.remove();
+    assert_eq!(watcher.try_recv(), Some(WatchEvent::Removed));
+    assert_eq!(watcher.try_recv(), None);
+}
+
+#[tokio::test]
+async fn test_watch_bulk_removal() {
+    let map = ShardMap::new();
+    for i in 0..10 {
+        map.insert(i, i).await;
+    }
+    let mut even = map.watch(&4);
+    let mut odd = map.watch(&5);
+
+    map.retain(|k, v| {
+        *v += 1;
+        k % 2 == 0
+    })
+    .await;
+    // Values kept by `retain` are not reported, even when they were modified in place.
+    assert_eq!(even.try_recv(), None);
+    assert_eq!(odd.try_recv(), Some(WatchEvent::Removed));
+
+    map.retain_async(|_, _| Box::pin(async { false })).await;
+    assert_eq!(even.try_recv(), Some(WatchEvent::Removed));
+
+    map.insert(4, 0).await;
+    assert_eq!(even.try_recv(), Some(WatchEvent::Changed(0)));
+    map.clear().await;
+    assert_eq!(even.try_recv(), Some(WatchEvent::Removed));
+
+    map.insert(5, 0).await;
+    assert_eq!(odd.try_recv(), Some(WatchEvent::Changed(0)));
+    let _: Vec<_> = map.drain().collect().await;
+    assert_eq!(odd.try_recv(), Some(WatchEvent::Removed));
+}
+
+#[tokio::test]
+async fn test_watch_ends_when_map_is_dropped() {
+    let map = ShardMap::new();
+    let mut first = map.watch(&"foo");
+    let second = map.watch(&"foo");
+
+    // Dropping one watcher doesn't affect the others.
+    drop(second);
+    map.insert("foo", 1).await;
+    drop(map);
+
+    let events: Vec<_> = first.by_ref().collect().await;
+    assert_eq!(events, [WatchEvent::Changed(1)]);
+    assert_eq!(first.recv().await, None);
+}
+
+#[tokio::test]
+async fn test_slow_watcher_keeps_latest_change() {
+    let map = ShardMap::new();
+    let mut watcher = map.watch(&"counter");
+
+    for i in 0..10_000 {
+        map.insert("counter", i).await;
+    }
+
+    assert_eq!(watcher.recv().await, Some(WatchEvent::Changed(9_999)));
+    assert_eq!(watcher.try_recv(), None);
+}
+
+#[tokio::test]
+async fn test_dropped_watcher_is_pruned() {
+    let map = ShardMap::new();
+    let value = Arc::new(());
+    let first = map.watch(&"foo");
+    let second = map.watch(&"foo");
+
+    // The latest change is kept for the watchers of the key until they are all gone.
+    map.insert("foo", value.clone()).await;
+    assert_eq!(Arc::strong_count(&value), 3);
+
+    drop(first);
+    assert_eq!(Arc::strong_count(&value), 3);
+    drop(second);
+    assert_eq!(Arc::strong_count(&value), 2);
+}